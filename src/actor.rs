@@ -0,0 +1,42 @@
+use std::convert::Infallible;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use crate::openapi::header_parameter;
+
+/// The user performing a request, read from the `X-User-Id` header.
+/// `None` when the header is missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Actor(pub Option<String>);
+
+impl Actor {
+
+    ///
+    /// # id
+    /// this function return the id of the user performing the request, if known
+    /// # Return
+    /// * `Option<&str>` - the id of the user
+    ///
+    pub fn id(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Actor {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let id = request.headers().get_one("X-User-Id")
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(str::to_string);
+        Outcome::Success(Actor(id))
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for Actor {
+    fn from_request_input(gen: &mut OpenApiGenerator, _: String, _: bool) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(header_parameter(gen, "X-User-Id", "id of the user performing the request, recorded in the audit log and checked for librarian-only actions"))
+    }
+}
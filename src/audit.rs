@@ -0,0 +1,106 @@
+use crate::mongo::Mongo;
+use rocket::get;
+use rocket_okapi::openapi;
+use crate::actor::Actor;
+use crate::error::ApiError;
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use rocket::{FromForm, FromFormField};
+use rocket::serde::json::Json;
+use bson::{Bson, Document};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    Borrow,
+    Return,
+    Vote,
+    Flag,
+    Moderate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, FromFormField, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditEntity {
+    Book,
+    User,
+    Genre,
+    Comment,
+    Author,
+    Work,
+    Subject,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AuditEntry {
+    /// `None` when the request did not identify its user
+    pub actor: Option<String>,
+    pub action: AuditAction,
+    pub entity: AuditEntity,
+    pub entity_id: String,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub date: DateTime<Utc>,
+    /// changed fields, each with its `before` and `after` value
+    #[schemars(schema_with = "crate::openapi::document")]
+    pub changes: Document,
+    /// id of the request that made the change, see `X-Request-Id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Filters of the audit endpoint, read from the query string. Dates are RFC 3339 strings.
+#[derive(Debug, Clone, Default, FromForm, JsonSchema)]
+pub struct AuditFilter {
+    pub entity: Option<AuditEntity>,
+    pub entity_id: Option<String>,
+    pub actor: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<i64>,
+}
+
+///
+/// # diff
+/// this function compare two versions of a document and return the changed fields with their before and after values
+/// a missing version is treated as an empty document, `updated_at` is ignored
+/// # Arguments
+/// * `before` - the document before the change
+/// * `after` - the document after the change
+/// # Return
+/// * `Document` - a document of `{ field: { before, after } }`
+///
+pub fn diff(before: Option<&Document>, after: Option<&Document>) -> Document {
+    let empty = Document::new();
+    let before = before.unwrap_or(&empty);
+    let after = after.unwrap_or(&empty);
+    let mut changes = Document::new();
+    let keys = before.keys().chain(after.keys().filter(|key| !before.contains_key(key.as_str())));
+    for key in keys {
+        if key == "updated_at" {
+            continue;
+        }
+        let old = before.get(key).cloned().unwrap_or(Bson::Null);
+        let new = after.get(key).cloned().unwrap_or(Bson::Null);
+        if old != new {
+            let mut change = Document::new();
+            change.insert("before", old);
+            change.insert("after", new);
+            changes.insert(key.clone(), change);
+        }
+    }
+    changes
+}
+
+/// query the audit log, most recent first, only librarians read it as it holds the personal fields of users
+#[openapi(tag = "Audit")]
+#[get("/api/audit?<filter..>")]
+pub async fn get_audit_entries(filter: AuditFilter, actor: Actor, db: Mongo) -> Result<Json<Vec<AuditEntry>>, ApiError> {
+    let entries = db.get_audit_entries(&filter, actor.id()).await?;
+    Ok(Json(entries))
+}
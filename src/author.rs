@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use crate::mongo::Mongo;
+use rocket::{get, post, put, delete};
+use rocket_okapi::openapi;
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use validator::{Validate, ValidationError};
+use rocket::serde::json::Json;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use crate::{TimeFilter, Value};
+use crate::actor::Actor;
+use crate::error::ApiError;
+use crate::validation::Validated;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Author {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "crate::openapi::object_id")]
+    pub id: Option<ObjectId>,
+    /// name the author is listed under
+    pub name: String,
+    /// other spellings and pseudonyms, an author is found by any of them
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub birth_year: Option<i32>,
+    #[serde(default)]
+    pub death_year: Option<i32>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub created_at: DateTime<Utc>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+#[validate(schema(function = "new_author_life"))]
+pub struct NewAuthor {
+    #[validate(length(min = 1, max = 200))]
+    pub name: String,
+    #[serde(default)]
+    #[validate(custom(function = "alias_lengths"))]
+    pub aliases: Vec<String>,
+    #[validate(range(min = -3000, max = 2100))]
+    pub birth_year: Option<i32>,
+    #[validate(range(min = -3000, max = 2100))]
+    pub death_year: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+#[validate(schema(function = "update_author_life"))]
+pub struct UpdateAuthor {
+    #[validate(length(min = 1, max = 200))]
+    pub name: Option<String>,
+    #[validate(custom(function = "alias_lengths"))]
+    pub aliases: Option<Vec<String>>,
+    #[validate(range(min = -3000, max = 2100))]
+    pub birth_year: Option<i32>,
+    #[validate(range(min = -3000, max = 2100))]
+    pub death_year: Option<i32>,
+}
+
+/// Role of an author in a book.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthorRole {
+    #[default]
+    Author,
+    Editor,
+    Translator,
+}
+
+/// An author of a book and the part they had in it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct BookAuthor {
+    #[validate(custom(function = "crate::validation::object_id"))]
+    pub author_id: String,
+    #[serde(default)]
+    pub role: AuthorRole,
+}
+
+/// A book of an author page, with the rating of its reviews.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BibliographyEntry {
+    pub book_id: String,
+    pub title: String,
+    pub year: i32,
+    pub role: AuthorRole,
+    /// average rating of the reviews, `None` without review
+    pub rating: Option<f64>,
+    /// number of reviews
+    pub reviews: u64,
+}
+
+/// An author with their books, the rating of the author is the average of the reviews of all of them.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AuthorPage {
+    pub author: Author,
+    pub bibliography: Vec<BibliographyEntry>,
+    pub rating: Option<f64>,
+    pub reviews: u64,
+}
+
+impl From<NewAuthor> for Author {
+    fn from(value: NewAuthor) -> Self {
+        Author {
+            id: None,
+            name: value.name,
+            aliases: value.aliases,
+            birth_year: value.birth_year,
+            death_year: value.death_year,
+            created_at: DateTime::default(),
+            updated_at: DateTime::default(),
+        }
+    }
+}
+
+impl UpdateAuthor {
+
+    ///
+    /// # into changes
+    /// this function return the fields set in the update, keyed by their name in database
+    /// # Arguments
+    /// * `self` - the update
+    /// # Return
+    /// * `HashMap<&str, Value>` - the fields to set
+    ///
+    pub fn into_changes(self) -> HashMap<&'static str, Value> {
+        let mut changes = HashMap::new();
+        if let Some(name) = self.name {
+            changes.insert("name", Value::Text(name));
+        }
+        if let Some(aliases) = self.aliases {
+            changes.insert("aliases", Value::TextList(aliases));
+        }
+        if let Some(birth_year) = self.birth_year {
+            changes.insert("birth_year", Value::Int(birth_year));
+        }
+        if let Some(death_year) = self.death_year {
+            changes.insert("death_year", Value::Int(death_year));
+        }
+        changes
+    }
+}
+
+///
+/// # alias lengths
+/// this function check the length of every alias of an author
+/// # Arguments
+/// * `aliases` - the aliases
+/// # Return
+/// * `Result<(), ValidationError>` - nothing, or an error naming the first invalid alias
+///
+fn alias_lengths(aliases: &[String]) -> Result<(), ValidationError> {
+    match aliases.iter().position(|alias| alias.is_empty() || alias.chars().count() > 200) {
+        Some(index) => {
+            let mut error = ValidationError::new("length");
+            error.message = Some(format!("item {} must be between 1 and 200 characters", index).into());
+            Err(error)
+        }
+        None => Ok(()),
+    }
+}
+
+///
+/// # name key
+/// this function return the form an author name is matched by: lowercase letters and digits only,
+/// so that `J.K. Rowling` and `J. K. Rowling` are the same name
+/// # Arguments
+/// * `name` - the name or alias
+/// # Return
+/// * `String` - the key, the lowercase name for a name without letters or digits
+///
+pub fn name_key(name: &str) -> String {
+    let key: String = name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect();
+    match key.is_empty() {
+        true => name.trim().to_lowercase(),
+        false => key,
+    }
+}
+
+///
+/// # name keys
+/// this function return the keys of the name and aliases of an author, stored as `name_keys` to find them by
+/// # Arguments
+/// * `name` - the name
+/// * `aliases` - the aliases
+/// # Return
+/// * `Vec<String>` - the distinct keys
+///
+pub fn name_keys(name: &str, aliases: &[String]) -> Vec<String> {
+    let mut keys: Vec<String> = std::iter::once(name).chain(aliases.iter().map(String::as_str)).map(name_key).collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+///
+/// # life
+/// this function check that an author did not die before being born
+/// # Arguments
+/// * `birth_year` - the year of birth
+/// * `death_year` - the year of death
+/// # Return
+/// * `Result<(), ValidationError>` - nothing, or an error keyed `death_year`
+///
+pub fn life(birth_year: Option<i32>, death_year: Option<i32>) -> Result<(), ValidationError> {
+    match (birth_year, death_year) {
+        (Some(birth_year), Some(death_year)) if death_year < birth_year => {
+            let mut error = ValidationError::new("death_year");
+            error.message = Some("must not be before birth_year".into());
+            Err(error)
+        }
+        _ => Ok(()),
+    }
+}
+
+///
+/// # new author life
+/// this function check the life dates of a new author
+/// # Arguments
+/// * `author` - the new author
+/// # Return
+/// * `Result<(), ValidationError>` - nothing or an error
+///
+fn new_author_life(author: &NewAuthor) -> Result<(), ValidationError> {
+    life(author.birth_year, author.death_year)
+}
+
+///
+/// # update author life
+/// this function check the life dates of an update, the ones in database are checked when it is written
+/// # Arguments
+/// * `author` - the update
+/// # Return
+/// * `Result<(), ValidationError>` - nothing or an error
+///
+fn update_author_life(author: &UpdateAuthor) -> Result<(), ValidationError> {
+    life(author.birth_year, author.death_year)
+}
+
+/// create an author
+#[openapi(tag = "Authors")]
+#[post("/api/author", data = "<author>")]
+pub async fn create_author(author: Validated<NewAuthor>, actor: Actor, db: Mongo) -> Result<Json<Author>, ApiError> {
+    let author = db.create_author(author.into_inner(), actor.id()).await?;
+    Ok(Json(author))
+}
+
+/// list authors whose name or an alias contains `name`, case insensitive, sorted and filtered on created_at/updated_at
+#[openapi(tag = "Authors")]
+#[get("/api/author?<name>&<time..>")]
+pub async fn get_authors(name: Option<&str>, time: TimeFilter, db: Mongo) -> Result<Json<Vec<Author>>, ApiError> {
+    let authors = db.get_authors(name, &time).await?;
+    Ok(Json(authors))
+}
+
+/// get an author with their bibliography and the rating of their books
+#[openapi(tag = "Authors")]
+#[get("/api/author/<id>")]
+pub async fn get_author(id: &str, db: Mongo) -> Result<Json<AuthorPage>, ApiError> {
+    let page = db.get_author_page(id).await?;
+    Ok(Json(page))
+}
+
+/// update an author
+#[openapi(tag = "Authors")]
+#[put("/api/author/<id>", data = "<author>")]
+pub async fn update_author(id: &str, author: Validated<UpdateAuthor>, actor: Actor, db: Mongo) -> Result<Json<Author>, ApiError> {
+    let author = db.update_author(id, author.into_inner().into_changes(), actor.id()).await?;
+    Ok(Json(author))
+}
+
+/// delete an author, refused with 409 while a book references them
+#[openapi(tag = "Authors")]
+#[delete("/api/author/<id>")]
+pub async fn delete_author(id: &str, actor: Actor, db: Mongo) -> Result<Json<Author>, ApiError> {
+    let author = db.delete_author(id, actor.id()).await?;
+    Ok(Json(author))
+}
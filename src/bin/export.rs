@@ -0,0 +1,40 @@
+use std::io::Write;
+use rocket::futures::StreamExt;
+use bibliotheca::config::figment;
+use bibliotheca::export::{stream, ExportEntity, ExportFilter, ExportFormat};
+use bibliotheca::mongo::BuildMongo;
+
+const USAGE: &str = "usage: export <books|users|comments|genres|authors|works|subjects|loans> [--format csv|jsonl|bson|marc|marcxml] [--genre name] [--user-id id] [--book-id id] [--anonymise] [--output file]";
+
+// export a collection to stdout or to a file, with the options of `GET /api/export/<entity>`;
+// it connects with the database credentials, so personal fields are exported without a librarian check
+#[rocket::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let option = |name: &str| args.iter().position(|arg| arg == name).and_then(|index| args.get(index + 1)).cloned();
+    let entity = args.first().and_then(|name| ExportEntity::from_name(name)).ok_or(USAGE)?;
+    let format = match option("--format") {
+        Some(name) => Some(ExportFormat::from_name(&name).ok_or(USAGE)?),
+        None => None,
+    };
+    let filter = ExportFilter {
+        format,
+        genre: option("--genre"),
+        user_id: option("--user-id"),
+        book_id: option("--book-id"),
+        anonymise: Some(args.iter().any(|arg| arg == "--anonymise")),
+    };
+    let mut output: Box<dyn Write + Send> = match option("--output") {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(std::io::BufWriter::new(std::io::stdout())),
+    };
+
+    let mongo = BuildMongo::new(&figment()).await?.build();
+    let cursor = mongo.export(entity, &filter).await?;
+    let mut bytes = stream(entity, filter, mongo.config.marc.clone(), cursor)?;
+    while let Some(chunk) = bytes.next().await {
+        output.write_all(&chunk?)?;
+    }
+    output.flush()?;
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use bibliotheca::config::figment;
+use bibliotheca::import::{parse, ImportFormat};
+use bibliotheca::mongo::BuildMongo;
+
+// import books from a CSV, JSON Lines, MARC21 or MARCXML file: `import <file> [--dry-run] [--format csv|jsonl|marc|marcxml]`,
+// the format is read from the extension of the file by default
+#[rocket::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let format = args.iter().position(|arg| arg == "--format").map(|index| args.get(index + 1).map(String::as_str));
+    let path = args.iter().enumerate()
+        .find(|(index, arg)| !arg.starts_with("--") && (*index == 0 || args[index - 1] != "--format"))
+        .map(|(_, arg)| arg.as_str())
+        .ok_or("usage: import <file> [--dry-run] [--format csv|jsonl|marc|marcxml]")?;
+    let format = match format {
+        Some(Some("csv")) => ImportFormat::Csv,
+        Some(Some("jsonl")) => ImportFormat::Jsonl,
+        Some(Some("marc")) => ImportFormat::Marc,
+        Some(Some("marcxml")) => ImportFormat::Marcxml,
+        Some(_) => return Err("--format must be csv, jsonl, marc or marcxml".into()),
+        None => ImportFormat::from_extension(path).ok_or("unknown file extension, use --format")?,
+    };
+
+    let content = std::fs::read(path)?;
+    let mongo = BuildMongo::new(&figment()).await?.build();
+    let rows = parse(format, &content, &mongo.config.marc)?;
+    let report = mongo.import_books(rows, dry_run, None).await?;
+
+    for error in &report.errors {
+        println!("line {}: {}", error.line, error.error);
+        for (field, messages) in &error.fields {
+            println!("    {}: {}", field, messages.join(", "));
+        }
+    }
+    let verb = if dry_run { "would be imported" } else { "imported" };
+    println!("{} of {} rows {}", report.imported, report.rows, verb);
+    Ok(())
+}
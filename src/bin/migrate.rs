@@ -0,0 +1,31 @@
+use bibliotheca::config::figment;
+use bibliotheca::mongo::BuildMongo;
+
+// apply the pending migrations, or list them with `--status`
+#[rocket::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mongo = BuildMongo::new(&figment()).await?.build();
+
+    if std::env::args().any(|arg| arg == "--status") {
+        for record in mongo.get_migrations().await? {
+            let state = if record.finished_at.is_some() { "done" } else { "incomplete" };
+            println!("{:>4} {} {}", record.version, record.name, state);
+            for warning in record.warnings {
+                println!("     warning: {}", warning);
+            }
+        }
+        return Ok(());
+    }
+
+    let applied = mongo.migrate().await?;
+    if applied.is_empty() {
+        println!("database is up to date");
+    }
+    for record in applied {
+        println!("applied migration {}", record.name);
+        for warning in record.warnings {
+            println!("     warning: {}", warning);
+        }
+    }
+    Ok(())
+}
@@ -1,156 +1,802 @@
 use std::collections::HashMap;
-use rocket::State;
-use std::error::Error;
-use rocket::response::Debug;
 use crate::mongo::Mongo;
+use rocket::{get, post, put, patch, delete};
+use rocket_okapi::openapi;
 use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use validator::{Validate, ValidationError};
 use rocket::serde::json::Json;
+use rocket::http::Status;
+use bson::{doc, Document};
 use crate::user::User;
-use crate::Value;
+use crate::author::BookAuthor;
+use crate::work::WorkHit;
+use crate::{Deletion, TimeFilter, Value};
+use crate::actor::Actor;
+use crate::error::{ApiError, HttpError};
+use crate::etag::{IfMatch, IfNoneMatch, Tagged};
+use crate::patch::Patch;
+use crate::validation::Validated;
+use crate::import::{parse, ImportFile, ImportReport};
+use crate::{call_number, isbn};
+use chrono::{DateTime, Utc};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Book {
     pub title: String,
+    /// the credit as printed on the book
     pub author: String,
+    /// the authors of the book, with their role
+    #[serde(default)]
+    pub authors: Vec<BookAuthor>,
     pub year: i32,
     pub resume: String,
     pub availability: bool,
-    pub gender_id: String,
+    /// ids of the genres of the book
+    #[serde(default)]
+    pub genre_ids: Vec<String>,
+    /// minimum age of a patron allowed to borrow the book
+    #[serde(default)]
+    pub min_age: Option<i32>,
+    /// ISBN-10 without hyphens, only for a book with a 978 ISBN-13
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isbn_10: Option<String>,
+    /// ISBN-13 without hyphens, unique among the books
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isbn_13: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<String>,
+    /// like `2nd edition` or `revised edition`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edition: Option<String>,
+    /// ISO 639 code of the language of the text, like `en` or `fra`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pages: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<BookFormat>,
+    /// name of the series the book belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series: Option<String>,
+    /// number of the book in its series
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<i32>,
+    /// id of the work the book is an edition of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub work_id: Option<String>,
+    /// Dewey Decimal call number, like `823.912 ROW`, the books are shelved in its order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_number: Option<String>,
+    /// ids of the subject headings of the book
+    #[serde(default)]
+    pub subject_ids: Vec<String>,
+    /// incremented on every change, sent as the `ETag` of the document
+    #[serde(default)]
+    pub version: i64,
+    /// the current loan, while the book is borrowed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loan: Option<Loan>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<Deletion>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub created_at: DateTime<Utc>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Loan of a book: who borrowed it, when, and when it is due back.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Loan {
+    pub user_id: String,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub at: DateTime<Utc>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub due: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
 pub struct SearchBook {
+    #[validate(length(min = 1, max = 200))]
     pub title: Option<String>,
+    #[validate(length(min = 1, max = 200))]
     pub author: Option<String>,
-    pub year: Option<i32>
+    #[validate(range(min = -3000, max = 2100))]
+    pub year: Option<i32>,
+    #[validate(length(min = 1, max = 200))]
+    pub publisher: Option<String>,
+    #[validate(custom(function = "crate::validation::language"))]
+    pub language: Option<String>,
+    pub format: Option<BookFormat>,
+    #[validate(length(min = 1, max = 200))]
+    pub series: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl SearchBook {
+
+    ///
+    /// # into search
+    /// this function return the fields searched, keyed by their name in database
+    /// # Arguments
+    /// * `self` - the search
+    /// # Return
+    /// * `HashMap<&str, String>` - the searched values, empty when nothing is searched
+    ///
+    pub fn into_search(self) -> HashMap<&'static str, String> {
+        let mut search = HashMap::new();
+        if let Some(title) = self.title {
+            search.insert("title", title);
+        }
+        if let Some(author) = self.author {
+            search.insert("author", author);
+        }
+        if let Some(year) = self.year {
+            search.insert("year", year.to_string());
+        }
+        if let Some(publisher) = self.publisher {
+            search.insert("publisher", publisher);
+        }
+        if let Some(language) = self.language {
+            search.insert("language", language.to_lowercase());
+        }
+        if let Some(format) = self.format {
+            search.insert("format", format.as_str().to_string());
+        }
+        if let Some(series) = self.series {
+            search.insert("series", series);
+        }
+        search
+    }
+}
+
+/// A series and its number of books.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Series {
+    pub name: String,
+    pub books: i32,
+}
+
+/// A book on the shelf, as seen when browsing by call number.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ShelfBook {
+    pub book_id: String,
+    pub call_number: String,
+    pub title: String,
+    pub author: String,
+    pub availability: bool,
+}
+
+/// The books around a call number: `before` ends with the closest book shelved before it,
+/// `after` starts with the books shelved at it.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Shelf {
+    /// the call number browsed, normalised
+    pub call_number: String,
+    pub before: Vec<ShelfBook>,
+    pub after: Vec<ShelfBook>,
+}
+
+/// default number of books listed on each side of a call number
+pub const SHELF_DEFAULT: u32 = 5;
+/// maximum number of books listed on each side of a call number
+pub const SHELF_MAX: u32 = 50;
+
+///
+/// # shelf limit
+/// this function return the number of books listed on each side of a call number
+/// # Arguments
+/// * `limit` - the limit asked for, if any
+/// # Return
+/// * `u32` - the limit, `SHELF_DEFAULT` when none is asked for, between 1 and `SHELF_MAX`
+///
+pub fn shelf_limit(limit: Option<u32>) -> u32 {
+    limit.unwrap_or(SHELF_DEFAULT).clamp(1, SHELF_MAX)
+}
+
+impl ShelfBook {
+
+    ///
+    /// # from document
+    /// this function read a book as seen on the shelf
+    /// # Arguments
+    /// * `document` - the book, with its id
+    /// # Return
+    /// * `Result<Option<ShelfBook>, bson::document::ValueAccessError>` - the book, `None` for a book without call number, which is not shelved, or an error
+    ///
+    pub fn from_document(document: &bson::Document) -> Result<Option<ShelfBook>, bson::document::ValueAccessError> {
+        let call_number = match document.get_str("call_number") {
+            Ok(call_number) => call_number.to_string(),
+            Err(_) => return Ok(None),
+        };
+        Ok(Some(ShelfBook {
+            book_id: document.get_object_id("_id")?.to_hex(),
+            call_number,
+            title: document.get_str("title")?.to_string(),
+            author: document.get_str("author")?.to_string(),
+            availability: document.get_bool("availability")?,
+        }))
+    }
+}
+
+impl Shelf {
+
+    ///
+    /// # around
+    /// this function place books around a call number: the ones shelved before it, closest last, and the ones from it,
+    /// books with the same call number are in the order of their id
+    /// # Arguments
+    /// * `call_number` - the normalised call number
+    /// * `books` - the books, in any order
+    /// * `limit` - the number of books on each side
+    /// # Return
+    /// * `Shelf` - the `limit` closest books on each side, in shelf order
+    ///
+    pub fn around(call_number: String, books: impl IntoIterator<Item = ShelfBook>, limit: u32) -> Shelf {
+        let (mut before, mut after): (Vec<ShelfBook>, Vec<ShelfBook>) = books.into_iter()
+            .partition(|book| book.call_number < call_number);
+        let shelf_order = |a: &ShelfBook, b: &ShelfBook| (&a.call_number, &a.book_id).cmp(&(&b.call_number, &b.book_id));
+        before.sort_by(shelf_order);
+        after.sort_by(shelf_order);
+        before.drain(..before.len().saturating_sub(limit as usize));
+        after.truncate(limit as usize);
+        Shelf { call_number, before, after }
+    }
+}
+
+/// Physical or digital form of a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BookFormat {
+    Hardcover,
+    Paperback,
+    Ebook,
+    Audio,
+}
+
+impl BookFormat {
+
+    ///
+    /// # as str
+    /// this function return the name of the format, as stored in database
+    /// # Arguments
+    /// * `self` - the format
+    /// # Return
+    /// * `&str` - the name
+    ///
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BookFormat::Hardcover => "hardcover",
+            BookFormat::Paperback => "paperback",
+            BookFormat::Ebook => "ebook",
+            BookFormat::Audio => "audio",
+        }
+    }
+}
+
+/// The editable fields of a book, `availability` only changes when the book is borrowed or returned.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+#[validate(schema(function = "update_book_isbn"))]
 pub struct UpdateBook {
+    #[validate(length(min = 1, max = 200))]
     pub title: Option<String>,
+    #[validate(length(min = 1, max = 200))]
     pub author: Option<String>,
+    #[validate(range(min = -3000, max = 2100))]
     pub year: Option<i32>,
+    #[validate(length(max = 5000))]
     pub resume: Option<String>,
-    pub availability: Option<bool>,
-    pub gender_id: Option<String>,
+    #[validate(custom(function = "crate::validation::object_ids"))]
+    pub genre_ids: Option<Vec<String>>,
+    #[validate(nested)]
+    pub authors: Option<Vec<BookAuthor>>,
+    #[validate(range(min = 0, max = 120))]
+    pub min_age: Option<i32>,
+    /// set with the ISBN-13 it is converted to
+    #[validate(custom(function = "crate::validation::isbn_10"))]
+    pub isbn_10: Option<String>,
+    /// set with the ISBN-10 it is converted to, if any
+    #[validate(custom(function = "crate::validation::isbn_13"))]
+    pub isbn_13: Option<String>,
+    #[validate(length(min = 1, max = 200))]
+    pub publisher: Option<String>,
+    #[validate(length(min = 1, max = 100))]
+    pub edition: Option<String>,
+    #[validate(custom(function = "crate::validation::language"))]
+    pub language: Option<String>,
+    #[validate(range(min = 1, max = 100000))]
+    pub pages: Option<i32>,
+    pub format: Option<BookFormat>,
+    #[validate(length(min = 1, max = 200))]
+    pub series: Option<String>,
+    /// only with `series`
+    #[validate(range(min = 0, max = 10000))]
+    pub volume: Option<i32>,
+    /// id of an existing work
+    #[validate(custom(function = "crate::validation::object_id"))]
+    pub work_id: Option<String>,
+    #[validate(custom(function = "crate::validation::call_number"))]
+    pub call_number: Option<String>,
+    /// ids of existing subject headings
+    #[validate(custom(function = "crate::validation::object_ids"))]
+    pub subject_ids: Option<Vec<String>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+#[validate(schema(function = "new_book_isbn"), schema(function = "new_book_series"))]
 pub struct NewBook {
+    #[validate(length(min = 1, max = 200))]
     pub title: String,
+    #[validate(length(min = 1, max = 200))]
     pub author: String,
+    #[validate(range(min = -3000, max = 2100))]
     pub year: i32,
-    pub resume: String
+    #[validate(length(max = 5000))]
+    pub resume: String,
+    #[validate(range(min = 0, max = 120))]
+    pub min_age: Option<i32>,
+    #[serde(default)]
+    #[validate(custom(function = "crate::validation::object_ids"))]
+    pub genre_ids: Vec<String>,
+    /// existing authors, `author` stays the credit as printed
+    #[serde(default)]
+    #[validate(nested)]
+    pub authors: Vec<BookAuthor>,
+    /// may be written with hyphens or spaces, the other form is derived from it
+    #[serde(default)]
+    #[validate(custom(function = "crate::validation::isbn_10"))]
+    pub isbn_10: Option<String>,
+    /// may be written with hyphens or spaces, the other form is derived from it
+    #[serde(default)]
+    #[validate(custom(function = "crate::validation::isbn_13"))]
+    pub isbn_13: Option<String>,
+    #[validate(length(min = 1, max = 200))]
+    #[serde(default)]
+    pub publisher: Option<String>,
+    #[validate(length(min = 1, max = 100))]
+    #[serde(default)]
+    pub edition: Option<String>,
+    #[validate(custom(function = "crate::validation::language"))]
+    #[serde(default)]
+    pub language: Option<String>,
+    #[validate(range(min = 1, max = 100000))]
+    #[serde(default)]
+    pub pages: Option<i32>,
+    #[serde(default)]
+    pub format: Option<BookFormat>,
+    #[validate(length(min = 1, max = 200))]
+    #[serde(default)]
+    pub series: Option<String>,
+    /// only with `series`
+    #[serde(default)]
+    #[validate(range(min = 0, max = 10000))]
+    pub volume: Option<i32>,
+    /// id of an existing work
+    #[serde(default)]
+    #[validate(custom(function = "crate::validation::object_id"))]
+    pub work_id: Option<String>,
+    /// case and spacing are ignored
+    #[serde(default)]
+    #[validate(custom(function = "crate::validation::call_number"))]
+    pub call_number: Option<String>,
+    /// ids of existing subject headings
+    #[serde(default)]
+    #[validate(custom(function = "crate::validation::object_ids"))]
+    pub subject_ids: Vec<String>,
+}
+
+///
+/// # new book isbn
+/// this function check that the ISBN-10 and ISBN-13 of a new book are the same book
+/// # Arguments
+/// * `book` - the new book
+/// # Return
+/// * `Result<(), ValidationError>` - nothing or an error
+///
+fn new_book_isbn(book: &NewBook) -> Result<(), ValidationError> {
+    crate::validation::isbn_pair(book.isbn_10.as_deref(), book.isbn_13.as_deref())
+}
+
+///
+/// # new book series
+/// this function check that a new book only has a volume in a series
+/// # Arguments
+/// * `book` - the new book
+/// # Return
+/// * `Result<(), ValidationError>` - nothing or an error
+///
+fn new_book_series(book: &NewBook) -> Result<(), ValidationError> {
+    crate::validation::volume_in_series(book.series.as_deref(), book.volume)
+}
+
+///
+/// # update book isbn
+/// this function check that the ISBN-10 and ISBN-13 of an update are the same book
+/// # Arguments
+/// * `book` - the update
+/// # Return
+/// * `Result<(), ValidationError>` - nothing or an error
+///
+fn update_book_isbn(book: &UpdateBook) -> Result<(), ValidationError> {
+    crate::validation::isbn_pair(book.isbn_10.as_deref(), book.isbn_13.as_deref())
+}
+
+impl From<&Book> for UpdateBook {
+    fn from(value: &Book) -> Self {
+        UpdateBook {
+            title: Some(value.title.clone()),
+            author: Some(value.author.clone()),
+            year: Some(value.year),
+            resume: Some(value.resume.clone()),
+            genre_ids: Some(value.genre_ids.clone()),
+            authors: Some(value.authors.clone()),
+            min_age: value.min_age,
+            isbn_10: value.isbn_10.clone(),
+            isbn_13: value.isbn_13.clone(),
+            publisher: value.publisher.clone(),
+            edition: value.edition.clone(),
+            language: value.language.clone(),
+            pages: value.pages,
+            format: value.format,
+            series: value.series.clone(),
+            volume: value.volume,
+            work_id: value.work_id.clone(),
+            call_number: value.call_number.clone(),
+            subject_ids: Some(value.subject_ids.clone()),
+        }
+    }
+}
+
+impl UpdateBook {
+
+    /// fields of a book that a patch cannot remove
+    pub const REQUIRED: [&'static str; 7] = ["title", "author", "year", "resume", "genre_ids", "authors", "subject_ids"];
+
+    ///
+    /// # into changes
+    /// this function return the fields set in the update, keyed by their name in database
+    /// # Arguments
+    /// * `self` - the update
+    /// # Return
+    /// * `HashMap<&str, Value>` - the fields to set
+    ///
+    pub fn into_changes(self) -> HashMap<&'static str, Value> {
+        let mut changes = HashMap::new();
+        if let Some(title) = self.title {
+            changes.insert("title", Value::Text(title));
+        }
+        if let Some(author) = self.author {
+            changes.insert("author", Value::Text(author));
+        }
+        if let Some(year) = self.year {
+            changes.insert("year", Value::Int(year));
+        }
+        if let Some(resume) = self.resume {
+            changes.insert("resume", Value::Text(resume));
+        }
+        if let Some(genre_ids) = self.genre_ids {
+            changes.insert("genre_ids", Value::TextList(genre_ids));
+        }
+        if let Some(authors) = self.authors {
+            changes.insert("authors", Value::DocumentList(authors.iter().filter_map(|author| bson::to_document(author).ok()).collect()));
+        }
+        if let Some(min_age) = self.min_age {
+            changes.insert("min_age", Value::Int(min_age));
+        }
+        if let Some((isbn_10, isbn_13)) = isbn::pair(self.isbn_10.as_deref(), self.isbn_13.as_deref()) {
+            changes.insert("isbn_10", isbn_10.map_or(Value::Null, Value::Text));
+            changes.insert("isbn_13", Value::Text(isbn_13));
+        }
+        if let Some(publisher) = self.publisher {
+            changes.insert("publisher", Value::Text(publisher));
+        }
+        if let Some(edition) = self.edition {
+            changes.insert("edition", Value::Text(edition));
+        }
+        if let Some(language) = self.language {
+            changes.insert("language", Value::Text(language.to_lowercase()));
+        }
+        if let Some(pages) = self.pages {
+            changes.insert("pages", Value::Int(pages));
+        }
+        if let Some(format) = self.format {
+            changes.insert("format", Value::Text(format.as_str().to_string()));
+        }
+        if let Some(series) = self.series {
+            changes.insert("series", Value::Text(series));
+        }
+        if let Some(volume) = self.volume {
+            changes.insert("volume", Value::Int(volume));
+        }
+        if let Some(work_id) = self.work_id {
+            changes.insert("work_id", Value::Text(work_id));
+        }
+        if let Some(call_number) = self.call_number {
+            changes.insert("call_number", Value::Text(call_number::normalise(&call_number)));
+        }
+        if let Some(subject_ids) = self.subject_ids {
+            changes.insert("subject_ids", Value::TextList(subject_ids));
+        }
+        changes
+    }
+
+    ///
+    /// # follow isbn
+    /// this function drop the ISBN a patch left untouched when it changed the other one,
+    /// so that `into_changes` derives it again from the new one
+    /// # Arguments
+    /// * `self` - the patched update
+    /// * `current` - the update before the patch
+    ///
+    pub fn follow_isbn(&mut self, current: &UpdateBook) {
+        let isbn_10_changed = self.isbn_10 != current.isbn_10;
+        let isbn_13_changed = self.isbn_13 != current.isbn_13;
+        if isbn_13_changed && !isbn_10_changed {
+            self.isbn_10 = None;
+        }
+        if isbn_10_changed && !isbn_13_changed {
+            self.isbn_13 = None;
+        }
+    }
+}
+
+impl Book {
+
+    ///
+    /// # check deletion
+    /// this function refuse the deletion of a book on loan, unless it is forced
+    /// # Arguments
+    /// * `self` - the book
+    /// * `held` - a user lists the book in their `borrowed_books`
+    /// * `force` - delete the book even if it is on loan
+    /// # Return
+    /// * `Result<(), HttpError>` - nothing, or `409` for a book on loan without force
+    ///
+    pub fn check_deletion(&self, held: bool, force: bool) -> Result<(), HttpError> {
+        let on_loan = !self.availability || self.loan.is_some() || held;
+        match on_loan && !force {
+            true => Err(HttpError::new(Status::Conflict, "Book is on loan, use force to delete it")),
+            false => Ok(()),
+        }
+    }
+
+    ///
+    /// # restore
+    /// this function take the book out of the trash and return the update to store,
+    /// a book still listed by a user stays on loan, otherwise it is available again
+    /// # Arguments
+    /// * `self` - the book
+    /// * `on_loan` - a user still lists the book in their `borrowed_books`
+    /// * `now` - the date of the restoration
+    /// # Return
+    /// * `Option<Deletion>` - the deletion the book is restored from, none if it was not in the trash
+    /// * `Document` - the update
+    ///
+    pub fn restore(&mut self, on_loan: bool, now: DateTime<Utc>) -> (Option<Deletion>, Document) {
+        let deletion = self.deleted.take();
+        self.availability = self.availability || !on_loan;
+        let unset = if on_loan {
+            doc! {"deleted": ""}
+        } else {
+            self.loan = None;
+            doc! {"deleted": "", "loan": ""}
+        };
+        self.updated_at = now;
+        self.version += 1;
+        let update = doc! {"$unset": unset, "$set": {"availability": self.availability, "updated_at": bson::DateTime::from_chrono(now)}, "$inc": {"version": 1}};
+        (deletion, update)
+    }
+}
+
+///
+/// # unlink borrowers
+/// this function return the update removing a deleted book from the users holding it, with the filter of these users
+/// # Arguments
+/// * `book_id` - the id of the deleted book
+/// # Return
+/// * `(Document, Document)` - the filter and the update
+///
+pub fn unlink_borrowers(book_id: &str) -> (Document, Document) {
+    (
+        doc! {"borrowed_books": book_id},
+        doc! {"$pull": {"borrowed_books": book_id}, "$set": {"updated_at": bson::DateTime::now()}, "$inc": {"version": 1}},
+    )
 }
 
 impl From<NewBook> for Book {
     fn from(value: NewBook) -> Self {
+        let (isbn_10, isbn_13) = match isbn::pair(value.isbn_10.as_deref(), value.isbn_13.as_deref()) {
+            Some((isbn_10, isbn_13)) => (isbn_10, Some(isbn_13)),
+            None => (None, None),
+        };
         Book {
             title: value.title,
             author: value.author,
             year: value.year,
             resume: value.resume,
             availability: true,
-            gender_id: "000000000000000000000000".to_string(),
+            genre_ids: value.genre_ids,
+            authors: value.authors,
+            min_age: value.min_age,
+            isbn_10,
+            isbn_13,
+            publisher: value.publisher,
+            edition: value.edition,
+            language: value.language.map(|language| language.to_lowercase()),
+            pages: value.pages,
+            format: value.format,
+            series: value.series,
+            volume: value.volume,
+            work_id: value.work_id,
+            call_number: value.call_number.as_deref().map(call_number::normalise),
+            subject_ids: value.subject_ids,
+            version: 1,
+            loan: None,
+            deleted: None,
+            created_at: DateTime::default(),
+            updated_at: DateTime::default(),
         }
     }
 }
 
-#[rocket::post("/api/book", data = "<book>")]
-pub async fn create_book(book: Json<NewBook>, db: &State<Mongo>) -> Result<Json<Book>, Debug<Box<dyn Error>>> {
-    let new_book = db.create_book(book.into_inner()).await?;
+/// create a book
+#[openapi(tag = "Books")]
+#[post("/api/book", data = "<book>")]
+pub async fn create_book(book: Validated<NewBook>, actor: Actor, db: Mongo) -> Result<Json<Book>, ApiError> {
+    let new_book = db.create_book(book.into_inner(), actor.id()).await?;
     Ok(Json(new_book))
 }
 
-#[rocket::get("/api/book")]
-pub async fn get_books(db: &State<Mongo>) -> Result<Json<Vec<Book>>, Debug<Box<dyn Error>>> {
-    let books = db.get_all_books().await?;
+/// get a book by its ISBN-10 or ISBN-13, written with or without hyphens
+#[openapi(tag = "Books")]
+#[get("/api/book/isbn/<isbn>")]
+pub async fn get_book_by_isbn(isbn: &str, db: Mongo) -> Result<Json<Book>, ApiError> {
+    let book = db.get_book_by_isbn(isbn).await?;
+    Ok(Json(book))
+}
+
+/// list the series with their number of books
+#[openapi(tag = "Books")]
+#[get("/api/series")]
+pub async fn get_series(db: Mongo) -> Result<Json<Vec<Series>>, ApiError> {
+    let series = db.get_series().await?;
+    Ok(Json(series))
+}
+
+/// list the books of a series in volume order, books without volume last
+#[openapi(tag = "Books")]
+#[get("/api/series/<name>")]
+pub async fn get_books_by_series(name: &str, db: Mongo) -> Result<Json<Vec<Book>>, ApiError> {
+    let books = db.get_books_by_series(name).await?;
     Ok(Json(books))
 }
 
-#[rocket::get("/api/book/<id>")]
-pub async fn get_book(id: &str, db: &State<Mongo>) -> Result<Json<Book>, Debug<Box<dyn Error>>> {
+/// browse the shelf: the books shelved before and from a call number, `limit` on each side, 5 by default and 50 at most
+#[openapi(tag = "Books")]
+#[get("/api/shelf/<call_number>?<limit>")]
+pub async fn browse_shelf(call_number: &str, limit: Option<u32>, db: Mongo) -> Result<Json<Shelf>, ApiError> {
+    let shelf = db.browse_shelf(call_number, shelf_limit(limit)).await?;
+    Ok(Json(shelf))
+}
+
+/// list books, sorted and filtered on created_at/updated_at
+#[openapi(tag = "Books")]
+#[get("/api/book?<time..>")]
+pub async fn get_books(time: TimeFilter, db: Mongo) -> Result<Json<Vec<Book>>, ApiError> {
+    let books = db.get_all_books(&time).await?;
+    Ok(Json(books))
+}
+
+/// get a book, sent with its ETag, 304 when If-None-Match matches it
+#[openapi(tag = "Books")]
+#[get("/api/book/<id>")]
+pub async fn get_book(id: &str, if_none_match: IfNoneMatch, db: Mongo) -> Result<Tagged<Book>, ApiError> {
     let book = db.get_book_by_id(id).await?;
-    Ok(Json(book))
+    let version = book.version;
+    Ok(Tagged::new(book, version).unless(&if_none_match))
 }
 
-#[rocket::put("/api/book/<id>", data = "<book>")]
-pub async fn update_book(id: &str, book: Json<UpdateBook>, db: &State<Mongo>) -> Result<Json<Book>, Debug<Box<dyn Error>>> {
-    let mut hashmap = HashMap::new();
+/// update book, refused with 412 when If-Match is not the current version
+#[openapi(tag = "Books")]
+#[put("/api/book/<id>", data = "<book>")]
+pub async fn update_book(id: &str, book: Validated<UpdateBook>, if_match: IfMatch, actor: Actor, db: Mongo) -> Result<Tagged<Book>, ApiError> {
+    let hashmap = book.into_inner().into_changes();
 
-    if book.title.is_none() && book.author.is_none() && book.year.is_none() && book.gender_id.is_none() && book.resume.is_none() && book.availability.is_none() {
-        return Ok(Json(db.get_book_by_id(id).await?));
+    if hashmap.is_empty() {
+        let book = db.get_book_by_id(id).await?;
+        if_match.check(book.version, "Book")?;
+        let version = book.version;
+        return Ok(Tagged::new(book, version));
     }
-    match &book.title {
-        Some(title) => hashmap.insert("title", Value::Text(title.clone())),
-        None => None,
-    };
-    match &book.author {
-        Some(author) => hashmap.insert("author", Value::Text(author.clone())),
-        None => None,
-    };
-    match &book.year {
-        Some(year) => hashmap.insert("year", Value::Int(*year)),
-        None => None,
-    };
-    match &book.resume {
-        Some(resume) => hashmap.insert("resume", Value::Text(resume.clone())),
-        None => None,
-    };
-    match &book.gender_id {
-        Some(gender_id) => hashmap.insert("gender_id", Value::Text(gender_id.clone())),
-        None => None,
-    };
-    match &book.availability {
-        Some(availability) => hashmap.insert("availability", Value::Bool(*availability)),
-        None => None,
-    };
-    let updated_book = db.update_book(id, hashmap).await?;
-    Ok(Json(updated_book))
-}
-
-// delete book
-#[rocket::delete("/api/book/<id>")]
-pub async fn delete_book(id: &str, db: &State<Mongo>) -> Result<Json<Book>, Debug<Box<dyn Error>>> {
-    let deleted_book = db.delete_book(id).await?;
+    let updated_book = db.update_book(id, hashmap, if_match.versions(), actor.id()).await?;
+    let version = updated_book.version;
+    Ok(Tagged::new(updated_book, version))
+}
+
+/// patch book with a JSON Merge Patch (RFC 7396) or a JSON Patch (RFC 6902), chosen by the content type
+#[openapi(tag = "Books")]
+#[patch("/api/book/<id>", data = "<patch>")]
+pub async fn patch_book(id: &str, patch: Patch, if_match: IfMatch, actor: Actor, db: Mongo) -> Result<Tagged<Book>, ApiError> {
+    let book = db.patch_book(id, &patch, if_match.versions(), actor.id()).await?;
+    let version = book.version;
+    Ok(Tagged::new(book, version))
+}
+
+/// delete book
+#[openapi(tag = "Books")]
+#[delete("/api/book/<id>?<force>")]
+pub async fn delete_book(id: &str, force: Option<bool>, actor: Actor, db: Mongo) -> Result<Json<Book>, ApiError> {
+    let deleted_book = db.delete_book(id, force.unwrap_or(false), actor.id()).await?;
     Ok(Json(deleted_book))
 }
 
-// search book
-#[rocket::post("/api/book/search", data = "<book>")]
-pub async fn search_book(book: Json<SearchBook>, db: &State<Mongo>) -> Result<Json<Vec<Book>>, Debug<Box<dyn Error>>> {
+/// list deleted books
+#[openapi(tag = "Trash")]
+#[get("/api/book/trash")]
+pub async fn get_deleted_books(db: Mongo) -> Result<Json<Vec<Book>>, ApiError> {
+    let books = db.get_deleted_books().await?;
+    Ok(Json(books))
+}
+
+/// restore a deleted book, only librarians restore from the trash, refused with 409 when its ISBN was taken since
+#[openapi(tag = "Trash")]
+#[post("/api/book/trash/<id>/restore")]
+pub async fn restore_book(id: &str, actor: Actor, db: Mongo) -> Result<Json<Book>, ApiError> {
+    let book = db.restore_book(id, actor.id()).await?;
+    Ok(Json(book))
+}
+
+/// search books and collapse the hits to one per work, listing the editions found
+#[openapi(tag = "Works")]
+#[post("/api/book/search/works?<time..>", data = "<book>")]
+pub async fn search_works(book: Validated<SearchBook>, time: TimeFilter, db: Mongo) -> Result<Json<Vec<WorkHit>>, ApiError> {
+    let search = book.into_inner().into_search();
+    if search.is_empty() {
+        return Ok(Json(vec![]));
+    }
+    let hits = db.search_works(search, &time).await?;
+    Ok(Json(hits))
+}
 
-    let mut hashmap = HashMap::new();
-    if book.title.is_none() && book.author.is_none() && book.year.is_none() {
+/// search book
+#[openapi(tag = "Books")]
+#[post("/api/book/search?<time..>", data = "<book>")]
+pub async fn search_book(book: Validated<SearchBook>, time: TimeFilter, db: Mongo) -> Result<Json<Vec<Book>>, ApiError> {
+    let search = book.into_inner().into_search();
+    if search.is_empty() {
         return Ok(Json(vec![]));
     }
-    match &book.title {
-        Some(title) => hashmap.insert("title", title.clone()),
-        None => None
-    };
-    match &book.author {
-        Some(author) => hashmap.insert("author", author.clone()),
-        None => None
-    };
-    match &book.year {
-        Some(year) => hashmap.insert("year", year.to_string()),
-        None => None
-    };
-
-    let books = db.search_book(hashmap).await?;
+    let books = db.search_book(search, &time).await?;
     Ok(Json(books))
 }
 
-// borrow book
-#[rocket::post("/api/book/<id>/<user_id>/borrow")]
-pub async fn borrow_book(id: &str, user_id: &str, db: &State<Mongo>) -> Result<Json<(User, Book)>, Debug<Box<dyn Error>>> {
-    let borrowed_book = db.borrow_book(id, user_id).await?;
+/// borrow book, refused with 409 when it is not available
+#[openapi(tag = "Books")]
+#[post("/api/book/<id>/<user_id>/borrow")]
+pub async fn borrow_book(id: &str, user_id: &str, actor: Actor, db: Mongo) -> Result<Json<(User, Book)>, ApiError> {
+    let borrowed_book = db.borrow_book(id, user_id, actor.id()).await?;
     Ok(Json(borrowed_book))
 }
 
-// return book
-#[rocket::post("/api/book/<id>/<user_id>/return")]
-pub async fn return_book(id: &str, user_id: &str, db: &State<Mongo>) -> Result<Json<(User, Book)>, Debug<Box<dyn Error>>> {
-    let returned_book = db.return_book(id, user_id).await?;
+/// return book, refused with 409 unless it is lent to the user
+#[openapi(tag = "Books")]
+#[post("/api/book/<id>/<user_id>/return")]
+pub async fn return_book(id: &str, user_id: &str, actor: Actor, db: Mongo) -> Result<Json<(User, Book)>, ApiError> {
+    let returned_book = db.return_book(id, user_id, actor.id()).await?;
     Ok(Json(returned_book))
 }
+
+/// import books from a CSV, JSON Lines, MARC21 or MARCXML file, genres are given by name in any case;
+/// invalid rows are reported and left out, a MARC call number that is not a Dewey class is left out with a warning,
+/// nothing is written with dry_run;
+/// a failed write stops the import, the report then gives the line it stopped at
+#[openapi(tag = "Books")]
+#[post("/api/book/import?<dry_run>", data = "<file>")]
+pub async fn import_books(file: ImportFile, dry_run: Option<bool>, actor: Actor, db: Mongo) -> Result<Json<ImportReport>, ApiError> {
+    let rows = parse(file.format, &file.content, &db.config.marc)?;
+    let report = db.import_books(rows, dry_run.unwrap_or(false), actor.id()).await?;
+    Ok(Json(report))
+}
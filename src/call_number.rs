@@ -0,0 +1,37 @@
+///
+/// # normalise
+/// this function trim a call number, collapse its spaces and uppercase its cutter
+/// # Arguments
+/// * `call_number` - the call number as written, like `823.912  row`
+/// # Return
+/// * `String` - the call number as stored, like `823.912 ROW`
+///
+pub fn normalise(call_number: &str) -> String {
+    call_number.split_whitespace()
+        .map(str::to_ascii_uppercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+///
+/// # is dewey
+/// this function check that a normalised call number is a Dewey Decimal class, optionally followed by a cutter
+/// the class always has three digits before the point, so that the call numbers sort in shelf order as text
+/// # Arguments
+/// * `call_number` - the normalised call number, like `823.912 ROW` or `005.133 K35 2018`
+/// # Return
+/// * `bool` - true for a valid call number
+///
+pub fn is_dewey(call_number: &str) -> bool {
+    let mut parts = call_number.split(' ');
+    let class = parts.next().unwrap_or_default();
+    let (main, decimals) = match class.split_once('.') {
+        Some((main, decimals)) => (main, Some(decimals)),
+        None => (class, None),
+    };
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|digit| digit.is_ascii_digit());
+    main.len() == 3
+        && digits(main)
+        && decimals.is_none_or(digits)
+        && parts.all(|cutter| !cutter.is_empty() && cutter.bytes().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == b'.'))
+}
@@ -1,85 +1,475 @@
-use rocket::State;
+use std::collections::HashMap;
 use std::error::Error;
-use rocket::response::Debug;
 use crate::mongo::Mongo;
+use rocket::{get, post, delete};
+use rocket_okapi::openapi;
+use rocket::http::Status;
+use crate::error::{ApiError, HttpError};
 use serde::{Serialize, Deserialize};
-use rocket::form::FromForm;
+use schemars::JsonSchema;
+use validator::{Validate, ValidationError};
+use rocket::{FromForm, FromFormField};
 use rocket::serde::json::Json;
-use crate::OperatorRating;
+use bson::{doc, Document};
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use crate::{Deletion, OperatorRating, TimeFilter};
 use crate::book::Book;
+use crate::date::to_stored_bson;
+use crate::actor::Actor;
+use crate::validation::Validated;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromForm)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Comment {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "crate::openapi::object_id")]
+    pub id: Option<ObjectId>,
     pub user_id: String,
     pub book_id: String,
     pub comment: String,
-    pub rating: i32,
+    /// rating of a review, replies have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<i32>,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub helpful_votes: Vec<String>,
+    #[serde(default)]
+    pub helpful_count: i32,
+    #[serde(default)]
+    pub status: CommentStatus,
+    #[serde(default)]
+    pub flags: Vec<CommentFlag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<Deletion>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub created_at: DateTime<Utc>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentStatus {
+    #[default]
+    Visible,
+    Held,
+    Hidden,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CommentFlag {
+    pub user_id: String,
+    pub reason: String,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub flagged_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct NewFlag {
+    #[validate(length(min = 1, max = 500))]
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ModerationKind {
+    Hold,
+    Hide,
+    Restore,
+    Delete,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromForm)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ModerationAction {
+    pub comment_id: String,
+    /// `None` when the action was taken automatically by the word filter
+    pub moderator_id: Option<String>,
+    pub action: ModerationKind,
+    pub reason: Option<String>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub date: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CommentThread {
+    #[serde(flatten)]
+    pub comment: Comment,
+    pub replies: Vec<CommentThread>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField, JsonSchema)]
+#[schemars(rename_all = "lowercase")]
+pub enum CommentSort {
+    Helpful,
+    Recent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromForm, JsonSchema, Validate)]
 pub struct UpdateComment {
+    #[validate(length(min = 1, max = 2000))]
     pub comment: Option<String>,
+    #[validate(range(min = 1, max = 5))]
     pub rating: Option<i32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromForm)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromForm, JsonSchema, Validate)]
+#[validate(schema(function = "review_rating"))]
 pub struct NewComment {
+    #[validate(custom(function = "crate::validation::object_id"))]
     pub user_id: String,
+    #[validate(custom(function = "crate::validation::object_id"))]
     pub book_id: String,
+    #[validate(length(min = 1, max = 2000))]
     pub comment: String,
-    pub rating: i32,
+    /// required for a review, optional for a reply
+    #[validate(range(min = 1, max = 5))]
+    pub rating: Option<i32>,
+    #[validate(custom(function = "crate::validation::object_id"))]
+    pub parent_id: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromForm)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromForm, JsonSchema, Validate)]
 pub struct SearchByRating {
+    /// one of =, !=, >, >=, <, <=
+    #[validate(custom(function = "crate::validation::rating_operator"))]
     pub operator: String,
+    #[validate(range(min = 0.0, max = 5.0))]
     pub rating: f64,
 }
 
+impl Comment {
+
+    ///
+    /// # hold if blocked
+    /// this function hold the comment for moderation when it contains one of the blocked words
+    /// # Arguments
+    /// * `self` - the comment
+    /// * `words` - the blocked words
+    /// # Return
+    /// * `bool` - true if the comment is held
+    ///
+    pub fn hold_if_blocked(&mut self, words: &[String]) -> bool {
+        if contains_blocked_word(&self.comment, words) {
+            self.status = CommentStatus::Held;
+        }
+        self.status == CommentStatus::Held
+    }
+
+    ///
+    /// # moderate
+    /// this function apply a moderation action to the comment and return the fields it changed, as stored
+    /// restoring a comment clears its flags, deleting a comment moves it to the trash
+    /// # Arguments
+    /// * `self` - the comment
+    /// * `action` - the moderation action
+    /// * `moderator_id` - the id of the librarian
+    /// * `now` - the date of the action
+    /// # Return
+    /// * `Result<Document, Box<dyn Error>>` - the fields to set, or `422` for `hold`, which only the word filter does
+    ///
+    pub fn moderate(&mut self, action: ModerationKind, moderator_id: &str, now: DateTime<Utc>) -> Result<Document, Box<dyn Error>> {
+        let changes = match action {
+            ModerationKind::Hide => {
+                self.status = CommentStatus::Hidden;
+                self.updated_at = now;
+                doc! {"status": "hidden", "updated_at": bson::DateTime::from_chrono(now)}
+            }
+            ModerationKind::Restore => {
+                self.status = CommentStatus::Visible;
+                self.flags.clear();
+                self.updated_at = now;
+                doc! {"status": "visible", "flags": [], "updated_at": bson::DateTime::from_chrono(now)}
+            }
+            ModerationKind::Delete => {
+                let deletion = Deletion { by: Some(moderator_id.to_string()), at: now };
+                let stored = to_stored_bson(&deletion)?;
+                self.deleted = Some(deletion);
+                doc! {"deleted": stored}
+            }
+            ModerationKind::Hold => return Err(HttpError::new(Status::UnprocessableEntity, "Comments are only held by the word filter").into()),
+        };
+        Ok(changes)
+    }
+}
+
+///
+/// # moderation queue
+/// this function return the filter of the moderation queue: the held comments and the flagged comments not hidden yet,
+/// the comments in the trash are left out
+/// # Return
+/// * `Document` - the filter
+///
+pub fn moderation_queue() -> Document {
+    doc! {
+        "$or": [
+            {"status": "held"},
+            {"status": {"$ne": "hidden"}, "flags.0": {"$exists": true}},
+        ],
+        "deleted": null
+    }
+}
+
+impl CommentSort {
+
+    ///
+    /// # order
+    /// this function return the sort of the top level comments, the id breaks ties so that pages do not overlap
+    /// # Arguments
+    /// * `self` - the sort
+    /// # Return
+    /// * `Document` - the sort document
+    ///
+    pub fn order(&self) -> Document {
+        match self {
+            CommentSort::Helpful => doc! {"helpful_count": -1, "_id": -1},
+            CommentSort::Recent => doc! {"created_at": -1, "_id": -1},
+        }
+    }
+}
+
+///
+/// # page skip
+/// this function return the number of comments before a page
+/// # Arguments
+/// * `page` - the page number, starting at 1
+/// * `per_page` - the number of comments per page
+/// # Return
+/// * `u64` - the comments to skip, saturating for a page far beyond the last one
+///
+pub fn page_skip(page: u64, per_page: u64) -> u64 {
+    page.saturating_sub(1).saturating_mul(per_page).min(i64::MAX as u64)
+}
+
+impl CommentThread {
+
+    ///
+    /// # build
+    /// this function nest the replies under their parent, oldest first, a reply whose parent is not listed is left out
+    /// # Arguments
+    /// * `roots` - the top level comments, in their order
+    /// * `replies` - the replies, in any order
+    /// # Return
+    /// * `Vec<CommentThread>` - a thread per top level comment
+    ///
+    pub fn build(roots: Vec<Comment>, replies: Vec<Comment>) -> Vec<CommentThread> {
+        let mut children: HashMap<String, Vec<Comment>> = HashMap::new();
+        for reply in replies {
+            if let Some(parent_id) = reply.parent_id.clone() {
+                children.entry(parent_id).or_default().push(reply);
+            }
+        }
+        for replies in children.values_mut() {
+            replies.sort_by_key(|reply| (reply.created_at, reply.id));
+        }
+
+        fn nest(comment: Comment, children: &mut HashMap<String, Vec<Comment>>) -> CommentThread {
+            let replies = comment.id
+                .and_then(|id| children.remove(&id.to_hex()))
+                .unwrap_or_default();
+            let replies = replies.into_iter().map(|reply| nest(reply, children)).collect();
+            CommentThread { comment, replies }
+        }
+
+        roots.into_iter().map(|root| nest(root, &mut children)).collect()
+    }
+}
+
 impl From<NewComment> for Comment {
     fn from(value: NewComment) -> Self {
         Comment {
+            id: None,
             user_id: value.user_id,
             book_id: value.book_id,
             comment: value.comment,
             rating: value.rating,
+            parent_id: value.parent_id,
+            helpful_votes: Vec::new(),
+            helpful_count: 0,
+            status: CommentStatus::Visible,
+            flags: Vec::new(),
+            deleted: None,
+            created_at: DateTime::default(),
+            updated_at: DateTime::default(),
+        }
+    }
+}
+
+///
+/// # review rating
+/// this function check that a review, a comment without parent, has a rating
+/// # Arguments
+/// * `comment` - the new comment
+/// # Return
+/// * `Result<(), ValidationError>` - nothing, or an error keyed `rating`
+///
+fn review_rating(comment: &NewComment) -> Result<(), ValidationError> {
+    match (&comment.parent_id, comment.rating) {
+        (None, None) => {
+            let mut error = ValidationError::new("rating");
+            error.message = Some("required for a review".into());
+            Err(error)
         }
+        _ => Ok(()),
     }
 }
 
-#[rocket::post("/api/comment", data = "<comment>")]
-pub async fn create_comment(comment: Json<NewComment>, db: &State<Mongo>) -> Result<Json<Comment>, Debug<Box<dyn Error>>> {
-    let new_comment = db.create_comment(comment.into_inner()).await?;
+///
+/// # contains blocked word
+/// this function check if a text contains one of the blocked words, ignoring case
+/// # Arguments
+/// * `text` - the text to check
+/// * `words` - the blocked words
+/// # Return
+/// * `bool` - true if a blocked word is found
+///
+pub fn contains_blocked_word(text: &str, words: &[String]) -> bool {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .any(|word| words.iter().any(|blocked| blocked.to_lowercase() == word))
+}
+
+/// create a comment, held for moderation when it contains a blocked word
+#[openapi(tag = "Comments")]
+#[post("/api/comment", data = "<comment>")]
+pub async fn create_comment(comment: Validated<NewComment>, actor: Actor, db: Mongo) -> Result<Json<Comment>, ApiError> {
+    let new_comment = db.create_comment(comment.into_inner(), actor.id()).await?;
     Ok(Json(new_comment))
 }
 
-#[rocket::get("/api/comment")]
-pub async fn get_comments(db: &State<Mongo>) -> Result<Json<Vec<Comment>>, Debug<Box<dyn Error>>> {
-    let comments = db.get_all_comments().await?;
+/// list comments, sorted and filtered on created_at/updated_at
+#[openapi(tag = "Comments")]
+#[get("/api/comment?<time..>")]
+pub async fn get_comments(time: TimeFilter, db: Mongo) -> Result<Json<Vec<Comment>>, ApiError> {
+    let comments = db.get_all_comments(&time).await?;
     Ok(Json(comments))
 }
 
-#[rocket::get("/api/comment/<book_id>")]
-pub async fn get_comments_by_book_id(book_id: &str, db: &State<Mongo>) -> Result<Json<Vec<Comment>>, Debug<Box<dyn Error>>> {
-    let comments = db.get_all_comments_with_book_id(book_id).await?;
+/// list comment threads of a book, sorted by helpfulness (default) or recency
+#[openapi(tag = "Comments")]
+#[get("/api/comment/<book_id>?<sort>&<page>&<per_page>")]
+pub async fn get_comments_by_book_id(book_id: &str, sort: Option<CommentSort>, page: Option<u64>, per_page: Option<u64>, db: Mongo) -> Result<Json<Vec<CommentThread>>, ApiError> {
+    let sort = sort.unwrap_or(CommentSort::Helpful);
+    let page = page.unwrap_or(1).max(1);
+    let per_page = per_page.unwrap_or(20).clamp(1, 100);
+    let threads = db.get_comment_threads_with_book_id(book_id, sort, page, per_page).await?;
+    Ok(Json(threads))
+}
+
+/// list the comments of a user
+#[openapi(tag = "Comments")]
+#[get("/api/comment/user/<user_id>?<time..>")]
+pub async fn get_comments_by_user_id(user_id: &str, time: TimeFilter, db: Mongo) -> Result<Json<Vec<Comment>>, ApiError> {
+    let comments = db.get_all_comments_with_user_id(user_id, &time).await?;
+    Ok(Json(comments))
+}
+
+/// vote a comment as helpful
+#[openapi(tag = "Comments")]
+#[post("/api/comment/<id>/<user_id>/helpful")]
+pub async fn vote_comment_helpful(id: &str, user_id: &str, db: Mongo) -> Result<Json<Comment>, ApiError> {
+    let comment = db.vote_comment_helpful(id, user_id).await?;
+    Ok(Json(comment))
+}
+
+/// withdraw a helpful vote
+#[openapi(tag = "Comments")]
+#[delete("/api/comment/<id>/<user_id>/helpful")]
+pub async fn unvote_comment_helpful(id: &str, user_id: &str, db: Mongo) -> Result<Json<Comment>, ApiError> {
+    let comment = db.unvote_comment_helpful(id, user_id).await?;
+    Ok(Json(comment))
+}
+
+/// flag a comment for moderation
+#[openapi(tag = "Moderation")]
+#[post("/api/comment/<id>/<user_id>/flag", data = "<flag>")]
+pub async fn flag_comment(id: &str, user_id: &str, flag: Validated<NewFlag>, db: Mongo) -> Result<Json<Comment>, ApiError> {
+    let comment = db.flag_comment(id, user_id, flag.into_inner().reason).await?;
+    Ok(Json(comment))
+}
+
+/// list held and flagged comments waiting for a librarian, who is the user performing the request
+#[openapi(tag = "Moderation")]
+#[get("/api/comment/moderation")]
+pub async fn get_moderation_queue(actor: Actor, db: Mongo) -> Result<Json<Vec<Comment>>, ApiError> {
+    let comments = db.get_moderation_queue(actor.id()).await?;
     Ok(Json(comments))
 }
 
-#[rocket::get("/api/comment/user/<user_id>")]
-pub async fn get_comments_by_user_id(user_id: &str, db: &State<Mongo>) -> Result<Json<Vec<Comment>>, Debug<Box<dyn Error>>> {
-    let comments = db.get_all_comments_with_user_id(user_id).await?;
+/// list moderation actions, optionally for a single comment
+#[openapi(tag = "Moderation")]
+#[get("/api/comment/moderation/actions?<comment_id>")]
+pub async fn get_moderation_actions(comment_id: Option<&str>, actor: Actor, db: Mongo) -> Result<Json<Vec<ModerationAction>>, ApiError> {
+    let actions = db.get_moderation_actions(actor.id(), comment_id).await?;
+    Ok(Json(actions))
+}
+
+/// hide a comment, as a moderator
+#[openapi(tag = "Moderation")]
+#[post("/api/comment/<id>/hide?<reason>")]
+pub async fn hide_comment(id: &str, reason: Option<String>, actor: Actor, db: Mongo) -> Result<Json<Comment>, ApiError> {
+    let comment = db.moderate_comment(id, actor.id(), ModerationKind::Hide, reason).await?;
+    Ok(Json(comment))
+}
+
+/// make a held or hidden comment visible again, as a moderator
+#[openapi(tag = "Moderation")]
+#[post("/api/comment/<id>/restore?<reason>")]
+pub async fn restore_comment(id: &str, reason: Option<String>, actor: Actor, db: Mongo) -> Result<Json<Comment>, ApiError> {
+    let comment = db.moderate_comment(id, actor.id(), ModerationKind::Restore, reason).await?;
+    Ok(Json(comment))
+}
+
+/// move a comment to the trash, as a moderator
+#[openapi(tag = "Moderation")]
+#[delete("/api/comment/<id>?<reason>")]
+pub async fn delete_comment(id: &str, reason: Option<String>, actor: Actor, db: Mongo) -> Result<Json<Comment>, ApiError> {
+    let comment = db.moderate_comment(id, actor.id(), ModerationKind::Delete, reason).await?;
+    Ok(Json(comment))
+}
+
+/// list deleted comments
+#[openapi(tag = "Trash")]
+#[get("/api/comment/trash")]
+pub async fn get_deleted_comments(db: Mongo) -> Result<Json<Vec<Comment>>, ApiError> {
+    let comments = db.get_deleted_comments().await?;
     Ok(Json(comments))
 }
 
-#[rocket::get("/api/comment/rating/<book_id>")]
-pub async fn get_rating_by_book_id(book_id: &str, db: &State<Mongo>) -> Result<Json<f64>, Debug<Box<dyn Error>>> {
+/// restore a deleted comment, only librarians restore from the trash
+#[openapi(tag = "Trash")]
+#[post("/api/comment/trash/<id>/restore")]
+pub async fn restore_deleted_comment(id: &str, actor: Actor, db: Mongo) -> Result<Json<Comment>, ApiError> {
+    let comment = db.restore_deleted_comment(id, actor.id()).await?;
+    Ok(Json(comment))
+}
+
+/// average rating of a book
+#[openapi(tag = "Comments")]
+#[get("/api/comment/rating/<book_id>")]
+pub async fn get_rating_by_book_id(book_id: &str, db: Mongo) -> Result<Json<f64>, ApiError> {
 
     let rating = db.calculate_rating_by_book_id(book_id).await?;
     Ok(Json(rating))
 }
 
-#[rocket::get("/api/comment/search/rating", data = "<search_by_rating>")]
-pub async fn get_all_books_by_search_rating(search_by_rating: Json<SearchByRating>, db: &State<Mongo>) -> Result<Json<Vec<Book>>, Debug<Box<dyn Error>>> {
+/// average rating of the reviews of the editions of a work, null without review
+#[openapi(tag = "Comments")]
+#[get("/api/comment/rating/work/<work_id>")]
+pub async fn get_rating_by_work_id(work_id: &str, db: Mongo) -> Result<Json<Option<f64>>, ApiError> {
+    let rating = db.calculate_rating_by_work_id(work_id).await?;
+    Ok(Json(rating))
+}
+
+/// list the books whose average rating compares to the rating with the operator (=, !=, >, >=, <, <=)
+#[openapi(tag = "Comments")]
+#[get("/api/comment/search/rating", data = "<search_by_rating>")]
+pub async fn get_all_books_by_search_rating(search_by_rating: Validated<SearchByRating>, db: Mongo) -> Result<Json<Vec<Book>>, ApiError> {
     let value = &search_by_rating.clone().rating;
     let rating = match search_by_rating.operator.as_str() {
         "=" => OperatorRating::Equal(*value),
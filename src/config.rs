@@ -0,0 +1,264 @@
+use std::fmt;
+use std::time::Duration;
+use mongodb::options::{Acknowledgment, ReadConcern, ResolverConfig, WriteConcern};
+use rocket::figment::Figment;
+use rocket::figment::providers::Env;
+use serde::{Deserialize, Deserializer};
+
+/// Names of the collections, each defaults to its usual name.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Collections {
+    pub books: String,
+    pub users: String,
+    pub comments: String,
+    pub genres: String,
+    pub authors: String,
+    pub works: String,
+    pub subjects: String,
+    pub moderation_actions: String,
+    pub audit: String,
+    pub migrations: String,
+}
+
+impl Default for Collections {
+    fn default() -> Self {
+        Collections {
+            books: "books".to_string(),
+            users: "users".to_string(),
+            comments: "comments".to_string(),
+            genres: "genres".to_string(),
+            authors: "authors".to_string(),
+            works: "works".to_string(),
+            subjects: "subjects".to_string(),
+            moderation_actions: "moderation_actions".to_string(),
+            audit: "audit".to_string(),
+            migrations: "_migrations".to_string(),
+        }
+    }
+}
+
+/// A subfield of a MARC record, written `245$a` in the configuration: the tag of the field, `$` and the code of the subfield.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MarcSubfield {
+    pub tag: String,
+    pub code: char,
+}
+
+impl TryFrom<String> for MarcSubfield {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mut chars = value.chars();
+        let tag: String = chars.by_ref().take(3).collect();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('$'), Some(code), None) if tag.len() == 3 && tag.chars().all(|c| c.is_ascii_digit()) && tag.as_str() >= "010" && code.is_ascii_alphanumeric() => {
+                Ok(MarcSubfield { tag, code })
+            }
+            _ => Err(format!("invalid MARC subfield {}, expected a data field tag and a code like 245$a", value)),
+        }
+    }
+}
+
+impl fmt::Display for MarcSubfield {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}${}", self.tag, self.code)
+    }
+}
+
+///
+/// # marc subfield
+/// this function build the default subfield of a mapping
+/// # Arguments
+/// * `tag` - the tag of the field
+/// * `code` - the code of the subfield
+/// # Return
+/// * `MarcSubfield` - the subfield
+///
+fn marc_subfield(tag: &str, code: char) -> MarcSubfield {
+    MarcSubfield { tag: tag.to_string(), code }
+}
+
+/// Subfields of a MARC record holding the fields of a book, used to import and export MARC21 and MARCXML.
+/// Each defaults to the usual MARC21 bibliographic subfield.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct MarcMapping {
+    pub title: MarcSubfield,
+    pub author: MarcSubfield,
+    pub year: MarcSubfield,
+    pub resume: MarcSubfield,
+    /// repeated, one field per genre
+    pub genres: MarcSubfield,
+    /// the ISBN-13 on export, either form on import
+    pub isbn: MarcSubfield,
+    pub publisher: MarcSubfield,
+    pub edition: MarcSubfield,
+    pub language: MarcSubfield,
+    /// the extent, the first number is the page count
+    pub pages: MarcSubfield,
+    pub series: MarcSubfield,
+    pub volume: MarcSubfield,
+    /// the Dewey Decimal classification number
+    pub call_number: MarcSubfield,
+}
+
+impl Default for MarcMapping {
+    fn default() -> Self {
+        MarcMapping {
+            title: marc_subfield("245", 'a'),
+            author: marc_subfield("100", 'a'),
+            year: marc_subfield("260", 'c'),
+            resume: marc_subfield("520", 'a'),
+            genres: marc_subfield("655", 'a'),
+            isbn: marc_subfield("020", 'a'),
+            publisher: marc_subfield("260", 'b'),
+            edition: marc_subfield("250", 'a'),
+            language: marc_subfield("041", 'a'),
+            pages: marc_subfield("300", 'a'),
+            series: marc_subfield("490", 'a'),
+            volume: marc_subfield("490", 'v'),
+            call_number: marc_subfield("082", 'a'),
+        }
+    }
+}
+
+/// DNS resolver used for `mongodb+srv://` urls, `system` reads the host configuration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolver {
+    #[default]
+    System,
+    Cloudflare,
+    Google,
+    Quad9,
+}
+
+impl Resolver {
+
+    ///
+    /// # resolver config
+    /// this function return the resolver config of the driver, `None` for the system resolver
+    /// # Arguments
+    /// * `self` - the resolver
+    /// # Return
+    /// * `Option<ResolverConfig>` - the resolver config
+    ///
+    pub fn resolver_config(self) -> Option<ResolverConfig> {
+        match self {
+            Resolver::System => None,
+            Resolver::Cloudflare => Some(ResolverConfig::cloudflare()),
+            Resolver::Google => Some(ResolverConfig::google()),
+            Resolver::Quad9 => Some(ResolverConfig::quad9()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadConcernLevel {
+    Local,
+    Majority,
+    Linearizable,
+    Available,
+    Snapshot,
+}
+
+impl From<ReadConcernLevel> for ReadConcern {
+    fn from(value: ReadConcernLevel) -> Self {
+        match value {
+            ReadConcernLevel::Local => ReadConcern::local(),
+            ReadConcernLevel::Majority => ReadConcern::majority(),
+            ReadConcernLevel::Linearizable => ReadConcern::linearizable(),
+            ReadConcernLevel::Available => ReadConcern::available(),
+            ReadConcernLevel::Snapshot => ReadConcern::snapshot(),
+        }
+    }
+}
+
+/// `w` of a write concern: a number of nodes, `majority` or a custom tag.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum WriteAcknowledgment {
+    Nodes(u32),
+    Tag(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct WriteConcernConfig {
+    pub w: Option<WriteAcknowledgment>,
+    pub journal: Option<bool>,
+    pub timeout_ms: Option<u64>,
+}
+
+impl WriteConcernConfig {
+
+    ///
+    /// # write concern
+    /// this function build the write concern of the driver, `None` when nothing is configured
+    /// # Arguments
+    /// * `self` - the write concern config
+    /// # Return
+    /// * `Option<WriteConcern>` - the write concern
+    ///
+    pub fn write_concern(&self) -> Option<WriteConcern> {
+        if self == &WriteConcernConfig::default() {
+            return None;
+        }
+        let w = self.w.clone().map(|w| match w {
+            WriteAcknowledgment::Nodes(nodes) => Acknowledgment::Nodes(nodes),
+            WriteAcknowledgment::Tag(tag) if tag == "majority" => Acknowledgment::Majority,
+            WriteAcknowledgment::Tag(tag) => Acknowledgment::Custom(tag),
+        });
+        Some(WriteConcern::builder()
+            .w(w)
+            .journal(self.journal)
+            .w_timeout(self.timeout_ms.map(Duration::from_millis))
+            .build())
+    }
+}
+
+///
+/// # words
+/// this function deserialize a word list given either as a list or as a comma separated string
+/// # Arguments
+/// * `deserializer` - the deserializer
+/// # Return
+/// * `Result<Vec<String>, D::Error>` - the trimmed, lowercased, non empty words
+///
+pub fn words<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Words {
+        List(Vec<String>),
+        Text(String),
+    }
+    let words = match Words::deserialize(deserializer)? {
+        Words::List(words) => words,
+        Words::Text(text) => text.split(',').map(str::to_string).collect(),
+    };
+    Ok(words.iter().map(|word| word.trim().to_lowercase()).filter(|word| !word.is_empty()).collect())
+}
+
+///
+/// # figment
+/// this function return the configuration sources of the application: Rocket.toml and `ROCKET_` variables,
+/// then the database variables `URL_MONGO`, `DB_NAME`, `MODERATION_WORDS`, `PURGE_AFTER_DAYS`, `MIGRATE_ON_STARTUP`
+/// and any `MONGO_` variable, nested with `__` (e.g. `MONGO_COLLECTIONS__BOOKS`)
+/// # Return
+/// * `Figment` - the configuration sources
+///
+pub fn figment() -> Figment {
+    let legacy = Env::raw()
+        .only(&["URL_MONGO", "DB_NAME", "MODERATION_WORDS", "PURGE_AFTER_DAYS", "MIGRATE_ON_STARTUP"])
+        .map(|key| match key.as_str().to_lowercase().as_str() {
+            "url_mongo" => "mongo.url".into(),
+            key => format!("mongo.{}", key).into(),
+        })
+        .global();
+    let mongo = Env::prefixed("MONGO_")
+        .map(|key| format!("mongo.{}", key.as_str().to_lowercase().replace("__", ".")).into())
+        .global();
+    rocket::Config::figment().merge(legacy).merge(mongo)
+}
@@ -0,0 +1,126 @@
+use std::cell::Cell;
+use chrono::{NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+
+///
+/// # to bson date
+/// this function convert a calendar date into a bson date at midnight UTC
+/// # Arguments
+/// * `date` - the date to convert
+/// # Return
+/// * `bson::DateTime` - a bson date
+///
+pub fn to_bson_date(date: NaiveDate) -> bson::DateTime {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    bson::DateTime::from_chrono(Utc.from_utc_datetime(&midnight))
+}
+
+thread_local! {
+    /// set while a value is serialized to be stored, `date_time` and `naive_date` then write bson dates
+    static STORING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Resets `STORING` when dropped, even if the serialization panics.
+struct StoringGuard(bool);
+
+impl Drop for StoringGuard {
+    fn drop(&mut self) {
+        STORING.with(|storing| storing.set(self.0));
+    }
+}
+
+///
+/// # storing
+/// this function run a serialization writing the storage form of the dates
+/// # Arguments
+/// * `serialize` - the serialization
+/// # Return
+/// * `R` - the result of the serialization
+///
+fn storing<R>(serialize: impl FnOnce() -> R) -> R {
+    let _guard = StoringGuard(STORING.with(|storing| storing.replace(true)));
+    serialize()
+}
+
+///
+/// # is storing
+/// this function tell if the value being serialized is to be stored
+/// # Return
+/// * `bool` - true inside `to_stored_bson` and `to_stored_document`
+///
+fn is_storing() -> bool {
+    STORING.with(Cell::get)
+}
+
+///
+/// # to stored bson
+/// this function serialize a value the way it is stored, with bson dates instead of the texts of the API
+/// # Arguments
+/// * `value` - the value to store
+/// # Return
+/// * `Result<bson::Bson, bson::ser::Error>` - the stored value
+///
+pub fn to_stored_bson<T: Serialize + ?Sized>(value: &T) -> Result<bson::Bson, bson::ser::Error> {
+    storing(|| bson::to_bson(value))
+}
+
+///
+/// # to stored document
+/// this function serialize a struct the way it is stored, with bson dates instead of the texts of the API
+/// # Arguments
+/// * `value` - the struct to store
+/// # Return
+/// * `Result<bson::Document, bson::ser::Error>` - the stored document
+///
+pub fn to_stored_document<T: Serialize + ?Sized>(value: &T) -> Result<bson::Document, bson::ser::Error> {
+    storing(|| bson::to_document(value))
+}
+
+/// Serde helper for a `DateTime<Utc>`: an RFC 3339 text in JSON and a bson date in database,
+/// used with `#[serde(with = "crate::date::date_time")]`. Either form is read back.
+pub mod date_time {
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        match super::is_storing() || !serializer.is_human_readable() {
+            false => serializer.serialize_str(&date.to_rfc3339_opts(SecondsFormat::Millis, true)),
+            true => bson::DateTime::from_chrono(*date).serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        match bson::Bson::deserialize(deserializer)? {
+            bson::Bson::DateTime(date) => Ok(date.to_chrono()),
+            bson::Bson::String(text) => DateTime::parse_from_rfc3339(&text)
+                .map(|date| date.with_timezone(&Utc))
+                .map_err(|_| D::Error::custom(format!("invalid date {}, expected RFC 3339", text))),
+            _ => Err(D::Error::custom("expected a date")),
+        }
+    }
+}
+
+/// Serde helper for a `NaiveDate`: a `YYYY-MM-DD` text in JSON and a bson date at midnight UTC in database,
+/// used with `#[serde(with = "crate::date::naive_date")]`. Either form is read back.
+pub mod naive_date {
+    use chrono::NaiveDate;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(date: &NaiveDate, serializer: S) -> Result<S::Ok, S::Error> {
+        match super::is_storing() || !serializer.is_human_readable() {
+            false => serializer.serialize_str(&date.format("%Y-%m-%d").to_string()),
+            true => super::to_bson_date(*date).serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDate, D::Error> {
+        match bson::Bson::deserialize(deserializer)? {
+            bson::Bson::DateTime(date) => Ok(date.to_chrono().date_naive()),
+            bson::Bson::String(text) => NaiveDate::parse_from_str(&text, "%Y-%m-%d")
+                .map_err(|_| D::Error::custom(format!("invalid date {}, expected YYYY-MM-DD", text))),
+            _ => Err(D::Error::custom("expected a date")),
+        }
+    }
+}
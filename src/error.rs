@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use mongodb::error::ErrorKind;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Debug, Responder, Response};
+use rocket::serde::json::Json;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{RefOr, Responses};
+use rocket_okapi::response::OpenApiResponderInner;
+use rocket_okapi::util::add_default_response_schema;
+use schemars::JsonSchema;
+use serde::Serialize;
+use crate::request_log::RequestError;
+use crate::validation::FieldErrors;
+
+/// An error answered with a specific HTTP status instead of a 500.
+/// `Mongo` methods return it boxed like any other error.
+#[derive(Debug, Clone)]
+pub struct HttpError {
+    pub status: Status,
+    pub message: String,
+}
+
+impl HttpError {
+
+    ///
+    /// # new
+    /// this function create an error answered with the given status
+    /// # Arguments
+    /// * `status` - the HTTP status of the response
+    /// * `message` - the message sent to the client
+    /// # Return
+    /// * `HttpError` - the error
+    ///
+    pub fn new(status: Status, message: impl Into<String>) -> Self {
+        HttpError { status, message: message.into() }
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for HttpError {}
+
+/// seconds a client is asked to wait before retrying when the database is unreachable
+pub const RETRY_AFTER_SECS: u64 = 5;
+
+///
+/// # is unavailable
+/// this function check if an error means the database cannot be reached
+/// # Arguments
+/// * `error` - the error
+/// # Return
+/// * `bool` - true for server selection, network and connection pool errors of the driver
+///
+pub fn is_unavailable(error: &(dyn Error + 'static)) -> bool {
+    match error.downcast_ref::<mongodb::error::Error>() {
+        Some(error) => matches!(
+            error.kind.as_ref(),
+            ErrorKind::ServerSelection { .. } | ErrorKind::Io(_) | ErrorKind::ConnectionPoolCleared { .. }
+        ),
+        None => false,
+    }
+}
+
+/// Body of an error response.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ErrorBody {
+    pub error: String,
+    /// messages of the invalid fields of the request body, keyed by the path of the field
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, Vec<String>>,
+}
+
+impl ErrorBody {
+
+    ///
+    /// # new
+    /// this function create the body of an error
+    /// # Arguments
+    /// * `error` - the message sent to the client
+    /// # Return
+    /// * `ErrorBody` - the body
+    ///
+    pub fn new(error: impl Into<String>) -> Self {
+        ErrorBody { error: error.into(), fields: BTreeMap::new() }
+    }
+
+    ///
+    /// # fields
+    /// this function create the body of a request refused by the validation rules
+    /// # Arguments
+    /// * `errors` - the messages of the invalid fields
+    /// # Return
+    /// * `ErrorBody` - the body
+    ///
+    pub fn fields(errors: FieldErrors) -> Self {
+        ErrorBody { error: "Validation failed".to_string(), fields: errors.0 }
+    }
+}
+
+/// Error type of the handlers. An `HttpError` is answered with its status and a JSON body,
+/// `FieldErrors` with `422` and the messages of the fields, an unreachable database with 503 and `Retry-After`, any other error is answered 500 like `Debug`.
+#[derive(Debug)]
+pub struct ApiError(pub Box<dyn Error>);
+
+impl From<Box<dyn Error>> for ApiError {
+    fn from(value: Box<dyn Error>) -> Self {
+        ApiError(value)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        request.local_cache(|| RequestError(Some(self.0.to_string())));
+        if let Some(errors) = self.0.downcast_ref::<FieldErrors>() {
+            let body = Json(ErrorBody::fields(errors.clone()));
+            return Response::build_from(body.respond_to(request)?).status(Status::UnprocessableEntity).ok();
+        }
+        match self.0.downcast_ref::<HttpError>() {
+            Some(error) => {
+                let body = Json(ErrorBody::new(error.message.clone()));
+                Response::build_from(body.respond_to(request)?).status(error.status).ok()
+            }
+            None if is_unavailable(self.0.as_ref()) => {
+                let body = Json(ErrorBody::new("Database unavailable, retry later"));
+                Response::build_from(body.respond_to(request)?)
+                    .status(Status::ServiceUnavailable)
+                    .raw_header("Retry-After", RETRY_AFTER_SECS.to_string())
+                    .ok()
+            }
+            None => Debug(self.0).respond_to(request),
+        }
+    }
+}
+
+impl OpenApiResponderInner for ApiError {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        let schema = gen.json_schema::<ErrorBody>();
+        if let RefOr::Object(response) = add_default_response_schema(&mut responses, "application/json", schema) {
+            response.description = "Error, with its message and the invalid fields on 422; 503 with Retry-After while the database is unreachable".to_string();
+        }
+        Ok(responses)
+    }
+}
@@ -0,0 +1,230 @@
+use std::convert::Infallible;
+use std::error::Error;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{Header, ParameterValue, RefOr, Response as OpenApiResponse, Responses};
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use rocket_okapi::response::OpenApiResponderInner;
+use rocket_okapi::util::ensure_status_code_exists;
+use schemars::JsonSchema;
+use serde::Serialize;
+use crate::error::HttpError;
+use crate::openapi::header_parameter;
+
+/// Entity tags listed in an `If-Match` or `If-None-Match` header.
+/// Tags are the document `version` in quotes, e.g. `"3"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntityTags {
+    /// `*`, any version of the document
+    Any,
+    Versions(Vec<i64>),
+}
+
+impl EntityTags {
+
+    ///
+    /// # parse
+    /// this function parse the value of a conditional header, weak tags are only kept if `weak` is set
+    /// tags that are not a version are dropped and never match
+    /// # Arguments
+    /// * `value` - the header value
+    /// * `weak` - accept weak tags (`W/"3"`)
+    /// # Return
+    /// * `EntityTags` - the tags of the header
+    ///
+    pub fn parse(value: &str, weak: bool) -> Self {
+        if value.trim() == "*" {
+            return EntityTags::Any;
+        }
+        let versions = value.split(',')
+            .map(str::trim)
+            .filter_map(|tag| match tag.strip_prefix("W/") {
+                Some(tag) if weak => Some(tag),
+                Some(_) => None,
+                None => Some(tag),
+            })
+            .filter_map(|tag| tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok())
+            .collect();
+        EntityTags::Versions(versions)
+    }
+
+    ///
+    /// # matches
+    /// this function check if the tags match a version of a document
+    /// # Arguments
+    /// * `version` - the version of the document
+    /// # Return
+    /// * `bool` - true if one of the tags is the version
+    ///
+    pub fn matches(&self, version: i64) -> bool {
+        match self {
+            EntityTags::Any => true,
+            EntityTags::Versions(versions) => versions.contains(&version),
+        }
+    }
+}
+
+///
+/// # etag
+/// this function format the version of a document as an entity tag
+/// # Arguments
+/// * `version` - the version of the document
+/// # Return
+/// * `String` - the entity tag
+///
+pub fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// The `If-Match` header of an update, `None` when missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfMatch(pub Option<EntityTags>);
+
+impl IfMatch {
+
+    ///
+    /// # versions
+    /// this function return the versions the update is allowed on, `None` for any version
+    /// # Return
+    /// * `Option<Vec<i64>>` - the expected versions
+    ///
+    pub fn versions(&self) -> Option<Vec<i64>> {
+        match &self.0 {
+            Some(EntityTags::Versions(versions)) => Some(versions.clone()),
+            _ => None,
+        }
+    }
+
+    ///
+    /// # check
+    /// this function check a document left unchanged is still at one of the expected versions
+    /// # Arguments
+    /// * `version` - the current version of the document
+    /// * `name` - the kind of document, e.g. `Book`, used in the message
+    /// # Return
+    /// * `Result<(), Box<dyn Error>>` - nothing or a `412 Precondition Failed`
+    ///
+    pub fn check(&self, version: i64, name: &str) -> Result<(), Box<dyn Error>> {
+        match self.versions() {
+            Some(versions) if !versions.contains(&version) => {
+                Err(HttpError::new(Status::PreconditionFailed, format!("{} was modified by someone else, reload it and retry", name)).into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let tags = request.headers().get_one("If-Match").map(|value| EntityTags::parse(value, false));
+        Outcome::Success(IfMatch(tags))
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for IfMatch {
+    fn from_request_input(gen: &mut OpenApiGenerator, _: String, _: bool) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(header_parameter(gen, "If-Match", "ETag of the version the change applies to, 412 when it is not the current version"))
+    }
+}
+
+/// The `If-None-Match` header of a read, `None` when missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfNoneMatch(pub Option<EntityTags>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let tags = request.headers().get_one("If-None-Match").map(|value| EntityTags::parse(value, true));
+        Outcome::Success(IfNoneMatch(tags))
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for IfNoneMatch {
+    fn from_request_input(gen: &mut OpenApiGenerator, _: String, _: bool) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(header_parameter(gen, "If-None-Match", "ETags already held by the client, 304 when one is the current version"))
+    }
+}
+
+/// A JSON document sent with its `ETag`, or an empty 304 when the client already holds this version.
+#[derive(Debug)]
+pub struct Tagged<T> {
+    pub value: T,
+    pub version: i64,
+    pub not_modified: bool,
+}
+
+impl<T> Tagged<T> {
+
+    ///
+    /// # new
+    /// this function wrap a document with its version
+    /// # Arguments
+    /// * `value` - the document
+    /// * `version` - the version of the document
+    /// # Return
+    /// * `Tagged<T>` - the tagged document
+    ///
+    pub fn new(value: T, version: i64) -> Self {
+        Tagged { value, version, not_modified: false }
+    }
+
+    ///
+    /// # unless
+    /// this function answer 304 instead of the document when `If-None-Match` matches its version
+    /// # Arguments
+    /// * `self` - the tagged document
+    /// * `if_none_match` - the `If-None-Match` header of the request
+    /// # Return
+    /// * `Tagged<T>` - the tagged document
+    ///
+    pub fn unless(mut self, if_none_match: &IfNoneMatch) -> Self {
+        self.not_modified = if_none_match.0.as_ref().is_some_and(|tags| tags.matches(self.version));
+        self
+    }
+}
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Tagged<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let etag = etag(self.version);
+        if self.not_modified {
+            return Response::build().status(Status::NotModified).raw_header("ETag", etag).ok();
+        }
+        Response::build_from(Json(self.value).respond_to(request)?).raw_header("ETag", etag).ok()
+    }
+}
+
+impl<T: Serialize + JsonSchema + Send> OpenApiResponderInner for Tagged<T> {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Json::<T>::responses(gen)?;
+        let etag = Header {
+            description: Some("version of the document".to_string()),
+            required: true,
+            deprecated: false,
+            allow_empty_value: false,
+            value: ParameterValue::Schema {
+                style: None,
+                explode: None,
+                allow_reserved: false,
+                schema: gen.json_schema::<String>(),
+                example: None,
+                examples: None,
+            },
+            extensions: Default::default(),
+        };
+        if let RefOr::Object(response) = ensure_status_code_exists(&mut responses, 200) {
+            response.headers.insert("ETag".to_string(), RefOr::Object(etag.clone()));
+        }
+        let mut not_modified = OpenApiResponse { description: "Not Modified, the client holds the current version".to_string(), ..Default::default() };
+        not_modified.headers.insert("ETag".to_string(), RefOr::Object(etag));
+        responses.responses.insert("304".to_string(), RefOr::Object(not_modified));
+        Ok(responses)
+    }
+}
@@ -0,0 +1,427 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use bson::{Bson, Document};
+use mongodb::Cursor;
+use rocket::futures::future::ready;
+use rocket::futures::stream::{self, BoxStream, StreamExt};
+use rocket::http::ContentType;
+use rocket::request::FromParam;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::{get, FromForm, FromFormField};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{MediaType, RefOr, Response as OpenApiResponse, Responses};
+use rocket_okapi::openapi;
+use rocket_okapi::response::OpenApiResponderInner;
+use schemars::JsonSchema;
+use tokio_util::io::StreamReader;
+use crate::actor::Actor;
+use crate::config::MarcMapping;
+use crate::error::ApiError;
+use crate::marc::{marcxml_header, write_iso2709, write_marcxml, Record, MARCXML_FOOTER};
+use crate::mongo::Mongo;
+
+/// A kind of document that can be exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[schemars(rename_all = "lowercase")]
+pub enum ExportEntity {
+    Books,
+    Users,
+    Comments,
+    Genres,
+    Authors,
+    Works,
+    Subjects,
+    /// the current loans, one row per borrowed book
+    Loans,
+}
+
+impl ExportEntity {
+
+    ///
+    /// # from name
+    /// this function return the entity with the given name
+    /// # Arguments
+    /// * `name` - the name, as in the export path
+    /// # Return
+    /// * `Option<ExportEntity>` - the entity, `None` for an unknown name
+    ///
+    pub fn from_name(name: &str) -> Option<ExportEntity> {
+        match name {
+            "books" => Some(ExportEntity::Books),
+            "users" => Some(ExportEntity::Users),
+            "comments" => Some(ExportEntity::Comments),
+            "genres" => Some(ExportEntity::Genres),
+            "authors" => Some(ExportEntity::Authors),
+            "works" => Some(ExportEntity::Works),
+            "subjects" => Some(ExportEntity::Subjects),
+            "loans" => Some(ExportEntity::Loans),
+            _ => None,
+        }
+    }
+
+    ///
+    /// # columns
+    /// this function return the columns of a CSV export, nested fields are joined with dots
+    /// # Arguments
+    /// * `self` - the entity
+    /// # Return
+    /// * `&[&str]` - the columns
+    ///
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self {
+            ExportEntity::Books => &["_id", "title", "author", "year", "resume", "availability", "genre_ids", "min_age", "isbn_10", "isbn_13", "publisher", "edition", "language", "pages", "format", "series", "volume", "work_id", "call_number", "subject_ids", "loan.user_id", "loan.at", "loan.due", "created_at", "updated_at"],
+            ExportEntity::Users => &["_id", "first_name", "last_name", "email", "birth_date", "role", "borrowed_books", "created_at", "updated_at"],
+            ExportEntity::Comments => &["_id", "book_id", "user_id", "parent_id", "rating", "comment", "status", "helpful_count", "created_at", "updated_at"],
+            ExportEntity::Genres => &["_id", "name", "created_at", "updated_at"],
+            ExportEntity::Authors => &["_id", "name", "aliases", "birth_year", "death_year", "created_at", "updated_at"],
+            ExportEntity::Works => &["_id", "title", "author", "year", "original_language", "created_at", "updated_at"],
+            ExportEntity::Subjects => &["_id", "heading", "scope_note", "created_at", "updated_at"],
+            ExportEntity::Loans => &["book_id", "title", "user_id", "at", "due"],
+        }
+    }
+
+    ///
+    /// # personal fields
+    /// this function return the fields identifying a person, left out of an anonymised export
+    /// # Arguments
+    /// * `self` - the entity
+    /// # Return
+    /// * `&[&str]` - the fields
+    ///
+    pub fn personal_fields(&self) -> &'static [&'static str] {
+        match self {
+            ExportEntity::Books => &["loan.user_id"],
+            ExportEntity::Users => &["first_name", "last_name", "email", "birth_date"],
+            ExportEntity::Comments => &["user_id", "helpful_votes", "flags"],
+            ExportEntity::Genres | ExportEntity::Authors | ExportEntity::Works | ExportEntity::Subjects => &[],
+            ExportEntity::Loans => &["user_id"],
+        }
+    }
+}
+
+impl fmt::Display for ExportEntity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ExportEntity::Books => "books",
+            ExportEntity::Users => "users",
+            ExportEntity::Comments => "comments",
+            ExportEntity::Genres => "genres",
+            ExportEntity::Authors => "authors",
+            ExportEntity::Works => "works",
+            ExportEntity::Subjects => "subjects",
+            ExportEntity::Loans => "loans",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl<'a> FromParam<'a> for ExportEntity {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        ExportEntity::from_name(param).ok_or(param)
+    }
+}
+
+/// Format of an export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromFormField, JsonSchema)]
+#[schemars(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// comma separated values with a header row, lists are joined with `;`
+    Csv,
+    /// one relaxed extended JSON document per line, readable by `mongoimport`
+    #[default]
+    Jsonl,
+    /// BSON documents one after the other, like the `.bson` files of `mongodump`, readable by `mongorestore`
+    Bson,
+    /// binary MARC21 records (ISO 2709) written with the configured `MarcMapping`, books only
+    Marc,
+    /// a MARCXML collection written with the configured `MarcMapping`, books only
+    Marcxml,
+}
+
+impl ExportFormat {
+
+    ///
+    /// # from name
+    /// this function return the format with the given name
+    /// # Arguments
+    /// * `name` - the name, as in the `format` query parameter
+    /// # Return
+    /// * `Option<ExportFormat>` - the format, `None` for an unknown name
+    ///
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        match name {
+            "csv" => Some(ExportFormat::Csv),
+            "jsonl" => Some(ExportFormat::Jsonl),
+            "bson" => Some(ExportFormat::Bson),
+            "marc" => Some(ExportFormat::Marc),
+            "marcxml" => Some(ExportFormat::Marcxml),
+            _ => None,
+        }
+    }
+
+    ///
+    /// # content type
+    /// this function return the content type of an export in the format
+    /// # Arguments
+    /// * `self` - the format
+    /// # Return
+    /// * `ContentType` - the content type
+    ///
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            ExportFormat::Csv => ContentType::CSV,
+            ExportFormat::Jsonl => ContentType::new("application", "x-ndjson"),
+            ExportFormat::Bson => ContentType::new("application", "bson"),
+            ExportFormat::Marc => ContentType::new("application", "marc"),
+            ExportFormat::Marcxml => ContentType::new("application", "marcxml+xml"),
+        }
+    }
+
+    ///
+    /// # is marc
+    /// this function check if the format writes MARC records, which only hold books
+    /// # Arguments
+    /// * `self` - the format
+    /// # Return
+    /// * `bool` - true for `marc` and `marcxml`
+    ///
+    pub fn is_marc(&self) -> bool {
+        matches!(self, ExportFormat::Marc | ExportFormat::Marcxml)
+    }
+}
+
+/// Options of an export, read from the query string.
+/// `genre` (a genre name) applies to books and loans, `user_id` to comments and loans, `book_id` to comments.
+#[derive(Debug, Clone, Default, FromForm, JsonSchema)]
+pub struct ExportFilter {
+    pub format: Option<ExportFormat>,
+    pub genre: Option<String>,
+    pub user_id: Option<String>,
+    pub book_id: Option<String>,
+    /// leave out the fields identifying a person
+    pub anonymise: Option<bool>,
+}
+
+impl ExportFilter {
+
+    ///
+    /// # anonymised
+    /// this function check if the personal fields are left out of the export
+    /// # Arguments
+    /// * `self` - the options of the export
+    /// # Return
+    /// * `bool` - true when `anonymise` is set
+    ///
+    pub fn anonymised(&self) -> bool {
+        self.anonymise.unwrap_or(false)
+    }
+}
+
+///
+/// # columns
+/// this function return the columns of a CSV export, without the personal fields when anonymised
+/// # Arguments
+/// * `entity` - the exported entity
+/// * `anonymise` - leave out the personal fields
+/// # Return
+/// * `Vec<&str>` - the columns
+///
+fn columns(entity: ExportEntity, anonymise: bool) -> Vec<&'static str> {
+    entity.columns().iter()
+        .copied()
+        .filter(|column| !anonymise || !entity.personal_fields().contains(column))
+        .collect()
+}
+
+///
+/// # header
+/// this function return the bytes written before the documents of an export
+/// # Arguments
+/// * `entity` - the exported entity
+/// * `filter` - the options of the export
+/// # Return
+/// * `Result<Vec<u8>, Box<dyn Error>>` - the header row of a CSV export, the start of a MARCXML collection, nothing for other formats
+///
+pub fn header(entity: ExportEntity, filter: &ExportFilter) -> Result<Vec<u8>, Box<dyn Error>> {
+    match filter.format.unwrap_or_default() {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record(columns(entity, filter.anonymised()))?;
+            Ok(writer.into_inner()?)
+        }
+        ExportFormat::Marcxml => Ok(marcxml_header().into_bytes()),
+        _ => Ok(Vec::new()),
+    }
+}
+
+///
+/// # footer
+/// this function return the bytes written after the documents of an export
+/// # Arguments
+/// * `filter` - the options of the export
+/// # Return
+/// * `Vec<u8>` - the end of a MARCXML collection, nothing for other formats
+///
+pub fn footer(filter: &ExportFilter) -> Vec<u8> {
+    match filter.format.unwrap_or_default() {
+        ExportFormat::Marcxml => MARCXML_FOOTER.as_bytes().to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+///
+/// # encode
+/// this function write one document of an export
+/// # Arguments
+/// * `entity` - the exported entity
+/// * `filter` - the options of the export
+/// * `marc` - the subfields of a MARC record holding the fields of a book
+/// * `document` - the document, its personal fields already left out when anonymised
+/// # Return
+/// * `Result<Vec<u8>, Box<dyn Error>>` - the bytes of the document or an error
+///
+pub fn encode(entity: ExportEntity, filter: &ExportFilter, marc: &MarcMapping, document: Document) -> Result<Vec<u8>, Box<dyn Error>> {
+    match filter.format.unwrap_or_default() {
+        ExportFormat::Csv => {
+            let row: Vec<String> = columns(entity, filter.anonymised()).into_iter()
+                .map(|column| cell(lookup(&document, column)))
+                .collect();
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record(row)?;
+            Ok(writer.into_inner()?)
+        }
+        ExportFormat::Jsonl => {
+            let mut line = Bson::Document(document).into_relaxed_extjson().to_string().into_bytes();
+            line.push(b'\n');
+            Ok(line)
+        }
+        ExportFormat::Bson => {
+            let mut bytes = Vec::new();
+            document.to_writer(&mut bytes)?;
+            Ok(bytes)
+        }
+        ExportFormat::Marc => write_iso2709(&Record::from_book(&document, marc)),
+        ExportFormat::Marcxml => Ok(write_marcxml(&Record::from_book(&document, marc)).into_bytes()),
+    }
+}
+
+///
+/// # lookup
+/// this function return the value of a field of a document
+/// # Arguments
+/// * `document` - the document
+/// * `path` - the name of the field, nested fields are joined with dots
+/// # Return
+/// * `Option<&Bson>` - the value, `None` when missing
+///
+fn lookup<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    let (first, rest) = match path.split_once('.') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (path, None),
+    };
+    match (document.get(first)?, rest) {
+        (Bson::Document(document), Some(rest)) => lookup(document, rest),
+        (value, None) => Some(value),
+        _ => None,
+    }
+}
+
+///
+/// # cell
+/// this function write a value as a CSV cell
+/// # Arguments
+/// * `value` - the value
+/// # Return
+/// * `String` - the cell: ids in hexadecimal, dates in RFC 3339, lists joined with `;`, empty when missing
+///
+fn cell(value: Option<&Bson>) -> String {
+    match value {
+        None | Some(Bson::Null) => String::new(),
+        Some(Bson::String(text)) => text.clone(),
+        Some(Bson::ObjectId(id)) => id.to_hex(),
+        Some(Bson::DateTime(date)) => date.to_chrono().to_rfc3339(),
+        Some(Bson::Array(values)) => values.iter().map(|value| cell(Some(value))).collect::<Vec<_>>().join(";"),
+        Some(Bson::Document(document)) => Bson::Document(document.clone()).into_relaxed_extjson().to_string(),
+        Some(value) => value.to_string(),
+    }
+}
+
+///
+/// # stream
+/// this function turn a cursor into the bytes of an export, documents are encoded as they are read;
+/// the export stops at the first error, which ends the stream so that the export is not taken for complete
+/// # Arguments
+/// * `entity` - the exported entity
+/// * `filter` - the options of the export
+/// * `marc` - the subfields of a MARC record holding the fields of a book
+/// * `cursor` - the cursor over the exported documents
+/// # Return
+/// * `Result<BoxStream<io::Result<Vec<u8>>>, Box<dyn Error>>` - the bytes of the export or an error
+///
+pub fn stream(entity: ExportEntity, filter: ExportFilter, marc: MarcMapping, cursor: Cursor<Document>) -> Result<BoxStream<'static, io::Result<Vec<u8>>>, Box<dyn Error>> {
+    let header = header(entity, &filter)?;
+    let footer = footer(&filter);
+    let documents = cursor.map(move |document| {
+        document.map_err(Box::<dyn Error>::from)
+            .and_then(|document| encode(entity, &filter, &marc, document))
+            .map_err(|e| io::Error::other(format!("export of {} stopped: {}", entity, e)))
+    });
+    Ok(stream::once(ready(Ok(header)))
+        .chain(documents)
+        .chain(stream::once(ready(Ok(footer))))
+        // nothing follows an error, not even the footer closing a MARCXML collection
+        .scan(false, |failed, bytes| {
+            if *failed {
+                return ready(None);
+            }
+            *failed = bytes.is_err();
+            ready(Some(bytes))
+        })
+        .filter(|bytes| ready(!matches!(bytes, Ok(bytes) if bytes.is_empty())))
+        .boxed())
+}
+
+/// An export streamed to the client, with the content type of its format.
+/// An error while streaming aborts the response, the client sees the body cut short.
+pub struct ExportStream {
+    pub content_type: ContentType,
+    pub bytes: BoxStream<'static, io::Result<Vec<u8>>>,
+}
+
+impl<'r> Responder<'r, 'r> for ExportStream {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'r> {
+        let reader = StreamReader::new(self.bytes.map(|bytes| bytes.map(io::Cursor::new)));
+        Response::build()
+            .header(self.content_type)
+            .streamed_body(reader)
+            .ok()
+    }
+}
+
+impl OpenApiResponderInner for ExportStream {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut response = OpenApiResponse { description: "the exported documents, in the requested format".to_string(), ..Default::default() };
+        for format in [ExportFormat::Csv, ExportFormat::Jsonl, ExportFormat::Bson, ExportFormat::Marc, ExportFormat::Marcxml] {
+            let media = MediaType { schema: Some(gen.json_schema::<String>()), ..Default::default() };
+            response.content.insert(format.content_type().to_string(), media);
+        }
+        let mut responses = Responses::default();
+        responses.responses.insert("200".to_string(), RefOr::Object(response));
+        Ok(responses)
+    }
+}
+
+/// export books, users, comments, genres, authors, works, subjects or loans in CSV, JSON Lines or BSON, or books in MARC21 or MARCXML, streamed from the database;
+/// only librarians export personal fields, others must set anonymise
+#[openapi(tag = "Export")]
+#[get("/api/export/<entity>?<filter..>")]
+pub async fn export(entity: ExportEntity, filter: ExportFilter, actor: Actor, db: Mongo) -> Result<ExportStream, ApiError> {
+    db.authorise_export(entity, &filter, actor.id()).await?;
+    let cursor = db.export(entity, &filter).await?;
+    let content_type = filter.format.unwrap_or_default().content_type();
+    Ok(ExportStream { content_type, bytes: stream(entity, filter, db.config.marc.clone(), cursor)? })
+}
@@ -1,32 +1,65 @@
-use rocket::State;
-use std::error::Error;
-use rocket::response::Debug;
 use crate::mongo::Mongo;
+use rocket::{get, post};
+use rocket_okapi::openapi;
 use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use validator::Validate;
 use rocket::form::FromForm;
 use rocket::serde::json::Json;
+use chrono::{DateTime, Utc};
 use crate::book::Book;
+use crate::TimeFilter;
+use crate::actor::Actor;
+use crate::error::ApiError;
+use crate::validation::Validated;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromForm)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Genre {
     pub name: String,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub created_at: DateTime<Utc>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub updated_at: DateTime<Utc>,
 }
 
-#[rocket::post("/api/genre", data = "<genre>")]
-pub async fn create_genre(genre: Json<Genre>, db: &State<Mongo>) -> Result<Json<Genre>, Debug<Box<dyn Error>>> {
-    let new_genre = db.create_genre(genre.into_inner()).await?;
+#[derive(Debug, Clone, Serialize, Deserialize, FromForm, JsonSchema, Validate)]
+pub struct NewGenre {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+}
+
+impl From<NewGenre> for Genre {
+    fn from(value: NewGenre) -> Self {
+        Genre {
+            name: value.name,
+            created_at: DateTime::default(),
+            updated_at: DateTime::default(),
+        }
+    }
+}
+
+/// create a genre
+#[openapi(tag = "Genres")]
+#[post("/api/genre", data = "<genre>")]
+pub async fn create_genre(genre: Validated<NewGenre>, actor: Actor, db: Mongo) -> Result<Json<Genre>, ApiError> {
+    let new_genre = db.create_genre(genre.into_inner(), actor.id()).await?;
     Ok(Json(new_genre))
 }
 
-#[rocket::get("/api/genre")]
-pub async fn get_genres(db: &State<Mongo>) -> Result<Json<Vec<Genre>>, Debug<Box<dyn Error>>> {
-    let genres = db.get_all_genres().await?;
+/// list genres, sorted and filtered on created_at/updated_at
+#[openapi(tag = "Genres")]
+#[get("/api/genre?<time..>")]
+pub async fn get_genres(time: TimeFilter, db: Mongo) -> Result<Json<Vec<Genre>>, ApiError> {
+    let genres = db.get_all_genres(&time).await?;
     Ok(Json(genres))
 }
 
-// list all books by gender name
-#[rocket::get("/api/genre/<name>")]
-pub async fn get_books_by_genre(name: &str, db: &State<Mongo>) -> Result<Json<Vec<Book>>, Debug<Box<dyn Error>>> {
+/// list all books by gender name
+#[openapi(tag = "Genres")]
+#[get("/api/genre/<name>")]
+pub async fn get_books_by_genre(name: &str, db: Mongo) -> Result<Json<Vec<Book>>, ApiError> {
     let books = db.get_books_by_genre(name).await?;
     Ok(Json(books))
 }
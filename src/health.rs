@@ -0,0 +1,27 @@
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use rocket::serde::json::Json;
+use crate::mongo::Mongo;
+use rocket::get;
+use rocket_okapi::openapi;
+use crate::error::ApiError;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Health {
+    pub status: String,
+}
+
+/// liveness, the process answers requests
+#[openapi(tag = "Monitoring")]
+#[get("/health")]
+pub async fn get_health() -> Json<Health> {
+    Json(Health { status: "ok".to_string() })
+}
+
+/// readiness, the database answers a ping, 503 with Retry-After otherwise
+#[openapi(tag = "Monitoring")]
+#[get("/ready")]
+pub async fn get_ready(db: Mongo) -> Result<Json<Health>, ApiError> {
+    db.ping().await?;
+    Ok(Json(Health { status: "ready".to_string() }))
+}
@@ -0,0 +1,427 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io;
+use rocket::data::{Data, FromData, Outcome, ToByteUnit};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::serde::json::serde_json;
+use rocket::FromFormField;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{MediaType, RequestBody};
+use rocket_okapi::request::OpenApiFromData;
+use schemars::JsonSchema;
+use serde::{Serialize, Deserialize};
+use crate::book::{BookFormat, NewBook};
+use crate::config::MarcMapping;
+use crate::marc::{read_iso2709, read_marcxml};
+use crate::error::HttpError;
+use crate::{call_number, isbn};
+use crate::validation::FieldErrors;
+use validator::Validate;
+
+/// books inserted by one `insert_many`
+pub const IMPORT_BATCH_SIZE: usize = 500;
+
+/// separator of the genre names in the `genres` column of a CSV file
+pub const GENRE_SEPARATOR: char = ';';
+
+/// Format of an import file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField, JsonSchema)]
+#[schemars(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// comma separated values with a header row naming the columns
+    Csv,
+    /// one JSON object per line
+    Jsonl,
+    /// binary MARC21 records (ISO 2709), read with the configured `MarcMapping`;
+    /// records must be in UTF-8 (leader/09 `a`), MARC-8 records are only read when they are plain ASCII
+    Marc,
+    /// MARC21 records in XML, read with the configured `MarcMapping`
+    Marcxml,
+}
+
+impl ImportFormat {
+
+    ///
+    /// # from extension
+    /// this function return the format of a file from its extension
+    /// # Arguments
+    /// * `path` - the path of the file
+    /// # Return
+    /// * `Option<ImportFormat>` - the format, `None` for an unknown extension
+    ///
+    pub fn from_extension(path: &str) -> Option<ImportFormat> {
+        match path.rsplit('.').next()?.to_lowercase().as_str() {
+            "csv" => Some(ImportFormat::Csv),
+            "jsonl" | "ndjson" => Some(ImportFormat::Jsonl),
+            "mrc" | "marc" => Some(ImportFormat::Marc),
+            "xml" => Some(ImportFormat::Marcxml),
+            _ => None,
+        }
+    }
+}
+
+/// A book of an import file: the fields of `NewBook`, with its genres given by name.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImportBook {
+    pub title: String,
+    pub author: String,
+    pub year: i32,
+    #[serde(default)]
+    pub resume: String,
+    #[serde(default)]
+    pub min_age: Option<i32>,
+    /// names of existing genres, in any case
+    #[serde(default)]
+    pub genres: Vec<String>,
+    /// ISBN-10 or ISBN-13, with or without hyphens
+    #[serde(default)]
+    pub isbn: Option<String>,
+    #[serde(default)]
+    pub publisher: Option<String>,
+    #[serde(default)]
+    pub edition: Option<String>,
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub pages: Option<i32>,
+    #[serde(default)]
+    pub format: Option<BookFormat>,
+    #[serde(default)]
+    pub series: Option<String>,
+    #[serde(default)]
+    pub volume: Option<i32>,
+    /// Dewey Decimal call number
+    #[serde(default)]
+    pub call_number: Option<String>,
+}
+
+/// A row of a CSV file, the genre names are separated by `GENRE_SEPARATOR`.
+#[derive(Debug, Deserialize)]
+struct CsvBook {
+    title: String,
+    author: String,
+    year: i32,
+    #[serde(default)]
+    resume: String,
+    #[serde(default)]
+    min_age: Option<i32>,
+    #[serde(default)]
+    genres: String,
+    #[serde(default)]
+    isbn: Option<String>,
+    #[serde(default)]
+    publisher: Option<String>,
+    #[serde(default)]
+    edition: Option<String>,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    pages: Option<i32>,
+    #[serde(default)]
+    format: Option<BookFormat>,
+    #[serde(default)]
+    series: Option<String>,
+    #[serde(default)]
+    volume: Option<i32>,
+    #[serde(default)]
+    call_number: Option<String>,
+}
+
+impl From<CsvBook> for ImportBook {
+    fn from(value: CsvBook) -> Self {
+        ImportBook {
+            title: value.title,
+            author: value.author,
+            year: value.year,
+            resume: value.resume,
+            min_age: value.min_age,
+            genres: value.genres.split(GENRE_SEPARATOR)
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+            isbn: value.isbn,
+            publisher: value.publisher,
+            edition: value.edition,
+            language: value.language,
+            pages: value.pages,
+            format: value.format,
+            series: value.series,
+            volume: value.volume,
+            call_number: value.call_number,
+        }
+    }
+}
+
+impl ImportBook {
+
+    ///
+    /// # into new book
+    /// this function resolve the genres of the book by name, read its ISBN and check it against the rules of `NewBook`
+    /// # Arguments
+    /// * `self` - the book read from the file
+    /// * `genres` - the ids of the genres, keyed by lowercase name
+    /// # Return
+    /// * `Result<NewBook, FieldErrors>` - the book to create, or the messages of its invalid fields
+    ///
+    pub fn into_new_book(self, genres: &HashMap<String, String>) -> Result<NewBook, FieldErrors> {
+        let mut errors = FieldErrors::default();
+        let mut genre_ids = Vec::new();
+        for name in &self.genres {
+            match genres.get(&name.to_lowercase()) {
+                Some(id) => genre_ids.push(id.clone()),
+                None => errors.0.entry("genres".to_string()).or_default().push(format!("unknown genre {}", name)),
+            }
+        }
+        let (isbn_10, isbn_13) = match self.isbn.as_deref().map(isbn::parse) {
+            Some(Some((isbn_10, isbn_13))) => (isbn_10, Some(isbn_13)),
+            Some(None) => {
+                errors.0.entry("isbn".to_string()).or_default().push("must be a valid ISBN-10 or ISBN-13".to_string());
+                (None, None)
+            }
+            None => (None, None),
+        };
+        let book = NewBook {
+            title: self.title,
+            author: self.author,
+            year: self.year,
+            resume: self.resume,
+            min_age: self.min_age,
+            genre_ids,
+            authors: Vec::new(),
+            isbn_10,
+            isbn_13,
+            publisher: self.publisher,
+            edition: self.edition,
+            language: self.language,
+            pages: self.pages,
+            format: self.format,
+            series: self.series,
+            volume: self.volume,
+            work_id: None,
+            call_number: self.call_number,
+            subject_ids: Vec::new(),
+        };
+        if let Err(e) = book.validate() {
+            for (field, messages) in FieldErrors::from(e).0 {
+                errors.0.entry(field).or_default().extend(messages);
+            }
+        }
+        match errors.0.is_empty() {
+            true => Ok(book),
+            false => Err(errors),
+        }
+    }
+}
+
+/// A row of an import file with its line number, or the number of the record in a MARC file, or why it could not be read.
+#[derive(Debug, Clone)]
+pub struct ImportRow {
+    pub line: usize,
+    pub book: Result<ImportBook, String>,
+    /// what was left out of the book, which is still imported
+    pub warnings: Vec<String>,
+}
+
+/// A row left out of an import.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RowError {
+    /// line of the row in the file, or number of the record in a MARC file, starting at 1
+    pub line: usize,
+    pub error: String,
+    /// messages of the invalid fields of the row, keyed by the name of the field
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, Vec<String>>,
+}
+
+/// A row imported without some of its fields.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RowWarning {
+    /// line of the row in the file, or number of the record in a MARC file, starting at 1
+    pub line: usize,
+    pub warning: String,
+}
+
+/// Result of an import: the rows read, the books inserted, the rows left out and the rows imported without some of their fields.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImportReport {
+    /// nothing was written, `imported` is the number of books that would have been inserted
+    pub dry_run: bool,
+    pub rows: usize,
+    pub imported: usize,
+    pub errors: Vec<RowError>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<RowWarning>,
+    /// the write that stopped the import, the books of the rows before it are imported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<ImportFailure>,
+}
+
+/// A write of an import that failed: the valid rows from `line` on were not imported.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImportFailure {
+    pub line: usize,
+    pub error: String,
+}
+
+///
+/// # parse
+/// this function read the rows of an import file, a row that cannot be read is kept with its error
+/// # Arguments
+/// * `format` - the format of the file
+/// * `content` - the content of the file
+/// * `marc` - the subfields of a MARC record holding the fields of a book
+/// # Return
+/// * `Result<Vec<ImportRow>, Box<dyn Error>>` - the rows, or a `422` error when a text file is not UTF-8,
+///   a CSV file lacks a required column or a MARCXML document is not well formed
+///
+pub fn parse(format: ImportFormat, content: &[u8], marc: &MarcMapping) -> Result<Vec<ImportRow>, Box<dyn Error>> {
+    let text = || std::str::from_utf8(content).map_err(|_| unprocessable("Import file is not UTF-8"));
+    let records = match format {
+        ImportFormat::Csv => return parse_csv(text()?),
+        ImportFormat::Jsonl => return Ok(parse_jsonl(text()?)),
+        ImportFormat::Marc => read_iso2709(content),
+        ImportFormat::Marcxml => read_marcxml(text()?).map_err(|e| unprocessable(e.to_string()))?,
+    };
+    Ok(records.into_iter()
+        .enumerate()
+        .map(|(index, record)| {
+            let mut warnings = Vec::new();
+            let book = record.and_then(|record| record.to_import_book(marc))
+                .map(|book| keep_dewey(book, &mut warnings));
+            ImportRow { line: index + 1, book, warnings }
+        })
+        .collect())
+}
+
+///
+/// # keep dewey
+/// this function leave out the call number of a MARC record that is not a Dewey class, like a Library of Congress one,
+/// as catalogues often have in `082`, so that the book is imported without it
+/// # Arguments
+/// * `book` - the book read from the record
+/// * `warnings` - the warnings of the row, the call number left out is added to them
+/// # Return
+/// * `ImportBook` - the book, with its call number when it is a Dewey class
+///
+fn keep_dewey(mut book: ImportBook, warnings: &mut Vec<String>) -> ImportBook {
+    if let Some(number) = book.call_number.take() {
+        match call_number::is_dewey(&call_number::normalise(&number)) {
+            true => book.call_number = Some(number),
+            false => warnings.push(format!("Call number {} is not a Dewey class, left out", number)),
+        }
+    }
+    book
+}
+
+///
+/// # unprocessable
+/// this function build a `422 Unprocessable Entity` error
+/// # Arguments
+/// * `message` - the message sent to the client
+/// # Return
+/// * `Box<dyn Error>` - the error
+///
+fn unprocessable(message: impl Into<String>) -> Box<dyn Error> {
+    HttpError::new(Status::UnprocessableEntity, message).into()
+}
+
+///
+/// # parse csv
+/// this function read the rows of a CSV file, the header row names the columns
+/// # Arguments
+/// * `content` - the content of the file
+/// # Return
+/// * `Result<Vec<ImportRow>, Box<dyn Error>>` - the rows or an error
+///
+fn parse_csv(content: &str) -> Result<Vec<ImportRow>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(content.as_bytes());
+    let headers = reader.headers()?.clone();
+    for column in ["title", "author", "year"] {
+        if !headers.iter().any(|header| header == column) {
+            return Err(unprocessable(format!("Missing column {}", column)));
+        }
+    }
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let row = match record {
+            Ok(record) => ImportRow {
+                line: record.position().map_or(0, |position| position.line() as usize),
+                book: record.deserialize::<CsvBook>(Some(&headers)).map(ImportBook::from).map_err(|e| e.to_string()),
+                warnings: Vec::new(),
+            },
+            Err(e) => ImportRow {
+                line: e.position().map_or(0, |position| position.line() as usize),
+                book: Err(e.to_string()),
+                warnings: Vec::new(),
+            },
+        };
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+///
+/// # parse jsonl
+/// this function read the rows of a JSON Lines file, blank lines are skipped
+/// # Arguments
+/// * `content` - the content of the file
+/// # Return
+/// * `Vec<ImportRow>` - the rows
+///
+fn parse_jsonl(content: &str) -> Vec<ImportRow> {
+    content.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| ImportRow {
+            line: index + 1,
+            book: serde_json::from_str(line).map_err(|e| e.to_string()),
+            warnings: Vec::new(),
+        })
+        .collect()
+}
+
+/// An import file sent as request data, `text/csv`, `application/x-ndjson`, `application/marc`
+/// or `application/marcxml+xml`, any other type is answered 415.
+/// Its size is limited by the `import` limit, 16 MiB by default.
+#[derive(Debug, Clone)]
+pub struct ImportFile {
+    pub format: ImportFormat,
+    pub content: Vec<u8>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for ImportFile {
+    type Error = io::Error;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r, Self> {
+        let format = match request.content_type() {
+            Some(content_type) if content_type.top() == "text" && content_type.sub() == "csv" => ImportFormat::Csv,
+            Some(content_type) if content_type.top() == "application" && ["x-ndjson", "jsonl"].contains(&content_type.sub().as_str()) => ImportFormat::Jsonl,
+            Some(content_type) if content_type.top() == "application" && content_type.sub() == "marc" => ImportFormat::Marc,
+            Some(content_type) if content_type.top() == "application" && content_type.sub() == "marcxml+xml" => ImportFormat::Marcxml,
+            _ => return Outcome::Forward((data, Status::UnsupportedMediaType)),
+        };
+        let limit = request.limits().get("import").unwrap_or(16.mebibytes());
+        match data.open(limit).into_bytes().await {
+            Ok(content) if content.is_complete() => Outcome::Success(ImportFile { format, content: content.into_inner() }),
+            Ok(_) => Outcome::Error((Status::PayloadTooLarge, io::Error::other("Import file too large"))),
+            Err(e) => Outcome::Error((Status::BadRequest, e)),
+        }
+    }
+}
+
+impl<'r> OpenApiFromData<'r> for ImportFile {
+    fn request_body(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<RequestBody> {
+        let mut body = RequestBody { required: true, ..Default::default() };
+        let csv = MediaType { schema: Some(gen.json_schema::<String>()), ..Default::default() };
+        let jsonl = MediaType { schema: Some(gen.json_schema::<ImportBook>()), ..Default::default() };
+        body.content.insert("text/csv".to_string(), csv);
+        body.content.insert("application/x-ndjson".to_string(), jsonl);
+        for marc in ["application/marc", "application/marcxml+xml"] {
+            body.content.insert(marc.to_string(), MediaType { schema: Some(gen.json_schema::<String>()), ..Default::default() });
+        }
+        Ok(body)
+    }
+}
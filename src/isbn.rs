@@ -0,0 +1,128 @@
+///
+/// # normalise
+/// this function remove the hyphens and spaces of an ISBN and uppercase its check digit
+/// # Arguments
+/// * `isbn` - the ISBN as written, like `978-0-441-01359-3` or `0 441 01359 7`
+/// # Return
+/// * `String` - the digits of the ISBN
+///
+pub fn normalise(isbn: &str) -> String {
+    isbn.chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+///
+/// # is isbn 10
+/// this function check the length, the digits and the check digit of a normalised ISBN-10
+/// # Arguments
+/// * `isbn` - the normalised ISBN
+/// # Return
+/// * `bool` - true for a valid ISBN-10
+///
+pub fn is_isbn_10(isbn: &str) -> bool {
+    let bytes = isbn.as_bytes();
+    if bytes.len() != 10 || !bytes[..9].iter().all(u8::is_ascii_digit) {
+        return false;
+    }
+    let check = match bytes[9] {
+        b'X' => 10,
+        digit if digit.is_ascii_digit() => (digit - b'0') as u32,
+        _ => return false,
+    };
+    let sum: u32 = bytes[..9].iter().enumerate().map(|(index, digit)| (10 - index as u32) * (digit - b'0') as u32).sum();
+    (sum + check).is_multiple_of(11)
+}
+
+///
+/// # is isbn 13
+/// this function check the length, the prefix, the digits and the check digit of a normalised ISBN-13
+/// # Arguments
+/// * `isbn` - the normalised ISBN
+/// # Return
+/// * `bool` - true for a valid ISBN-13, starting with 978 or 979
+///
+pub fn is_isbn_13(isbn: &str) -> bool {
+    isbn.len() == 13
+        && isbn.bytes().all(|digit| digit.is_ascii_digit())
+        && (isbn.starts_with("978") || isbn.starts_with("979"))
+        && isbn_13_check_digit(&isbn[..12]) == isbn.as_bytes()[12]
+}
+
+///
+/// # isbn 13 check digit
+/// this function compute the check digit of an ISBN-13
+/// # Arguments
+/// * `digits` - the first 12 digits
+/// # Return
+/// * `u8` - the check digit, as an ASCII digit
+///
+fn isbn_13_check_digit(digits: &str) -> u8 {
+    let sum: u32 = digits.bytes().enumerate().map(|(index, digit)| (digit - b'0') as u32 * if index % 2 == 0 { 1 } else { 3 }).sum();
+    b'0' + ((10 - sum % 10) % 10) as u8
+}
+
+///
+/// # to isbn 13
+/// this function convert a valid ISBN-10 to its ISBN-13
+/// # Arguments
+/// * `isbn` - the normalised ISBN-10
+/// # Return
+/// * `String` - the ISBN-13, prefixed with 978
+///
+pub fn to_isbn_13(isbn: &str) -> String {
+    let digits = format!("978{}", &isbn[..9]);
+    let check = isbn_13_check_digit(&digits) as char;
+    format!("{}{}", digits, check)
+}
+
+///
+/// # to isbn 10
+/// this function convert a valid ISBN-13 to its ISBN-10
+/// # Arguments
+/// * `isbn` - the normalised ISBN-13
+/// # Return
+/// * `Option<String>` - the ISBN-10, `None` for a 979 ISBN which has none
+///
+pub fn to_isbn_10(isbn: &str) -> Option<String> {
+    let digits = isbn.strip_prefix("978")?.get(..9)?;
+    let sum: u32 = digits.bytes().enumerate().map(|(index, digit)| (10 - index as u32) * (digit - b'0') as u32).sum();
+    let check = match (11 - sum % 11) % 11 {
+        10 => 'X',
+        check => char::from(b'0' + check as u8),
+    };
+    Some(format!("{}{}", digits, check))
+}
+
+///
+/// # parse
+/// this function read an ISBN in either form
+/// # Arguments
+/// * `isbn` - the ISBN as written
+/// # Return
+/// * `Option<(Option<String>, String)>` - the normalised ISBN-10, if it has one, and ISBN-13, `None` when invalid
+///
+pub fn parse(isbn: &str) -> Option<(Option<String>, String)> {
+    let isbn = normalise(isbn);
+    if is_isbn_10(&isbn) {
+        return Some((Some(isbn.clone()), to_isbn_13(&isbn)));
+    }
+    if is_isbn_13(&isbn) {
+        return Some((to_isbn_10(&isbn), isbn));
+    }
+    None
+}
+
+///
+/// # pair
+/// this function complete the ISBNs of a book from the ones given, the ISBN-13 wins when both are given
+/// # Arguments
+/// * `isbn_10` - the ISBN-10, as written
+/// * `isbn_13` - the ISBN-13, as written
+/// # Return
+/// * `Option<(Option<String>, String)>` - the normalised ISBN-10, if it has one, and ISBN-13, `None` when none is given or valid
+///
+pub fn pair(isbn_10: Option<&str>, isbn_13: Option<&str>) -> Option<(Option<String>, String)> {
+    isbn_13.and_then(parse).or_else(|| isbn_10.and_then(parse))
+}
@@ -3,11 +3,43 @@ pub mod book;
 pub mod user;
 pub mod comment;
 pub mod mongo;
+pub mod date;
+pub mod actor;
+pub mod audit;
+pub mod error;
+pub mod etag;
+pub mod patch;
+pub mod migration;
+pub mod config;
+pub mod health;
+pub mod metrics;
+pub mod request_log;
+pub mod openapi;
+pub mod validation;
+pub mod import;
+pub mod export;
+pub mod marc;
+pub mod isbn;
+pub mod author;
+pub mod work;
+pub mod subject;
+pub mod call_number;
 
+use rocket::form::{FromForm, FromFormField};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use bson::{doc, Document};
+use schemars::JsonSchema;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Int(i32),
     Bool(bool),
     Text(String),
+    Date(NaiveDate),
+    TextList(Vec<String>),
+    DocumentList(Vec<bson::Document>),
+    Null,
 }
 
 pub enum OperatorRating {
@@ -17,4 +49,84 @@ pub enum OperatorRating {
     GreaterOrEqual(f64),
     Less(f64),
     LessOrEqual(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField, JsonSchema)]
+#[schemars(rename_all = "snake_case")]
+pub enum TimestampField {
+    #[field(value = "created_at")]
+    CreatedAt,
+    #[field(value = "updated_at")]
+    UpdatedAt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField, JsonSchema)]
+#[schemars(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Soft delete marker of a book, user or comment: who deleted it and when.
+/// Documents carrying it are kept in the trash until restored or purged.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Deletion {
+    pub by: Option<String>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub at: DateTime<Utc>,
+}
+
+impl Deletion {
+
+    ///
+    /// # in trash
+    /// this function restrict a filter to the documents in the trash
+    /// # Arguments
+    /// * `filter` - the filter to restrict
+    /// # Return
+    /// * `Document` - the restricted filter
+    ///
+    pub fn in_trash(mut filter: Document) -> Document {
+        filter.insert("deleted", doc! {"$ne": null});
+        filter
+    }
+
+    ///
+    /// # expired
+    /// this function return the filter of the documents deleted before a date, which the purge removes
+    /// # Arguments
+    /// * `before` - the date
+    /// # Return
+    /// * `Document` - the filter
+    ///
+    pub fn expired(before: DateTime<Utc>) -> Document {
+        doc! {"deleted.at": {"$lt": bson::DateTime::from_chrono(before)}}
+    }
+
+    ///
+    /// # deleted along
+    /// this function restrict a filter to the documents deleted along with this deletion, they share its date
+    /// # Arguments
+    /// * `self` - the deletion
+    /// * `filter` - the filter to restrict
+    /// # Return
+    /// * `Document` - the restricted filter
+    ///
+    pub fn along(&self, mut filter: Document) -> Document {
+        filter.insert("deleted.at", bson::DateTime::from_chrono(self.at));
+        filter
+    }
+}
+
+/// Sorting and filtering on `created_at`/`updated_at`, read from the query string
+/// of the list and search endpoints. Dates are RFC 3339 strings.
+#[derive(Debug, Clone, Default, FromForm, JsonSchema)]
+pub struct TimeFilter {
+    pub sort: Option<TimestampField>,
+    pub order: Option<SortOrder>,
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+    pub updated_after: Option<String>,
+    pub updated_before: Option<String>,
 }
\ No newline at end of file
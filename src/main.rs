@@ -1,20 +1,69 @@
-use bibliotheca::mongo::BuildMongo;
-use bibliotheca::book::{create_book, get_books, get_book, search_book, update_book, delete_book, borrow_book, return_book};
-use bibliotheca::user::{create_user, get_users, delete_user, update_user, search_user};
-use bibliotheca::genre::{create_genre, get_genres, get_books_by_genre};
-use bibliotheca::comment::{create_comment, get_comments, get_comments_by_book_id, get_comments_by_user_id, get_rating_by_book_id, get_all_books_by_search_rating};
+use std::time::Duration;
+use rocket::fairing::AdHoc;
+use bibliotheca::config::figment;
+use bibliotheca::mongo::{BuildMongo, Mongo};
+use bibliotheca::metrics::RequestMetrics;
+use bibliotheca::openapi::{api, docs, DOCS_PATH};
+use bibliotheca::request_log::RequestLog;
+use bibliotheca::validation::unprocessable_entity;
 
 // no main function
 #[macro_use] extern crate rocket;
 
 #[launch]
-async fn rocket() -> _ {
-    let mongo = BuildMongo::new().await.unwrap().build();
-
-    rocket::build()
-        .mount("/", routes![create_book, get_books, get_book, search_book, delete_book, update_book, borrow_book, return_book])
-        .mount("/", routes![create_user, get_users, delete_user, update_user, search_user])
-        .mount("/", routes![create_genre, get_genres, get_books_by_genre])
-        .mount("/", routes![create_comment, get_comments, get_comments_by_book_id, get_comments_by_user_id, get_rating_by_book_id, get_all_books_by_search_rating])
-        .manage(mongo)
+fn rocket() -> _ {
+    let (routes, spec) = api();
+    let (openapi, swagger_ui) = docs(spec);
+    rocket::custom(figment())
+        .mount("/", routes)
+        .mount("/", vec![openapi])
+        .mount(DOCS_PATH, swagger_ui)
+        .register("/", catchers![unprocessable_entity])
+        .attach(RequestMetrics)
+        .attach(RequestLog)
+        .attach(AdHoc::try_on_ignite("Database", |rocket| async move {
+            match BuildMongo::new(rocket.figment()).await {
+                Ok(mongo) => Ok(rocket.manage(mongo.build())),
+                Err(e) => {
+                    error!("{}", e);
+                    Err(rocket)
+                }
+            }
+        }))
+        .attach(AdHoc::try_on_ignite("Migrations", |rocket| async move {
+            let mongo = match rocket.state::<Mongo>() {
+                Some(mongo) if mongo.config.migrate_on_startup => mongo.clone(),
+                _ => return Ok(rocket),
+            };
+            match mongo.migrate().await {
+                Ok(applied) => {
+                    for record in applied {
+                        info!("applied migration {}", record.name);
+                        for warning in record.warnings {
+                            warn!("migration {}: {}", record.name, warning);
+                        }
+                    }
+                    Ok(rocket)
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    Err(rocket)
+                }
+            }
+        }))
+        .attach(AdHoc::on_liftoff("Trash purge", |rocket| Box::pin(async move {
+            let mongo = rocket.state::<Mongo>().unwrap().clone();
+            if let Some(days) = mongo.config.purge_after_days {
+                rocket::tokio::spawn(async move {
+                    let mut interval = rocket::tokio::time::interval(Duration::from_secs(60 * 60));
+                    loop {
+                        interval.tick().await;
+                        let before = chrono::Utc::now() - chrono::Duration::days(days);
+                        if let Err(e) = mongo.purge_deleted(before).await {
+                            error!("trash purge failed: {}", e);
+                        }
+                    }
+                });
+            }
+        })))
 }
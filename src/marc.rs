@@ -0,0 +1,482 @@
+use std::error::Error;
+use bson::{Bson, Document};
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use crate::config::{MarcMapping, MarcSubfield};
+use crate::import::ImportBook;
+
+/// end of a record in an ISO 2709 file
+pub const RECORD_TERMINATOR: u8 = 0x1D;
+/// end of the directory and of each field
+pub const FIELD_TERMINATOR: u8 = 0x1E;
+/// start of each subfield, followed by its code
+pub const SUBFIELD_DELIMITER: u8 = 0x1F;
+/// namespace of MARCXML documents
+pub const MARCXML_NAMESPACE: &str = "http://www.loc.gov/MARC21/slim";
+
+/// leader of the exported records: new language material, monograph, UTF-8
+const DEFAULT_LEADER: &str = "00000nam a2200000   4500";
+
+/// A field of a MARC record: a control field (tags `001` to `009`) or a data field with its indicators and subfields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    Control { tag: String, value: String },
+    Data { tag: String, indicators: [char; 2], subfields: Vec<(char, String)> },
+}
+
+impl Field {
+
+    ///
+    /// # tag
+    /// this function return the tag of the field
+    /// # Arguments
+    /// * `self` - the field
+    /// # Return
+    /// * `&str` - the tag
+    ///
+    pub fn tag(&self) -> &str {
+        match self {
+            Field::Control { tag, .. } | Field::Data { tag, .. } => tag,
+        }
+    }
+}
+
+/// A MARC21 bibliographic record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub leader: String,
+    pub fields: Vec<Field>,
+}
+
+impl Record {
+
+    ///
+    /// # values
+    /// this function return the values of a subfield, in the order of the record
+    /// # Arguments
+    /// * `self` - the record
+    /// * `subfield` - the tag and code of the subfield
+    /// # Return
+    /// * `Vec<&str>` - the values, empty when the record has none
+    ///
+    pub fn values(&self, subfield: &MarcSubfield) -> Vec<&str> {
+        self.fields.iter()
+            .filter_map(|field| match field {
+                Field::Data { tag, subfields, .. } if *tag == subfield.tag => Some(subfields),
+                _ => None,
+            })
+            .flatten()
+            .filter(|(code, _)| *code == subfield.code)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    ///
+    /// # to import book
+    /// this function read the fields of a book from the record, ISBD punctuation is trimmed
+    /// # Arguments
+    /// * `self` - the record
+    /// * `mapping` - the subfields holding the fields of a book
+    /// # Return
+    /// * `Result<ImportBook, String>` - the book, or an error when the record has no year
+    ///
+    pub fn to_import_book(&self, mapping: &MarcMapping) -> Result<ImportBook, String> {
+        let first = |subfield: &MarcSubfield| self.values(subfield).first().map(|value| trim_punctuation(value)).unwrap_or_default();
+        let optional = |subfield: &MarcSubfield| Some(first(subfield)).filter(|value| !value.is_empty());
+        let count = |subfield: &MarcSubfield| self.values(subfield).iter().find_map(|value| number(value));
+        let year = self.values(&mapping.year).iter()
+            .find_map(|value| year(value))
+            .ok_or_else(|| format!("No year in {}", mapping.year))?;
+        Ok(ImportBook {
+            title: first(&mapping.title),
+            author: first(&mapping.author),
+            year,
+            resume: self.values(&mapping.resume).join(" "),
+            min_age: None,
+            genres: self.values(&mapping.genres).iter().map(|value| trim_punctuation(value)).collect(),
+            // a qualifier may follow the ISBN, like `9780441013593 (pbk.)`
+            isbn: self.values(&mapping.isbn).first().and_then(|value| value.split_whitespace().next()).map(str::to_string),
+            publisher: optional(&mapping.publisher),
+            edition: optional(&mapping.edition),
+            language: optional(&mapping.language),
+            // the extent, like `412 p. ;`
+            pages: count(&mapping.pages),
+            format: None,
+            series: optional(&mapping.series),
+            // the volume designation, like `v. 3`
+            volume: count(&mapping.volume),
+            // the classification number, like `823/.912`, the prime marks are left out
+            call_number: optional(&mapping.call_number).map(|call_number| call_number.replace('/', "")),
+        })
+    }
+
+    ///
+    /// # from book
+    /// this function build the record of a book, its id is written in the `001` control field
+    /// # Arguments
+    /// * `document` - the book, with the names of its genres in `genres`
+    /// * `mapping` - the subfields holding the fields of a book
+    /// # Return
+    /// * `Record` - the record, its fields sorted by tag
+    ///
+    pub fn from_book(document: &Document, mapping: &MarcMapping) -> Record {
+        let mut fields = Vec::new();
+        if let Ok(id) = document.get_object_id("_id") {
+            fields.push(Field::Control { tag: "001".to_string(), value: id.to_hex() });
+        }
+        let year = match document.get("year") {
+            Some(Bson::Int32(year)) => Some(year.to_string()),
+            Some(Bson::Int64(year)) => Some(year.to_string()),
+            _ => None,
+        };
+        let text = |field: &str| document.get_str(field).ok().map(str::to_string);
+        let single = [
+            (&mapping.title, document.get_str("title").ok().map(str::to_string)),
+            (&mapping.author, document.get_str("author").ok().map(str::to_string)),
+            (&mapping.publisher, text("publisher")),
+            (&mapping.year, year),
+            (&mapping.resume, document.get_str("resume").ok().filter(|resume| !resume.is_empty()).map(str::to_string)),
+            (&mapping.isbn, document.get_str("isbn_13").ok().map(str::to_string)),
+            (&mapping.edition, text("edition")),
+            (&mapping.language, text("language")),
+            (&mapping.pages, document.get_i32("pages").ok().map(|pages| format!("{} p.", pages))),
+            (&mapping.series, text("series")),
+            (&mapping.volume, document.get_i32("volume").ok().map(|volume| volume.to_string())),
+            (&mapping.call_number, text("call_number")),
+        ];
+        for (subfield, value) in single {
+            let Some(value) = value else { continue };
+            // subfields mapped to the same tag share one field
+            match fields.iter_mut().find(|field| field.tag() == subfield.tag) {
+                Some(Field::Data { subfields, .. }) => subfields.push((subfield.code, value)),
+                _ => fields.push(Field::Data { tag: subfield.tag.clone(), indicators: [' ', ' '], subfields: vec![(subfield.code, value)] }),
+            }
+        }
+        for genre in document.get_array("genres").into_iter().flatten().filter_map(Bson::as_str) {
+            fields.push(Field::Data { tag: mapping.genres.tag.clone(), indicators: [' ', ' '], subfields: vec![(mapping.genres.code, genre.to_string())] });
+        }
+        fields.sort_by(|a, b| a.tag().cmp(b.tag()));
+        Record { leader: DEFAULT_LEADER.to_string(), fields }
+    }
+}
+
+///
+/// # trim punctuation
+/// this function remove the ISBD punctuation ending a MARC value, like the ` /` of a title followed by its statement of responsibility
+/// # Arguments
+/// * `value` - the value
+/// # Return
+/// * `String` - the trimmed value
+///
+fn trim_punctuation(value: &str) -> String {
+    value.trim().trim_end_matches([' ', '/', ':', ';', ',', '.', '=']).to_string()
+}
+
+///
+/// # year
+/// this function read the year of a date of publication, like `c1965.` or `[1862]`
+/// # Arguments
+/// * `value` - the date of publication
+/// # Return
+/// * `Option<i32>` - the first run of four digits
+///
+fn year(value: &str) -> Option<i32> {
+    value.split(|c: char| !c.is_ascii_digit())
+        .find(|digits| digits.len() == 4)
+        .and_then(|digits| digits.parse().ok())
+}
+
+///
+/// # number
+/// this function read the number of a MARC value, like the pages of `412 p.` or the volume of `v. 3`
+/// # Arguments
+/// * `value` - the value
+/// # Return
+/// * `Option<i32>` - the first run of digits
+///
+fn number(value: &str) -> Option<i32> {
+    value.split(|c: char| !c.is_ascii_digit())
+        .find(|digits| !digits.is_empty())
+        .and_then(|digits| digits.parse().ok())
+}
+
+///
+/// # read iso 2709
+/// this function read the records of a binary MARC21 file, a record that cannot be read is kept with its error
+/// # Arguments
+/// * `content` - the content of the file
+/// # Return
+/// * `Vec<Result<Record, String>>` - the records, in the order of the file
+///
+pub fn read_iso2709(content: &[u8]) -> Vec<Result<Record, String>> {
+    content.split(|byte| *byte == RECORD_TERMINATOR)
+        .filter(|record| !record.iter().all(u8::is_ascii_whitespace))
+        .map(read_record)
+        .collect()
+}
+
+///
+/// # read record
+/// this function read one ISO 2709 record: its leader, its directory and the fields the directory points to.
+/// a record whose leader/09 is not `a` is in MARC-8, which is not transcoded: it is only read when its fields are ASCII
+/// # Arguments
+/// * `record` - the bytes of the record, without its terminator
+/// # Return
+/// * `Result<Record, String>` - the record or why it cannot be read
+///
+fn read_record(record: &[u8]) -> Result<Record, String> {
+    let record = record.strip_prefix(b"\n").or_else(|| record.strip_prefix(b"\r\n")).unwrap_or(record);
+    if record.len() < 24 {
+        return Err("Record shorter than its leader".to_string());
+    }
+    if !record[..24].is_ascii() {
+        return Err("Leader is not ASCII".to_string());
+    }
+    let leader = String::from_utf8_lossy(&record[..24]);
+    let unicode = record[9] == b'a';
+    let base: usize = leader[12..17].parse().map_err(|_| format!("Invalid base address {}", &leader[12..17]))?;
+    if base < 25 || base > record.len() || record[base - 1] != FIELD_TERMINATOR {
+        return Err(format!("Invalid base address {}", base));
+    }
+    let directory = &record[24..base - 1];
+    if !directory.len().is_multiple_of(12) {
+        return Err("Directory length is not a multiple of 12".to_string());
+    }
+    let mut fields = Vec::new();
+    for entry in directory.chunks(12) {
+        if !entry.is_ascii() {
+            return Err("Directory is not ASCII".to_string());
+        }
+        let entry = String::from_utf8_lossy(entry);
+        let tag = &entry[..3];
+        let (length, start): (usize, usize) = match (entry[3..7].parse(), entry[7..12].parse()) {
+            (Ok(length), Ok(start)) => (length, start),
+            _ => return Err(format!("Invalid directory entry {}", entry)),
+        };
+        let data = record.get(base + start..base + start + length)
+            .ok_or_else(|| format!("Field {} is out of the record", tag))?;
+        let data = data.strip_suffix(&[FIELD_TERMINATOR]).unwrap_or(data);
+        let text = |bytes: &[u8]| match unicode {
+            // ASCII reads the same in MARC-8, its escape sequences and other characters are not supported
+            false if !bytes.is_ascii() || bytes.contains(&0x1B) => Err(format!("Field {} is MARC-8, not supported: only UTF-8 records (leader/09 a) are read", tag)),
+            _ => String::from_utf8(bytes.to_vec()).map_err(|_| format!("Field {} is not UTF-8", tag)),
+        };
+        if tag < "010" {
+            fields.push(Field::Control { tag: tag.to_string(), value: text(data)? });
+            continue;
+        }
+        if data.len() < 2 {
+            return Err(format!("Field {} has no indicators", tag));
+        }
+        let indicators = [data[0] as char, data[1] as char];
+        let mut subfields = Vec::new();
+        for subfield in data[2..].split(|byte| *byte == SUBFIELD_DELIMITER).skip(1) {
+            let Some((code, value)) = subfield.split_first() else { continue };
+            subfields.push((*code as char, text(value)?));
+        }
+        fields.push(Field::Data { tag: tag.to_string(), indicators, subfields });
+    }
+    Ok(Record { leader: leader.into_owned(), fields })
+}
+
+///
+/// # write iso 2709
+/// this function write a record in the binary MARC21 format, its leader gets the lengths of the record
+/// # Arguments
+/// * `record` - the record
+/// # Return
+/// * `Result<Vec<u8>, Box<dyn Error>>` - the bytes of the record, or an error when it is too long for the format
+///
+pub fn write_iso2709(record: &Record) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut directory = Vec::new();
+    let mut data = Vec::new();
+    for field in &record.fields {
+        let start = data.len();
+        match field {
+            Field::Control { value, .. } => data.extend_from_slice(value.as_bytes()),
+            Field::Data { indicators, subfields, .. } => {
+                data.extend(indicators.iter().map(|indicator| *indicator as u8));
+                for (code, value) in subfields {
+                    data.push(SUBFIELD_DELIMITER);
+                    data.push(*code as u8);
+                    data.extend_from_slice(value.as_bytes());
+                }
+            }
+        }
+        data.push(FIELD_TERMINATOR);
+        let length = data.len() - start;
+        if length > 9999 || start > 99999 {
+            return Err(format!("Field {} is too long for a MARC21 record", field.tag()).into());
+        }
+        directory.extend_from_slice(format!("{}{:04}{:05}", field.tag(), length, start).as_bytes());
+    }
+    let base = 24 + directory.len() + 1;
+    let length = base + data.len() + 1;
+    if length > 99999 {
+        return Err("Record is too long for MARC21".into());
+    }
+    let leader = format!("{:05}{}{:05}{}", length, &record.leader[5..12], base, &record.leader[17..24]);
+    let mut bytes = leader.into_bytes();
+    bytes.extend(directory);
+    bytes.push(FIELD_TERMINATOR);
+    bytes.extend(data);
+    bytes.push(RECORD_TERMINATOR);
+    Ok(bytes)
+}
+
+///
+/// # read marcxml
+/// this function read the records of a MARCXML document, with or without a `collection` element
+/// # Arguments
+/// * `content` - the document
+/// # Return
+/// * `Result<Vec<Result<Record, String>>, Box<dyn Error>>` - the records, or an error when the document is not well formed
+///
+pub fn read_marcxml(content: &str) -> Result<Vec<Result<Record, String>>, Box<dyn Error>> {
+    let mut reader = Reader::from_str(content);
+    let mut records = Vec::new();
+    let mut record: Option<Result<Record, String>> = None;
+    // the element whose text is being read, with the attributes of its field
+    let mut current: Option<(Vec<u8>, Option<String>)> = None;
+    let mut text = String::new();
+    loop {
+        let event = reader.read_event().map_err(|e| format!("Invalid MARCXML at byte {}: {}", reader.error_position(), e))?;
+        match event {
+            Event::Start(element) => {
+                let name = element.local_name().as_ref().to_vec();
+                text.clear();
+                match name.as_slice() {
+                    b"record" => record = Some(Ok(Record { leader: DEFAULT_LEADER.to_string(), fields: Vec::new() })),
+                    b"controlfield" => current = Some((name, attribute(&element, "tag"))),
+                    b"datafield" => {
+                        let field = match (attribute(&element, "tag"), attribute(&element, "ind1"), attribute(&element, "ind2")) {
+                            (Some(tag), ind1, ind2) => Ok(Field::Data {
+                                tag,
+                                indicators: [indicator(ind1), indicator(ind2)],
+                                subfields: Vec::new(),
+                            }),
+                            (None, _, _) => Err("Data field without tag".to_string()),
+                        };
+                        push(&mut record, field);
+                    }
+                    b"subfield" => current = Some((name, attribute(&element, "code"))),
+                    b"leader" => current = Some((name, None)),
+                    _ => {}
+                }
+            }
+            Event::Text(value) => text.push_str(&value.unescape()?),
+            Event::CData(value) => text.push_str(&String::from_utf8_lossy(&value.into_inner())),
+            Event::End(element) => {
+                let name = element.local_name().as_ref().to_vec();
+                if name == b"record" {
+                    if let Some(record) = record.take() {
+                        records.push(record);
+                    }
+                    continue;
+                }
+                let Some((element, attribute)) = current.take() else { continue };
+                if element != name {
+                    continue;
+                }
+                let Some(Ok(open)) = record.as_mut() else { continue };
+                match (name.as_slice(), attribute) {
+                    (b"leader", _) if text.len() == 24 && text.is_ascii() => open.leader = text.clone(),
+                    (b"leader", _) => record = Some(Err("Leader is not 24 ASCII characters".to_string())),
+                    (b"controlfield", Some(tag)) => open.fields.push(Field::Control { tag, value: text.clone() }),
+                    (b"controlfield", None) => record = Some(Err("Control field without tag".to_string())),
+                    (b"subfield", code) => {
+                        let code = code.and_then(|code| code.chars().next()).filter(char::is_ascii_graphic);
+                        match (code, open.fields.last_mut()) {
+                            (Some(code), Some(Field::Data { subfields, .. })) => subfields.push((code, text.clone())),
+                            (None, _) => record = Some(Err("Subfield without code".to_string())),
+                            _ => record = Some(Err("Subfield outside of a data field".to_string())),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(records)
+}
+
+///
+/// # push
+/// this function add a field to the record being read, unless the record already failed
+/// # Arguments
+/// * `record` - the record being read
+/// * `field` - the field or why it cannot be read
+///
+fn push(record: &mut Option<Result<Record, String>>, field: Result<Field, String>) {
+    match (record.as_mut(), field) {
+        (Some(Ok(open)), Ok(field)) => open.fields.push(field),
+        (Some(Ok(_)), Err(e)) => *record = Some(Err(e)),
+        _ => {}
+    }
+}
+
+///
+/// # attribute
+/// this function return the value of an attribute of an element
+/// # Arguments
+/// * `element` - the element
+/// * `name` - the name of the attribute
+/// # Return
+/// * `Option<String>` - the unescaped value, `None` when missing or invalid
+///
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element.try_get_attribute(name).ok().flatten()
+        .and_then(|attribute| attribute.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+///
+/// # indicator
+/// this function read an indicator, blank when missing or not ASCII
+/// # Arguments
+/// * `value` - the value of the attribute
+/// # Return
+/// * `char` - the indicator
+///
+fn indicator(value: Option<String>) -> char {
+    value.and_then(|value| value.chars().next()).filter(char::is_ascii).unwrap_or(' ')
+}
+
+/// start of a MARCXML document, before its records
+pub fn marcxml_header() -> String {
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<collection xmlns=\"{}\">\n", MARCXML_NAMESPACE)
+}
+
+/// end of a MARCXML document, after its records
+pub const MARCXML_FOOTER: &str = "</collection>\n";
+
+///
+/// # write marcxml
+/// this function write a record as a MARCXML `record` element, on one line
+/// # Arguments
+/// * `record` - the record
+/// # Return
+/// * `String` - the element
+///
+pub fn write_marcxml(record: &Record) -> String {
+    let mut xml = format!("<record><leader>{}</leader>", escape(record.leader.as_str()));
+    for field in &record.fields {
+        match field {
+            Field::Control { tag, value } => {
+                xml.push_str(&format!("<controlfield tag=\"{}\">{}</controlfield>", escape(tag.as_str()), escape(value.as_str())));
+            }
+            Field::Data { tag, indicators, subfields } => {
+                xml.push_str(&format!("<datafield tag=\"{}\" ind1=\"{}\" ind2=\"{}\">", escape(tag.as_str()), indicators[0], indicators[1]));
+                for (code, value) in subfields {
+                    xml.push_str(&format!("<subfield code=\"{}\">{}</subfield>", code, escape(value.as_str())));
+                }
+                xml.push_str("</datafield>");
+            }
+        }
+    }
+    xml.push_str("</record>\n");
+    xml
+}
@@ -0,0 +1,110 @@
+use std::sync::LazyLock;
+use std::time::Instant;
+use prometheus::{Encoder, HistogramTimer, HistogramVec, IntCounterVec, IntGauge, TextEncoder};
+use prometheus::{histogram_opts, opts, register_histogram_vec, register_int_counter_vec, register_int_gauge};
+use rocket::{Data, Request, Response};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use serde::{Serialize, Deserialize};
+use crate::mongo::Mongo;
+use rocket::get;
+use rocket_okapi::openapi;
+use crate::error::ApiError;
+
+static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| register_int_counter_vec!(
+    opts!("bibliotheca_http_requests_total", "Requests answered, by route, method and status"),
+    &["route", "method", "status"]
+).unwrap());
+
+static HTTP_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| register_histogram_vec!(
+    histogram_opts!("bibliotheca_http_request_duration_seconds", "Time to answer a request, by route and method"),
+    &["route", "method"]
+).unwrap());
+
+static DB_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| register_histogram_vec!(
+    histogram_opts!("bibliotheca_db_operation_duration_seconds", "Time spent in a database method, by method"),
+    &["method"]
+).unwrap());
+
+static BOOKS_ON_LOAN: LazyLock<IntGauge> = LazyLock::new(|| register_int_gauge!(
+    "bibliotheca_books_on_loan", "Books currently borrowed"
+).unwrap());
+
+static OVERDUE_LOANS: LazyLock<IntGauge> = LazyLock::new(|| register_int_gauge!(
+    "bibliotheca_overdue_loans", "Borrowed books past their due date"
+).unwrap());
+
+static ACTIVE_USERS: LazyLock<IntGauge> = LazyLock::new(|| register_int_gauge!(
+    "bibliotheca_active_users", "Users not in the trash"
+).unwrap());
+
+static COMMENTS_LAST_DAY: LazyLock<IntGauge> = LazyLock::new(|| register_int_gauge!(
+    "bibliotheca_comments_last_day", "Comments posted over the last 24 hours"
+).unwrap());
+
+/// Counts of the library shown on the dashboards, refreshed on every scrape.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryStats {
+    pub books_on_loan: u64,
+    pub overdue_loans: u64,
+    pub active_users: u64,
+    pub comments_last_day: u64,
+}
+
+///
+/// # db timer
+/// this function start timing a database method, the time is recorded when the timer is dropped
+/// # Arguments
+/// * `method` - the name of the `Mongo` method
+/// # Return
+/// * `HistogramTimer` - the running timer
+///
+pub fn db_timer(method: &str) -> HistogramTimer {
+    DB_DURATION.with_label_values(&[method]).start_timer()
+}
+
+/// start time of a request, kept in the request local cache
+struct RequestStart(Instant);
+
+/// Fairing counting the requests and timing them per route.
+pub struct RequestMetrics;
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info { name: "Request metrics", kind: Kind::Request | Kind::Response }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let start = request.local_cache(|| RequestStart(Instant::now()));
+        // unmatched requests share one label, so unknown paths cannot grow the series
+        let route = request.route().map(|route| route.uri.to_string()).unwrap_or_else(|| "unmatched".to_string());
+        let method = request.method().as_str();
+        let status = response.status().code.to_string();
+        HTTP_REQUESTS.with_label_values(&[&route, method, &status]).inc();
+        HTTP_DURATION.with_label_values(&[&route, method]).observe(start.0.elapsed().as_secs_f64());
+    }
+}
+
+/// metrics in the Prometheus text format, the library gauges are refreshed from the database
+/// and keep their last value while it is unreachable
+#[openapi(tag = "Monitoring")]
+#[get("/metrics")]
+pub async fn get_metrics(db: Mongo) -> Result<(ContentType, String), ApiError> {
+    match db.get_library_stats().await {
+        Ok(stats) => {
+            BOOKS_ON_LOAN.set(stats.books_on_loan as i64);
+            OVERDUE_LOANS.set(stats.overdue_loans as i64);
+            ACTIVE_USERS.set(stats.active_users as i64);
+            COMMENTS_LAST_DAY.set(stats.comments_last_day as i64);
+        }
+        Err(e) => rocket::warn!("library stats not refreshed: {}", e),
+    }
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer).map_err(|e| ApiError(e.into()))?;
+    Ok((ContentType::new("text", "plain").with_params(("version", "0.0.4")), String::from_utf8(buffer).map_err(|e| ApiError(e.into()))?))
+}
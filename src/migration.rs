@@ -0,0 +1,374 @@
+use std::error::Error;
+use bson::{doc, Document};
+use mongodb::{Collection, Database};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::FindOptions;
+use rocket::futures::future::{BoxFuture, FutureExt};
+use rocket::futures::StreamExt;
+use serde::{Serialize, Deserialize};
+use chrono::NaiveDate;
+use crate::date::to_bson_date;
+use crate::author::{name_key, name_keys};
+use crate::config::Collections;
+
+/// the function applying a migration to the database, it returns warnings about the documents it left unchanged
+pub type MigrationFn = for<'a> fn(&'a Database, &'a Collections) -> BoxFuture<'a, Result<Vec<String>, Box<dyn Error>>>;
+
+/// A versioned change of the documents in database.
+/// Migrations must be idempotent: a migration interrupted half way is run again from the start.
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub up: MigrationFn,
+}
+
+/// Record of a migration in the `_migrations` collection, `finished_at` is only set once it completed.
+/// An unfinished record is the lock of the run applying the migration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationRecord {
+    #[serde(rename = "_id")]
+    pub version: i32,
+    pub name: String,
+    pub started_at: bson::DateTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<bson::DateTime>,
+    /// the documents the migration could not convert, to be fixed by hand
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// a migration unfinished this long after it started is taken over, the run applying it is assumed dead
+const STALE_AFTER_MS: i64 = 60 * 60 * 1000;
+
+/// every migration, in the order they are applied
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "birth_date_to_date", up: birth_date_to_date },
+    Migration { version: 2, name: "gender_id_to_genre_ids", up: gender_id_to_genre_ids },
+    Migration { version: 3, name: "backfill_timestamps", up: backfill_timestamps },
+    Migration { version: 4, name: "backfill_versions", up: backfill_versions },
+    Migration { version: 5, name: "link_book_authors", up: link_book_authors },
+    Migration { version: 6, name: "backfill_loans", up: backfill_loans },
+    Migration { version: 7, name: "backfill_author_name_keys", up: backfill_author_name_keys },
+];
+
+/// days of the loans backfilled by `backfill_loans`, the default loan period as the configured one is not known here
+const BACKFILL_LOAN_DAYS: i64 = 21;
+
+///
+/// # run
+/// this function apply the migrations not yet completed, in order, and return their records or an error
+/// a failed migration stops the run, it is retried on the next run.
+/// a migration being applied by another run stops this one with an error
+/// # Arguments
+/// * `db` - the database to migrate
+/// * `collections` - the names of the collections
+/// # Return
+/// * `Result<Vec<MigrationRecord>, Box<dyn Error>>` - the records of the applied migrations or an error
+///
+pub async fn run(db: &Database, collections: &Collections) -> Result<Vec<MigrationRecord>, Box<dyn Error>> {
+    let collection: Collection<Document> = db.collection(&collections.migrations);
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS {
+        let mut record = MigrationRecord {
+            version: migration.version,
+            name: migration.name.to_string(),
+            started_at: bson::DateTime::now(),
+            finished_at: None,
+            warnings: Vec::new(),
+        };
+        if !lock(&collection, &record).await? {
+            continue;
+        }
+        let result = (migration.up)(db, collections).await
+            .map_err(|e| format!("Migration {} {} failed: {}", migration.version, migration.name, e));
+        record.warnings = match result {
+            Ok(warnings) => warnings,
+            Err(message) => {
+                // release the lock for the next run to retry
+                collection.delete_one(doc! {"_id": migration.version, "started_at": record.started_at}, None).await?;
+                return Err(message.into());
+            }
+        };
+        record.finished_at = Some(bson::DateTime::now());
+        collection.update_one(
+            doc! {"_id": migration.version, "started_at": record.started_at},
+            doc! {"$set": {"finished_at": record.finished_at, "warnings": record.warnings.clone()}},
+            None,
+        ).await?;
+        applied.push(record);
+    }
+    Ok(applied)
+}
+
+///
+/// # lock
+/// this function claim a migration for this run by inserting its record, `_id` being unique only one run succeeds
+/// the record of a run that died is taken over once it is stale
+/// # Arguments
+/// * `collection` - the `_migrations` collection
+/// * `record` - the record of the migration started by this run
+/// # Return
+/// * `Result<bool, Box<dyn Error>>` - true when this run applies the migration, false when it is already finished,
+///   an error when another run is applying it
+///
+async fn lock(collection: &Collection<Document>, record: &MigrationRecord) -> Result<bool, Box<dyn Error>> {
+    let error = match collection.insert_one(bson::to_document(record)?, None).await {
+        Ok(_) => return Ok(true),
+        Err(e) => e,
+    };
+    if !matches!(error.kind.as_ref(), ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == 11000) {
+        return Err(error.into());
+    }
+    let existing: MigrationRecord = match collection.find_one(doc! {"_id": record.version}, None).await? {
+        Some(existing) => bson::from_document(existing)?,
+        None => return Err(format!("Migration {} {} was released by another run, run again", record.version, record.name).into()),
+    };
+    if existing.finished_at.is_some() {
+        return Ok(false);
+    }
+    if record.started_at.timestamp_millis() - existing.started_at.timestamp_millis() > STALE_AFTER_MS {
+        let taken = collection.update_one(
+            doc! {"_id": record.version, "started_at": existing.started_at, "finished_at": null},
+            doc! {"$set": {"started_at": record.started_at}},
+            None,
+        ).await?;
+        if taken.modified_count == 1 {
+            return Ok(true);
+        }
+    }
+    Err(format!("Migration {} {} is being applied by another run since {}", record.version, record.name, existing.started_at).into())
+}
+
+///
+/// # status
+/// this function return the record of every migration started on the database
+/// # Arguments
+/// * `db` - the database
+/// * `collections` - the names of the collections
+/// # Return
+/// * `Result<Vec<MigrationRecord>, Box<dyn Error>>` - the records, by version, or an error
+///
+pub async fn status(db: &Database, collections: &Collections) -> Result<Vec<MigrationRecord>, Box<dyn Error>> {
+    let collection: Collection<Document> = db.collection(&collections.migrations);
+    let options = FindOptions::builder().sort(doc! {"_id": 1}).build();
+    let mut cursor = collection.find(doc! {}, options).await?;
+    let mut records = Vec::new();
+    while let Some(result) = cursor.next().await {
+        let record = bson::from_bson(bson::Bson::Document(result?))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+///
+/// # birth date to date
+/// this function convert the `birth_date` of users stored as a `YYYY-MM-DD` string into a date,
+/// an invalid date is left as is and reported in the warnings
+/// # Arguments
+/// * `db` - the database to migrate
+/// * `collections` - the names of the collections
+/// # Return
+/// * `BoxFuture<Result<Vec<String>, Box<dyn Error>>>` - the users with an invalid date or an error
+///
+fn birth_date_to_date<'a>(db: &'a Database, collections: &'a Collections) -> BoxFuture<'a, Result<Vec<String>, Box<dyn Error>>> {
+    async move {
+        let collection: Collection<Document> = db.collection(&collections.users);
+        let mut cursor = collection.find(doc! {"birth_date": {"$type": "string"}}, None).await?;
+        let mut invalid = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let user = result?;
+            let id = user.get_object_id("_id")?;
+            match NaiveDate::parse_from_str(user.get_str("birth_date")?, "%Y-%m-%d") {
+                Ok(date) => {
+                    collection.update_one(doc! {"_id": id}, doc! {"$set": {"birth_date": to_bson_date(date)}}, None).await?;
+                }
+                Err(_) => invalid.push(format!("user {} has an invalid birth_date {:?}", id.to_hex(), user.get_str("birth_date")?)),
+            }
+        }
+        Ok(invalid)
+    }.boxed()
+}
+
+///
+/// # gender id to genre ids
+/// this function replace the single `gender_id` of books by a `genre_ids` array,
+/// the placeholder id `000000000000000000000000` becomes an empty array
+/// # Arguments
+/// * `db` - the database to migrate
+/// * `collections` - the names of the collections
+/// # Return
+/// * `BoxFuture<Result<Vec<String>, Box<dyn Error>>>` - no warnings or an error
+///
+fn gender_id_to_genre_ids<'a>(db: &'a Database, collections: &'a Collections) -> BoxFuture<'a, Result<Vec<String>, Box<dyn Error>>> {
+    async move {
+        let collection: Collection<Document> = db.collection(&collections.books);
+        let pipeline = vec![
+            doc! {
+                "$set": {
+                    "genre_ids": {
+                        "$cond": [
+                            { "$in": ["$gender_id", [null, "", "000000000000000000000000"]] },
+                            [],
+                            ["$gender_id"]
+                        ]
+                    }
+                }
+            },
+            doc! { "$unset": "gender_id" },
+        ];
+        collection.update_many(doc! {"gender_id": {"$exists": true}}, pipeline, None).await?;
+        Ok(Vec::new())
+    }.boxed()
+}
+
+///
+/// # backfill timestamps
+/// this function set the missing `created_at` to the creation time of the document id
+/// and the missing `updated_at` to `created_at`
+/// # Arguments
+/// * `db` - the database to migrate
+/// * `collections` - the names of the collections
+/// # Return
+/// * `BoxFuture<Result<Vec<String>, Box<dyn Error>>>` - no warnings or an error
+///
+fn backfill_timestamps<'a>(db: &'a Database, collections: &'a Collections) -> BoxFuture<'a, Result<Vec<String>, Box<dyn Error>>> {
+    async move {
+        for name in [&collections.books, &collections.users, &collections.comments, &collections.genres] {
+            let collection: Collection<Document> = db.collection(name);
+            let pipeline = vec![
+                doc! { "$set": { "created_at": { "$ifNull": ["$created_at", { "$toDate": "$_id" }] } } },
+                doc! { "$set": { "updated_at": { "$ifNull": ["$updated_at", "$created_at"] } } },
+            ];
+            let filter = doc! {"$or": [{"created_at": {"$exists": false}}, {"updated_at": {"$exists": false}}]};
+            collection.update_many(filter, pipeline, None).await?;
+        }
+        Ok(Vec::new())
+    }.boxed()
+}
+
+///
+/// # backfill versions
+/// this function set the version of the books and users written before versioning to 1
+/// # Arguments
+/// * `db` - the database to migrate
+/// * `collections` - the names of the collections
+/// # Return
+/// * `BoxFuture<Result<Vec<String>, Box<dyn Error>>>` - no warnings or an error
+///
+fn backfill_versions<'a>(db: &'a Database, collections: &'a Collections) -> BoxFuture<'a, Result<Vec<String>, Box<dyn Error>>> {
+    async move {
+        for name in [&collections.books, &collections.users] {
+            let collection: Collection<Document> = db.collection(name);
+            collection.update_many(doc! {"version": {"$exists": false}}, doc! {"$set": {"version": 1}}, None).await?;
+        }
+        Ok(Vec::new())
+    }.boxed()
+}
+
+///
+/// # link book authors
+/// this function credit the books written before authors existed to the author named by their `author`,
+/// an author is created for a name that is neither the name nor an alias of an existing one, ignoring case, punctuation and spaces
+/// # Arguments
+/// * `db` - the database to migrate
+/// * `collections` - the names of the collections
+/// # Return
+/// * `BoxFuture<Result<Vec<String>, Box<dyn Error>>>` - no warnings or an error
+///
+fn link_book_authors<'a>(db: &'a Database, collections: &'a Collections) -> BoxFuture<'a, Result<Vec<String>, Box<dyn Error>>> {
+    async move {
+        let collection: Collection<Document> = db.collection(&collections.books);
+        let collection_author: Collection<Document> = db.collection(&collections.authors);
+        let mut authors = std::collections::HashMap::new();
+        let mut cursor = collection_author.find(doc! {}, None).await?;
+        while let Some(result) = cursor.next().await {
+            let author = result?;
+            let aliases: Vec<String> = author.get_array("aliases").map(|aliases| aliases.iter().filter_map(bson::Bson::as_str).map(str::to_string).collect()).unwrap_or_default();
+            for key in name_keys(author.get_str("name")?, &aliases) {
+                authors.entry(key).or_insert(author.get_object_id("_id")?);
+            }
+        }
+        let names = collection.distinct("author", doc! {"authors": {"$exists": false}}, None).await?;
+        for name in names.iter().filter_map(bson::Bson::as_str).filter(|name| !name.trim().is_empty()) {
+            let author_id = match authors.get(&name_key(name)) {
+                Some(author_id) => *author_id,
+                None => {
+                    let now = bson::DateTime::now();
+                    let author = doc! {"name": name, "aliases": [], "name_keys": [name_key(name)], "birth_year": null, "death_year": null, "created_at": now, "updated_at": now};
+                    let result = collection_author.insert_one(author, None).await?;
+                    let author_id = result.inserted_id.as_object_id().ok_or("author id is not an ObjectId")?;
+                    authors.insert(name_key(name), author_id);
+                    author_id
+                }
+            };
+            let authors = vec![doc! {"author_id": author_id.to_hex(), "role": "author"}];
+            collection.update_many(doc! {"author": name, "authors": {"$exists": false}}, doc! {"$set": {"authors": authors}}, None).await?;
+        }
+        collection.update_many(doc! {"authors": {"$exists": false}}, doc! {"$set": {"authors": []}}, None).await?;
+        Ok(Vec::new())
+    }.boxed()
+}
+
+///
+/// # backfill loans
+/// this function record the loan of the books borrowed before loans were recorded, from the `borrowed_books` of users.
+/// the loan is taken to start at the last update of the book, the borrow being the last change made to it
+/// # Arguments
+/// * `db` - the database to migrate
+/// * `collections` - the names of the collections
+/// # Return
+/// * `BoxFuture<Result<Vec<String>, Box<dyn Error>>>` - the borrowed book ids that are not valid, or an error
+///
+fn backfill_loans<'a>(db: &'a Database, collections: &'a Collections) -> BoxFuture<'a, Result<Vec<String>, Box<dyn Error>>> {
+    async move {
+        let collection: Collection<Document> = db.collection(&collections.books);
+        let collection_user: Collection<Document> = db.collection(&collections.users);
+        let mut cursor = collection_user.find(doc! {"borrowed_books.0": {"$exists": true}, "deleted": null}, None).await?;
+        let mut invalid = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let user = result?;
+            let user_id = user.get_object_id("_id")?.to_hex();
+            for book_id in user.get_array("borrowed_books")?.iter().filter_map(bson::Bson::as_str) {
+                let Ok(oid) = bson::oid::ObjectId::parse_str(book_id) else {
+                    invalid.push(format!("user {} borrowed an invalid book id {:?}", user_id, book_id));
+                    continue;
+                };
+                let pipeline = vec![doc! {
+                    "$set": {
+                        "availability": false,
+                        "loan": {
+                            "user_id": &user_id,
+                            "at": "$updated_at",
+                            "due": { "$add": ["$updated_at", BACKFILL_LOAN_DAYS * 24 * 60 * 60 * 1000] },
+                        },
+                    }
+                }];
+                collection.update_one(doc! {"_id": oid, "loan": {"$exists": false}}, pipeline, None).await?;
+            }
+        }
+        Ok(invalid)
+    }.boxed()
+}
+
+///
+/// # backfill author name keys
+/// this function set the `name_keys` the authors are found by, from their name and aliases
+/// # Arguments
+/// * `db` - the database to migrate
+/// * `collections` - the names of the collections
+/// # Return
+/// * `BoxFuture<Result<Vec<String>, Box<dyn Error>>>` - no warnings or an error
+///
+fn backfill_author_name_keys<'a>(db: &'a Database, collections: &'a Collections) -> BoxFuture<'a, Result<Vec<String>, Box<dyn Error>>> {
+    async move {
+        let collection: Collection<Document> = db.collection(&collections.authors);
+        let mut cursor = collection.find(doc! {"name_keys": {"$exists": false}}, None).await?;
+        while let Some(result) = cursor.next().await {
+            let author = result?;
+            let aliases: Vec<String> = author.get_array("aliases").map(|aliases| aliases.iter().filter_map(bson::Bson::as_str).map(str::to_string).collect()).unwrap_or_default();
+            let keys = name_keys(author.get_str("name")?, &aliases);
+            collection.update_one(doc! {"_id": author.get_object_id("_id")?}, doc! {"$set": {"name_keys": keys}}, None).await?;
+        }
+        Ok(Vec::new())
+    }.boxed()
+}
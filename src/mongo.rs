@@ -1,30 +1,139 @@
-use std::collections::HashMap;
-use mongodb::{Client, Collection, options::{ClientOptions, ResolverConfig}};
-use std::env;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+use mongodb::{Client, Collection, Cursor, options::{ClientOptions, ReadConcern}};
+use rocket::figment::Figment;
+use serde::Deserialize;
 use std::error::Error;
 use bson::{doc, Document};
+use mongodb::{Database, IndexModel};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, IndexOptions, ReturnDocument};
 use rocket::futures::StreamExt;
-use crate::book::{Book, NewBook};
-use crate::comment::{Comment, NewComment};
-use crate::genre::Genre;
-use crate::user::{NewUser, User};
-use crate::{OperatorRating, Value};
-
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use crate::request_log::RequestId;
+use crate::book::{unlink_borrowers, Book, Loan, NewBook, Series, Shelf, ShelfBook, UpdateBook};
+use crate::comment::{moderation_queue, page_skip, Comment, CommentFlag, CommentSort, CommentStatus, CommentThread, ModerationAction, ModerationKind, NewComment};
+use crate::genre::{Genre, NewGenre};
+use crate::subject::{NewSubject, Subject};
+use crate::work::{average_rating, Edition, NewWork, Work, WorkHit, WorkPage};
+use crate::author::{life, name_key, name_keys, Author, AuthorPage, AuthorRole, BibliographyEntry, BookAuthor, NewAuthor};
+use crate::user::{release_books, unlink_comments, NewUser, UpdateUser, User, UserCommentPolicy, DELETED_USER_ID};
+use crate::date::{to_bson_date, to_stored_bson, to_stored_document};
+use crate::config::{words, Collections, MarcMapping, ReadConcernLevel, Resolver, WriteConcernConfig};
+use crate::error::HttpError;
+use crate::patch::{changed_fields, Patch};
+use crate::migration::{self, MigrationRecord};
+use crate::metrics::{db_timer, LibraryStats};
+use crate::validation::{parse_id, validate, FieldErrors};
+use crate::{call_number, isbn};
+use crate::import::{ImportFailure, ImportReport, ImportRow, RowError, RowWarning, IMPORT_BATCH_SIZE};
+use crate::export::{ExportEntity, ExportFilter};
+use crate::audit::{diff, AuditAction, AuditEntity, AuditEntry, AuditFilter};
+use crate::{Deletion, OperatorRating, SortOrder, TimeFilter, TimestampField, Value};
+
+#[derive(Clone)]
 pub struct Config {
     pub url: String,
     pub db_name: String,
-    pub collection_name: String,
+    pub collections: Collections,
+    pub resolver: Resolver,
+    pub min_pool_size: Option<u32>,
+    pub max_pool_size: Option<u32>,
+    pub connect_timeout_ms: Option<u64>,
+    pub server_selection_timeout_ms: Option<u64>,
+    pub read_concern: Option<ReadConcernLevel>,
+    pub write_concern: WriteConcernConfig,
+    pub marc: MarcMapping,
+    pub moderation_words: Vec<String>,
+    pub purge_after_days: Option<i64>,
+    pub loan_days: i64,
+    pub migrate_on_startup: bool,
+    pub connect_retries: u32,
+    pub connect_backoff_ms: u64,
 }
 
+/// Database settings, read from the `mongo` key of the configuration, see `config::figment`.
+#[derive(Deserialize)]
 pub struct BuildConfig {
     pub url: String,
     pub db_name: String,
-    pub collection_name: String,
+    #[serde(default)]
+    pub collections: Collections,
+    #[serde(default)]
+    pub resolver: Resolver,
+    pub min_pool_size: Option<u32>,
+    pub max_pool_size: Option<u32>,
+    pub connect_timeout_ms: Option<u64>,
+    pub server_selection_timeout_ms: Option<u64>,
+    pub read_concern: Option<ReadConcernLevel>,
+    #[serde(default)]
+    pub write_concern: WriteConcernConfig,
+    /// subfields of the MARC records holding the fields of a book
+    #[serde(default)]
+    pub marc: MarcMapping,
+    #[serde(default, deserialize_with = "words")]
+    pub moderation_words: Vec<String>,
+    pub purge_after_days: Option<i64>,
+    /// days a book is lent for before the loan is overdue
+    #[serde(default = "default_loan_days")]
+    pub loan_days: i64,
+    #[serde(default = "default_migrate_on_startup")]
+    pub migrate_on_startup: bool,
+    /// attempts to reach the database at startup after the first one
+    #[serde(default = "default_connect_retries")]
+    pub connect_retries: u32,
+    /// delay before the first retry, doubled after each attempt
+    #[serde(default = "default_connect_backoff_ms")]
+    pub connect_backoff_ms: u64,
+}
+
+fn default_loan_days() -> i64 {
+    21
+}
+
+fn default_migrate_on_startup() -> bool {
+    true
+}
+
+fn default_connect_retries() -> u32 {
+    5
+}
+
+fn default_connect_backoff_ms() -> u64 {
+    500
 }
 
+/// longest delay between two connection attempts at startup
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
 pub struct Mongo {
     pub config: Config,
     pub client: Client,
+    /// id of the request the database is used for, recorded in the audit entries
+    pub request_id: Option<String>,
+}
+
+/// The managed `Mongo` state, tagged with the id of the request.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Mongo {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.rocket().state::<Mongo>() {
+            Some(mongo) => Outcome::Success(mongo.for_request(&RequestId::of(request).0)),
+            None => Outcome::Error((Status::ServiceUnavailable, "Database not configured")),
+        }
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for Mongo {
+    fn from_request_input(_: &mut OpenApiGenerator, _: String, _: bool) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
 }
 
 
@@ -45,27 +154,62 @@ impl BuildConfig {
         Config {
             url: self.url,
             db_name: self.db_name,
-            collection_name: self.collection_name,
+            collections: self.collections,
+            resolver: self.resolver,
+            min_pool_size: self.min_pool_size,
+            max_pool_size: self.max_pool_size,
+            connect_timeout_ms: self.connect_timeout_ms,
+            server_selection_timeout_ms: self.server_selection_timeout_ms,
+            read_concern: self.read_concern,
+            write_concern: self.write_concern,
+            marc: self.marc,
+            moderation_words: self.moderation_words,
+            purge_after_days: self.purge_after_days,
+            loan_days: self.loan_days,
+            migrate_on_startup: self.migrate_on_startup,
+            connect_retries: self.connect_retries,
+            connect_backoff_ms: self.connect_backoff_ms,
         }
     }
 
     ///
     /// # new
-    /// this function create a new build config struct and return a build config struct or an error
+    /// this function read the build config struct from the `mongo` key of the configuration and return it or an error
+    /// # Arguments
+    /// * `figment` - the configuration
     /// # Return
-    /// * `Result<Self, Box<dyn Error>>` - a build config struct or an error
+    /// * `Result<Self, Box<dyn Error>>` - a build config struct or an error naming the invalid setting
     ///
-    fn new() -> Result<Self, Box<dyn Error>>
+    fn new(figment: &Figment) -> Result<Self, Box<dyn Error>>
     {
-        let url = env::var("URL_MONGO")?;
-        let db_name = env::var("DB_NAME")?;
-        let collection_name = env::var("COLLECTION_NAME")?;
-
-        Ok(BuildConfig {
-            url,
-            db_name,
-            collection_name,
-        })
+        let config: BuildConfig = figment.extract_inner("mongo")
+            .map_err(|e| format!("Invalid database configuration: {}", e))?;
+        if config.min_pool_size.unwrap_or(0) > config.max_pool_size.unwrap_or(u32::MAX) {
+            return Err("Invalid database configuration: min_pool_size is greater than max_pool_size".into());
+        }
+        Ok(config)
+    }
+
+    ///
+    /// # client options
+    /// this function parse the url and apply the configured settings on top of it
+    /// # Arguments
+    /// * `self` - the build config struct
+    /// # Return
+    /// * `Result<ClientOptions, Box<dyn Error>>` - the client options or an error
+    ///
+    async fn client_options(&self) -> Result<ClientOptions, Box<dyn Error>> {
+        let mut options = match self.resolver.resolver_config() {
+            Some(resolver) => ClientOptions::parse_with_resolver_config(&self.url, resolver).await,
+            None => ClientOptions::parse(&self.url).await,
+        }.map_err(|e| format!("Invalid database url: {}", e))?;
+        options.min_pool_size = self.min_pool_size.or(options.min_pool_size);
+        options.max_pool_size = self.max_pool_size.or(options.max_pool_size);
+        options.connect_timeout = self.connect_timeout_ms.map(Duration::from_millis).or(options.connect_timeout);
+        options.server_selection_timeout = self.server_selection_timeout_ms.map(Duration::from_millis).or(options.server_selection_timeout);
+        options.read_concern = self.read_concern.map(ReadConcern::from).or(options.read_concern);
+        options.write_concern = self.write_concern.write_concern().or(options.write_concern);
+        Ok(options)
     }
 }
 
@@ -83,27 +227,280 @@ impl BuildMongo {
         Mongo {
             config: self.config.build(),
             client: self.client,
+            request_id: None,
         }
     }
 
     ///
     /// # new
-    /// this function create a new mongo struct and return a mongo struct or an error
+    /// this function create a new mongo struct from the configuration and return a mongo struct or an error
+    /// # Arguments
+    /// * `figment` - the configuration
     /// # Return
     /// * `Result<BuildMongo, Box<dyn Error>>` - a mongo struct or an error
     ///
-    pub async fn new() -> Result<BuildMongo, Box<dyn Error>>
+    pub async fn new(figment: &Figment) -> Result<BuildMongo, Box<dyn Error>>
     {
-        let config = BuildConfig::new()?;
-        let options = ClientOptions::parse_with_resolver_config(&config.url, ResolverConfig::cloudflare()).await?;
+        let config = BuildConfig::new(figment)?;
+        let options = config.client_options().await?;
         let client = Client::with_options(options)?;
+        let db = client.database(&config.db_name);
+
+        let mut delay = Duration::from_millis(config.connect_backoff_ms);
+        let mut attempt = 0;
+        while let Err(e) = db.run_command(doc! {"ping": 1}, None).await {
+            if attempt == config.connect_retries {
+                return Err(format!("Database unreachable after {} attempts: {}", attempt + 1, e).into());
+            }
+            attempt += 1;
+            rocket::warn!("database unreachable, retry {}/{} in {:?}: {}", attempt, config.connect_retries, delay, e);
+            rocket::tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_CONNECT_BACKOFF);
+        }
+        ensure_indexes(&db, &config.collections).await?;
 
         Ok(BuildMongo { config, client })
     }
 }
 
+///
+/// # indexes
+/// this function return the indexes declared on each collection
+/// # Arguments
+/// * `collections` - the names of the collections
+/// # Return
+/// * `Vec<(&str, IndexModel)>` - the collection names and their indexes
+///
+fn indexes(collections: &Collections) -> Vec<(&str, IndexModel)> {
+    let index = |keys: Document, name: &str, unique: bool| {
+        let options = IndexOptions::builder().name(name.to_string()).unique(unique).build();
+        IndexModel::builder().keys(keys).options(options).build()
+    };
+    // unique among the documents matching the filter, e.g. the books that have the field
+    let partial = |keys: Document, name: &str, filter: Document| {
+        let options = IndexOptions::builder().name(name.to_string()).unique(true).partial_filter_expression(filter).build();
+        IndexModel::builder().keys(keys).options(options).build()
+    };
+    vec![
+        // deleted users each have their own `deleted.at`, the others share a null one: the email of a deleted user is free
+        (&collections.users, index(doc! {"email": 1, "deleted.at": 1}, "email_active_unique", true)),
+        (&collections.genres, index(doc! {"name": 1}, "name_unique", true)),
+        (&collections.comments, index(doc! {"book_id": 1}, "book_id", false)),
+        (&collections.comments, index(doc! {"user_id": 1}, "user_id", false)),
+        (&collections.books, index(doc! {"author": 1, "title": 1}, "author_title", false)),
+        (&collections.books, index(doc! {"title": 1}, "title", false)),
+        (&collections.books, index(doc! {"authors.author_id": 1}, "authors_author_id", false)),
+        (&collections.books, index(doc! {"series": 1, "volume": 1}, "series_volume", false)),
+        (&collections.books, index(doc! {"work_id": 1}, "work_id", false)),
+        (&collections.books, index(doc! {"call_number": 1}, "call_number", false)),
+        (&collections.books, index(doc! {"subject_ids": 1}, "subject_ids", false)),
+        (&collections.works, index(doc! {"title": 1}, "title", false)),
+        (&collections.subjects, index(doc! {"heading": 1}, "heading_unique", true)),
+        (&collections.authors, index(doc! {"name": 1}, "name", false)),
+        (&collections.authors, index(doc! {"name_keys": 1}, "name_keys", false)),
+        // like the email of users, the ISBN of a book in the trash is free
+        (&collections.books, partial(doc! {"isbn_13": 1, "deleted.at": 1}, "isbn_13_active_unique", doc! {"isbn_13": {"$type": "string"}})),
+    ]
+}
+
+///
+/// # replaced indexes
+/// this function return the indexes that were declared before and replaced since, dropped before creating the declared ones
+/// # Arguments
+/// * `collections` - the names of the collections
+/// # Return
+/// * `Vec<(&str, &str)>` - the collection names and the names of their replaced indexes
+///
+fn replaced_indexes(collections: &Collections) -> Vec<(&str, &str)> {
+    vec![
+        (&collections.users, "email_unique"),
+        (&collections.books, "isbn_13_unique"),
+        (&collections.authors, "aliases"),
+    ]
+}
+
+///
+/// # ensure indexes
+/// this function drop the replaced indexes and create the declared indexes that do not exist yet, existing indexes are left untouched
+/// # Arguments
+/// * `db` - the database
+/// * `collections` - the names of the collections
+/// # Return
+/// * `Result<(), Box<dyn Error>>` - nothing or an error, e.g. when existing documents break a unique index
+///
+async fn ensure_indexes(db: &Database, collections: &Collections) -> Result<(), Box<dyn Error>> {
+    for (name, index) in replaced_indexes(collections) {
+        let collection: Collection<Document> = db.collection(name);
+        match collection.drop_index(index, None).await {
+            // already dropped, or the collection does not exist yet
+            Err(e) if matches!(e.kind.as_ref(), ErrorKind::Command(c) if c.code == 26 || c.code == 27) => {}
+            result => result.map_err(|e| format!("Cannot drop index {} on {}: {}", index, name, e))?,
+        }
+    }
+    for (name, index) in indexes(collections) {
+        let collection: Collection<Document> = db.collection(name);
+        collection.create_index(index, None).await
+            .map_err(|e| format!("Cannot create index on {}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+///
+/// # conflict on duplicate
+/// this function turn a duplicate key error of a unique index into a `409 Conflict`, other errors are kept
+/// # Arguments
+/// * `error` - the error of the write
+/// * `message` - the message sent to the client on a duplicate
+/// # Return
+/// * `Box<dyn Error>` - the error
+///
+fn conflict_on_duplicate(error: mongodb::error::Error, message: &str) -> Box<dyn Error> {
+    let duplicate = match error.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == 11000,
+        ErrorKind::Command(e) => e.code == 11000,
+        _ => false,
+    };
+    if duplicate {
+        return HttpError::new(Status::Conflict, message).into();
+    }
+    error.into()
+}
+
+///
+/// # visible comments
+/// this function restrict a comment filter to the comments that are not held or hidden by moderation
+/// # Arguments
+/// * `filter` - the filter to restrict
+/// # Return
+/// * `Document` - the restricted filter
+///
+fn visible_comments(mut filter: Document) -> Document {
+    filter.insert("status", doc! {"$nin": ["held", "hidden"]});
+    not_deleted(filter)
+}
+
+///
+/// # not deleted
+/// this function restrict a filter to the documents that are not in the trash
+/// # Arguments
+/// * `filter` - the filter to restrict
+/// # Return
+/// * `Document` - the restricted filter
+///
+fn not_deleted(mut filter: Document) -> Document {
+    filter.insert("deleted", bson::Bson::Null);
+    filter
+}
+
+///
+/// # escape regex
+/// this function escape the characters of a text that have a meaning in a regular expression
+/// # Arguments
+/// * `text` - the text
+/// # Return
+/// * `String` - a regular expression matching the text
+///
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.^$|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+///
+/// # time query
+/// this function restrict a filter to the created_at/updated_at ranges of a time filter and build the matching find options
+/// # Arguments
+/// * `filter` - the filter to restrict
+/// * `time` - the time filter from the query string
+/// # Return
+/// * `Result<(Document, FindOptions), Box<dyn Error>>` - the restricted filter and the find options or an error
+///
+fn time_query(mut filter: Document, time: &TimeFilter) -> Result<(Document, FindOptions), Box<dyn Error>> {
+    let ranges = [
+        ("created_at", ("created_after", &time.created_after), ("created_before", &time.created_before)),
+        ("updated_at", ("updated_after", &time.updated_after), ("updated_before", &time.updated_before)),
+    ];
+    for (field, (after_name, after), (before_name, before)) in ranges {
+        let mut range = doc! {};
+        if let Some(after) = after {
+            range.insert("$gte", parse_date(after_name, after)?);
+        }
+        if let Some(before) = before {
+            range.insert("$lt", parse_date(before_name, before)?);
+        }
+        if !range.is_empty() {
+            filter.insert(field, range);
+        }
+    }
+
+    let sort = time.sort.map(|field| {
+        let key = match field {
+            TimestampField::CreatedAt => "created_at",
+            TimestampField::UpdatedAt => "updated_at",
+        };
+        let order = match time.order.unwrap_or(SortOrder::Desc) {
+            SortOrder::Asc => 1,
+            SortOrder::Desc => -1,
+        };
+        let mut sort = doc! {};
+        sort.insert(key, order);
+        sort
+    });
+    Ok((filter, FindOptions::builder().sort(sort).build()))
+}
+
+///
+/// # parse date
+/// this function parse a RFC 3339 date from the query string into a bson date
+/// # Arguments
+/// * `name` - the name of the query parameter
+/// * `value` - the date to parse
+/// # Return
+/// * `Result<bson::DateTime, Box<dyn Error>>` - a bson date or a `422` naming the parameter
+///
+fn parse_date(name: &str, value: &str) -> Result<bson::DateTime, Box<dyn Error>> {
+    let date = chrono::DateTime::parse_from_rfc3339(value)
+        .map_err(|_| HttpError::new(Status::UnprocessableEntity, format!("Invalid {} {}, expected an RFC 3339 date", name, value)))?;
+    Ok(bson::DateTime::from_chrono(date.with_timezone(&chrono::Utc)))
+}
+
+///
+/// # version filter
+/// this function build the query matching one of the expected versions of a document,
+/// documents written before versioning have no version and match version 0
+/// # Arguments
+/// * `versions` - the expected versions
+/// # Return
+/// * `Document` - the query on the version field
+///
+fn version_filter(versions: &[i64]) -> Document {
+    let mut accepted: Vec<bson::Bson> = versions.iter().map(|v| bson::Bson::Int64(*v)).collect();
+    if versions.contains(&0) {
+        accepted.push(bson::Bson::Null);
+    }
+    doc! {"$in": accepted}
+}
+
 impl Mongo {
 
+    ///
+    /// # for request
+    /// this function return a copy of the mongo struct used for a request
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `request_id` - the id of the request
+    /// # Return
+    /// * `Mongo` - the mongo struct tagged with the request id
+    ///
+    pub fn for_request(&self, request_id: &str) -> Mongo {
+        Mongo { request_id: Some(request_id.to_string()), ..self.clone() }
+    }
+
     // book
 
     ///
@@ -112,14 +509,17 @@ impl Mongo {
     ///
     /// # Arguments
     /// * `self` - the mongo struct
+    /// * `time` - the created_at/updated_at sorting and filtering
     ///
     /// # Return
     /// * `Result<Vec<Book>, Box<dyn Error>>` - a vector of books or an error
     ///
     ///
-    pub async fn get_all_books(&self) -> Result<Vec<Book>, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("books");
-        let mut cursor = collection.find(None, None).await?;
+    pub async fn get_all_books(&self, time: &TimeFilter) -> Result<Vec<Book>, Box<dyn Error>> {
+        let _timer = db_timer("get_all_books");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let (filter, options) = time_query(not_deleted(doc! {}), time)?;
+        let mut cursor = collection.find(filter, options).await?;
         let mut books = Vec::new();
         while let Some(result) = cursor.next().await {
             let book = bson::from_bson(bson::Bson::Document(result?))?;
@@ -138,28 +538,66 @@ impl Mongo {
     /// * `Result<Book, Box<dyn Error>>` - a book or an error
     ///
     pub async fn get_book_by_id(&self, id: &str) -> Result<Book, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("books");
-        let cursor = collection.find_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, None).await?;
-        let book = bson::from_bson(bson::Bson::Document(cursor.unwrap()))?;
-        Ok(book)
+        let _timer = db_timer("get_book_by_id");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let cursor = collection.find_one(not_deleted(doc! {"_id": parse_id(id, "Book")?}), None).await?;
+        match cursor {
+            Some(book) => Ok(bson::from_bson(bson::Bson::Document(book))?),
+            None => Err(HttpError::new(Status::NotFound, "Book not found").into()),
+        }
     }
 
 
+    ///
+    /// # get a book by isbn from database
+    /// this function get a book by its ISBN-10 or ISBN-13 and return a book or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `isbn` - the ISBN, in either form, with or without hyphens
+    /// # Return
+    /// * `Result<Book, Box<dyn Error>>` - a book, `422` for an invalid ISBN or `404` when no book has it
+    ///
+    pub async fn get_book_by_isbn(&self, isbn: &str) -> Result<Book, Box<dyn Error>> {
+        let _timer = db_timer("get_book_by_isbn");
+        let Some((_, isbn_13)) = isbn::parse(isbn) else {
+            return Err(HttpError::new(Status::UnprocessableEntity, "Invalid ISBN").into());
+        };
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        match collection.find_one(not_deleted(doc! {"isbn_13": isbn_13}), None).await? {
+            Some(book) => Ok(bson::from_bson(bson::Bson::Document(book))?),
+            None => Err(HttpError::new(Status::NotFound, "Book not found").into()),
+        }
+    }
+
     ///
     /// # create a book in database
     /// this function create a book in mongo database and return a book or an error
     /// # Arguments
     /// * `self` - the mongo struct
     /// * `book` - the book to create
+    /// * `actor` - the id of the user performing the change
     /// # Return
     /// * `Result<Book, Box<dyn Error>>` - a book or an error
     ///
-    pub async fn create_book(&self, book: NewBook) -> Result<Book, Box<dyn Error>> {
-        let book = Book::from(book);
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("books");
-        let doc = bson::to_bson(&book)?;
+    pub async fn create_book(&self, book: NewBook, actor: Option<&str>) -> Result<Book, Box<dyn Error>> {
+        let _timer = db_timer("create_book");
+        let author_ids: Vec<String> = book.authors.iter().map(|author| author.author_id.clone()).collect();
+        self.check_authors(&author_ids).await?;
+        if let Some(work_id) = &book.work_id {
+            self.check_work(work_id).await?;
+        }
+        self.check_subjects(&book.subject_ids).await?;
+        let mut book = Book::from(book);
+        let now = bson::DateTime::now().to_chrono();
+        book.created_at = now;
+        book.updated_at = now;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let doc = to_stored_bson(&book)?;
         let doc = doc.as_document().unwrap();
-        collection.insert_one(doc.clone(), None).await?;
+        let result = collection.insert_one(doc.clone(), None).await
+            .map_err(|e| conflict_on_duplicate(e, "A book with this ISBN already exists"))?;
+        let id = result.inserted_id.as_object_id().unwrap().to_hex();
+        self.audit(actor, AuditAction::Create, AuditEntity::Book, &id, None, Some(doc)).await?;
         Ok(book)
     }
 
@@ -169,39 +607,136 @@ impl Mongo {
     /// # Arguments
     /// * `self` - the mongo struct
     /// * `id` - the id of the book
-    /// * `book` - the book to update (HashMap<&str, String>)
+    /// * `book` - the book to update (HashMap<&str, Value>)
+    /// * `if_match` - the versions the update is allowed on, `None` for any version
+    /// * `actor` - the id of the user performing the change
     /// # Return
-    /// * `Result<Book, Box<dyn Error>>` - a book or an error
+    /// * `Result<Book, Box<dyn Error>>` - a book or an error, `412 Precondition Failed` on a stale version
     ///
-    pub async fn update_book(&self, id: &str, book: HashMap<&str, Value>) -> Result<Book, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("books");
+    pub async fn update_book(&self, id: &str, book: HashMap<&str, Value>, if_match: Option<Vec<i64>>, actor: Option<&str>) -> Result<Book, Box<dyn Error>> {
+        let _timer = db_timer("update_book");
+        if let Some(Value::DocumentList(authors)) = book.get("authors") {
+            let author_ids: Vec<String> = authors.iter().filter_map(|author| author.get_str("author_id").ok()).map(str::to_string).collect();
+            self.check_authors(&author_ids).await?;
+        }
+        if let Some(Value::Text(work_id)) = book.get("work_id") {
+            self.check_work(work_id).await?;
+        }
+        if let Some(Value::TextList(subject_ids)) = book.get("subject_ids") {
+            self.check_subjects(subject_ids).await?;
+        }
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
         let mut query = doc! {};
         for (key, value) in book {
             match value {
                 Value::Bool(b) => query.insert(key, b),
                 Value::Int(i) => query.insert(key, i),
                 Value::Text(t) => query.insert(key, t),
+                Value::Date(d) => query.insert(key, to_bson_date(d)),
+                Value::TextList(l) => query.insert(key, l),
+                Value::DocumentList(l) => query.insert(key, l),
+                Value::Null => query.insert(key, bson::Bson::Null),
             };
         }
-        collection.update_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, doc! {"$set": query}, None).await?;
-        let cursor = collection.find_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, None).await?;
-        let book = bson::from_bson(bson::Bson::Document(cursor.unwrap()))?;
+        query.insert("updated_at", bson::DateTime::now());
+        let oid = parse_id(id, "Book")?;
+        let before = match collection.find_one(not_deleted(doc! {"_id": oid}), None).await? {
+            Some(before) => before,
+            None => return Err(HttpError::new(Status::NotFound, "Book not found").into()),
+        };
+        let in_series = match query.get("series") {
+            Some(series) => series.as_str().is_some(),
+            None => before.get_str("series").is_ok(),
+        };
+        let numbered = match query.get("volume") {
+            Some(volume) => volume.as_i32().is_some(),
+            None => before.get_i32("volume").is_ok(),
+        };
+        if numbered && !in_series {
+            return Err(FieldErrors(BTreeMap::from([("volume".to_string(), vec!["requires series".to_string()])])).into());
+        }
+        let mut filter = doc! {"_id": oid};
+        if let Some(versions) = if_match {
+            filter.insert("version", version_filter(&versions));
+        }
+        let result = collection.update_one(filter, doc! {"$set": query, "$inc": {"version": 1}}, None).await
+            .map_err(|e| conflict_on_duplicate(e, "A book with this ISBN already exists"))?;
+        if result.matched_count == 0 {
+            return Err(HttpError::new(Status::PreconditionFailed, "Book was modified by someone else, reload it and retry").into());
+        }
+        let after = collection.find_one(doc! {"_id": oid}, None).await?.unwrap();
+        self.audit(actor, AuditAction::Update, AuditEntity::Book, id, Some(&before), Some(&after)).await?;
+        let book = bson::from_bson(bson::Bson::Document(after))?;
         Ok(book)
     }
 
+    ///
+    /// # patch a book in database
+    /// this function apply a merge patch or a JSON Patch to the editable fields of a book and return the book or an error
+    /// the book is only written if it is still at the version the patch was applied to
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the book
+    /// * `patch` - the patch
+    /// * `if_match` - the versions the patch is allowed on, `None` for any version
+    /// * `actor` - the id of the user performing the change
+    /// # Return
+    /// * `Result<Book, Box<dyn Error>>` - a book or an error, `412 Precondition Failed` on a stale version
+    ///
+    pub async fn patch_book(&self, id: &str, patch: &Patch, if_match: Option<Vec<i64>>, actor: Option<&str>) -> Result<Book, Box<dyn Error>> {
+        let _timer = db_timer("patch_book");
+        let book = self.get_book_by_id(id).await?;
+        if if_match.is_some_and(|versions| !versions.contains(&book.version)) {
+            return Err(HttpError::new(Status::PreconditionFailed, "Book was modified by someone else, reload it and retry").into());
+        }
+        let current = UpdateBook::from(&book);
+        let mut patched = patch.apply_to(&current)?;
+        patched.follow_isbn(&current);
+        validate(&patched)?;
+        let changes = changed_fields(current.into_changes(), patched.into_changes(), &UpdateBook::REQUIRED)?;
+        if changes.is_empty() {
+            return Ok(book);
+        }
+        self.update_book(id, changes, Some(vec![book.version]), actor).await
+    }
+
     ///
     /// # delete a book from database
-    /// this function delete a book with id from mongo database and return a book or an error
+    /// this function move a book with id to the trash and return a book or an error
     /// # Arguments
     /// * `self` - the mongo struct
     /// * `id` - the id of the book
+    /// * `force` - delete the book even if it is on loan
+    /// * `actor` - the id of the user performing the change
     /// # Return
     /// * `Result<Book, Box<dyn Error>>` - a book or an error
-    pub async fn delete_book(&self, id: &str) -> Result<Book, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("books");
-        let cursor = collection.find_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, None).await?;
-        let book = bson::from_bson(bson::Bson::Document(cursor.unwrap()))?;
-        collection.delete_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, None).await?;
+    ///
+    /// a book on loan is only deleted with `force`, the loan is then removed from the borrower.
+    /// the comments of the book go to the trash with it.
+    pub async fn delete_book(&self, id: &str, force: bool, actor: Option<&str>) -> Result<Book, Box<dyn Error>> {
+        let _timer = db_timer("delete_book");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let collection_user: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
+        let collection_comment: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let oid = parse_id(id, "Book")?;
+        let mut book: Book = match collection.find_one(not_deleted(doc! {"_id": oid}), None).await? {
+            Some(book) => bson::from_bson(bson::Bson::Document(book))?,
+            None => return Err(HttpError::new(Status::NotFound, "Book not found").into()),
+        };
+
+        let held = collection_user.find_one(doc! {"borrowed_books": id}, None).await?.is_some();
+        book.check_deletion(held, force)?;
+
+        let before = to_stored_document(&book)?;
+        let deletion = Deletion { by: actor.map(str::to_string), at: bson::DateTime::now().to_chrono() };
+        let stamp = to_stored_bson(&deletion)?;
+        collection.update_one(doc! {"_id": oid}, doc! {"$set": {"deleted": stamp.clone()}, "$inc": {"version": 1}}, None).await?;
+        let (filter, update) = unlink_borrowers(id);
+        collection_user.update_many(filter, update, None).await?;
+        collection_comment.update_many(not_deleted(doc! {"book_id": id}), doc! {"$set": {"deleted": stamp}}, None).await?;
+        book.deleted = Some(deletion);
+        book.version += 1;
+        self.audit(actor, AuditAction::Delete, AuditEntity::Book, id, Some(&before), Some(&to_stored_document(&book)?)).await?;
         Ok(book)
     }
 
@@ -211,16 +746,19 @@ impl Mongo {
     /// # Arguments
     /// * `self` - the mongo struct
     /// * `search` - the search query (HashMap<&str, String>)
+    /// * `time` - the created_at/updated_at sorting and filtering
     /// # Return
     /// * `Result<Vec<Book>, Box<dyn Error>>` - a vector of books or an error
     ///
-    pub async fn search_book(&self, search: HashMap<&str, String>) -> Result<Vec<Book>, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("books");
+    pub async fn search_book(&self, search: HashMap<&str, String>, time: &TimeFilter) -> Result<Vec<Book>, Box<dyn Error>> {
+        let _timer = db_timer("search_book");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
         let mut query = doc! {};
         for (key, value) in search {
             query.insert(key, value);
         }
-        let mut cursor = collection.find(query, None).await?;
+        let (query, options) = time_query(not_deleted(query), time)?;
+        let mut cursor = collection.find(query, options).await?;
         let mut books = Vec::new();
         while let Some(result) = cursor.next().await {
             let book = bson::from_bson(bson::Bson::Document(result?))?;
@@ -229,6 +767,141 @@ impl Mongo {
         Ok(books)
     }
 
+    ///
+    /// # search works from database
+    /// this function search books like `search_book` and collapse the hits to one per work, in the order of their first edition found
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `search` - the searched fields (HashMap<&str, String>)
+    /// * `time` - the created_at/updated_at sorting and filtering
+    /// # Return
+    /// * `Result<Vec<WorkHit>, Box<dyn Error>>` - the hits, a book without work is a hit of its own, or an error
+    ///
+    pub async fn search_works(&self, search: HashMap<&str, String>, time: &TimeFilter) -> Result<Vec<WorkHit>, Box<dyn Error>> {
+        let _timer = db_timer("search_works");
+        let db = self.client.database(&self.config.db_name);
+        let collection: Collection<Document> = db.collection(&self.config.collections.books);
+        let collection_work: Collection<Document> = db.collection(&self.config.collections.works);
+        let mut query = doc! {};
+        for (key, value) in search {
+            query.insert(key, value);
+        }
+        let (query, options) = time_query(not_deleted(query), time)?;
+        let mut cursor = collection.find(query, options).await?;
+        let mut books = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let document = result?;
+            let book_id = document.get_object_id("_id")?.to_hex();
+            books.push((book_id, bson::from_bson::<Book>(bson::Bson::Document(document))?));
+        }
+        let mut hits = WorkHit::collapse(books);
+        let hit_of_work: HashMap<String, usize> = hits.iter().enumerate()
+            .filter_map(|(index, hit)| hit.work_id.clone().map(|work_id| (work_id, index)))
+            .collect();
+
+        let work_ids = hit_of_work.keys().filter_map(|id| bson::oid::ObjectId::parse_str(id).ok()).collect::<Vec<_>>();
+        if !work_ids.is_empty() {
+            let mut cursor = collection_work.find(doc! {"_id": {"$in": work_ids}}, None).await?;
+            while let Some(result) = cursor.next().await {
+                let work: Work = bson::from_bson(bson::Bson::Document(result?))?;
+                if let Some(index) = work.id.and_then(|id| hit_of_work.get(&id.to_hex())) {
+                    hits[*index].title = work.title;
+                    hits[*index].author = work.author;
+                }
+            }
+        }
+        Ok(hits)
+    }
+
+    ///
+    /// # get series from database
+    /// this function return the name of every series with its number of books, by name, or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// # Return
+    /// * `Result<Vec<Series>, Box<dyn Error>>` - a vector of series or an error
+    ///
+    pub async fn get_series(&self) -> Result<Vec<Series>, Box<dyn Error>> {
+        let _timer = db_timer("get_series");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let pipeline = vec![
+            doc! { "$match": not_deleted(doc! {"series": {"$type": "string"}}) },
+            doc! { "$group": { "_id": "$series", "books": { "$sum": 1 } } },
+            doc! { "$sort": { "_id": 1 } },
+            doc! { "$project": { "_id": 0, "name": "$_id", "books": 1 } },
+        ];
+        let mut cursor = collection.aggregate(pipeline, None).await?;
+        let mut series = Vec::new();
+        while let Some(result) = cursor.next().await {
+            series.push(bson::from_bson(bson::Bson::Document(result?))?);
+        }
+        Ok(series)
+    }
+
+    ///
+    /// # get books by series from database
+    /// this function return the books of a series in volume order, books without volume last, by year, or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `name` - the name of the series
+    /// # Return
+    /// * `Result<Vec<Book>, Box<dyn Error>>` - a vector of book or an error
+    ///
+    pub async fn get_books_by_series(&self, name: &str) -> Result<Vec<Book>, Box<dyn Error>> {
+        let _timer = db_timer("get_books_by_series");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let pipeline = vec![
+            doc! { "$match": not_deleted(doc! {"series": name}) },
+            doc! { "$set": { "unnumbered": { "$eq": [{ "$ifNull": ["$volume", null] }, null] } } },
+            doc! { "$sort": { "unnumbered": 1, "volume": 1, "year": 1 } },
+            doc! { "$unset": "unnumbered" },
+        ];
+        let mut cursor = collection.aggregate(pipeline, None).await?;
+        let mut books = Vec::new();
+        while let Some(result) = cursor.next().await {
+            books.push(bson::from_bson(bson::Bson::Document(result?))?);
+        }
+        Ok(books)
+    }
+
+    ///
+    /// # browse shelf from database
+    /// this function return the books shelved around a call number, in call number order, books without call number are not shelved
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `call_number` - the call number, case and spacing are ignored
+    /// * `limit` - the number of books on each side
+    /// # Return
+    /// * `Result<Shelf, Box<dyn Error>>` - the books before and from the call number, or `422` for an invalid call number
+    ///
+    pub async fn browse_shelf(&self, call_number: &str, limit: u32) -> Result<Shelf, Box<dyn Error>> {
+        let _timer = db_timer("browse_shelf");
+        let call_number = call_number::normalise(call_number);
+        if !call_number::is_dewey(&call_number) {
+            return Err(HttpError::new(Status::UnprocessableEntity, "Invalid call number").into());
+        }
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let side = |operator: &str, order: i32| {
+            let filter = not_deleted(doc! {"call_number": {operator: call_number.as_str()}});
+            let options = FindOptions::builder()
+                .sort(doc! {"call_number": order, "_id": order})
+                .limit(i64::from(limit))
+                .projection(doc! {"call_number": 1, "title": 1, "author": 1, "availability": 1})
+                .build();
+            (filter, options)
+        };
+        // the closest books on each side, read backwards before the call number
+        let mut books = Vec::new();
+        for (operator, order) in [("$lt", -1), ("$gte", 1)] {
+            let (filter, options) = side(operator, order);
+            let mut cursor = collection.find(filter, options).await?;
+            while let Some(result) = cursor.next().await {
+                books.extend(ShelfBook::from_document(&result?)?);
+            }
+        }
+        Ok(Shelf::around(call_number, books, limit))
+    }
+
     ///
     /// # borrow a book from database
     /// this function borrow a book with id from mongo database and return a book or an error
@@ -236,33 +909,69 @@ impl Mongo {
     /// * `self` - the mongo struct
     /// * `id` - the id of the book
     /// * `user_id` - the id of the user
+    /// * `actor` - the id of the user performing the change
     /// # Return
     /// * `Result<(User, Book), Box<dyn Error>>` - a tuple of user and book or an error
     ///
-    pub async fn borrow_book(&self, id: &str, user_id: &str) -> Result<(User, Book), Box<dyn Error>> {
-        let collection_book: Collection<Document> = self.client.database(&self.config.db_name).collection("books");
-        let collection_user: Collection<Document> = self.client.database(&self.config.db_name).collection("users");
-        let cursor = collection_book.find_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, None).await?;
-        let mut book: Book = bson::from_bson(bson::Bson::Document(cursor.unwrap()))?;
-        let cursor = collection_user.find_one(doc! {"_id": bson::oid::ObjectId::parse_str(user_id).unwrap()}, None).await?;
-        let mut user: User = bson::from_bson(bson::Bson::Document(cursor.unwrap()))?;
+    pub async fn borrow_book(&self, id: &str, user_id: &str, actor: Option<&str>) -> Result<(User, Book), Box<dyn Error>> {
+        let _timer = db_timer("borrow_book");
+        let collection_book: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let collection_user: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
+        let book_oid = parse_id(id, "Book")?;
+        let user_oid = parse_id(user_id, "User")?;
+        let book_before = match collection_book.find_one(not_deleted(doc! {"_id": book_oid}), None).await? {
+            Some(book) => book,
+            None => return Err(HttpError::new(Status::NotFound, "Book not found").into()),
+        };
+        let user_before = match collection_user.find_one(not_deleted(doc! {"_id": user_oid}), None).await? {
+            Some(user) => user,
+            None => return Err(HttpError::new(Status::NotFound, "User not found").into()),
+        };
+        let book: Book = bson::from_bson(bson::Bson::Document(book_before.clone()))?;
+        let user: User = bson::from_bson(bson::Bson::Document(user_before.clone()))?;
 
         if !book.availability {
-            return Err("Book not available".into());
+            return Err(HttpError::new(Status::Conflict, "Book not available").into());
         }
-        book.availability = false;
-        user.borrowed_books.push(id.to_string());
-
-        let doc = bson::to_bson(&book)?;
-        let doc = doc.as_document().unwrap();
-        collection_book.update_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, doc! {"$set": doc}, None).await?;
-
-
-        let doc = bson::to_bson(&user)?;
-        let doc = doc.as_document().unwrap();
-        collection_user.update_one(doc! {"_id": bson::oid::ObjectId::parse_str(user_id).unwrap()}, doc! {"$set": doc}, None).await?;
+        if let Some(min_age) = book.min_age {
+            if user.age() < min_age {
+                return Err(HttpError::new(Status::Forbidden, format!("User must be at least {} years old to borrow this book", min_age)).into());
+            }
+        }
+        let now = bson::DateTime::now().to_chrono();
+        let loan = Loan { user_id: user_id.to_string(), at: now, due: now + chrono::Duration::days(self.config.loan_days) };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        // only an available book is lent, a borrow racing with another one or with a change finds nothing to update
+        let book_after = collection_book.find_one_and_update(
+            not_deleted(doc! {"_id": book_oid, "availability": true}),
+            doc! {"$set": {"availability": false, "loan": to_stored_bson(&loan)?, "updated_at": bson::DateTime::from_chrono(now)}, "$inc": {"version": 1}},
+            options.clone(),
+        ).await?;
+        let book_after = match book_after {
+            Some(book) => book,
+            None => return Err(HttpError::new(Status::Conflict, "Book not available").into()),
+        };
+        let user_after = collection_user.find_one_and_update(
+            not_deleted(doc! {"_id": user_oid}),
+            doc! {"$push": {"borrowed_books": id}, "$set": {"updated_at": bson::DateTime::from_chrono(now)}, "$inc": {"version": 1}},
+            options,
+        ).await?;
+        let user_after = match user_after {
+            Some(user) => user,
+            None => {
+                // the user went to the trash meanwhile, the book is not lent to them
+                collection_book.update_one(
+                    doc! {"_id": book_oid, "loan.user_id": user_id},
+                    doc! {"$set": {"availability": true, "updated_at": bson::DateTime::from_chrono(now)}, "$unset": {"loan": ""}, "$inc": {"version": 1}},
+                    None,
+                ).await?;
+                return Err(HttpError::new(Status::NotFound, "User not found").into());
+            }
+        };
+        self.audit(actor, AuditAction::Borrow, AuditEntity::Book, id, Some(&book_before), Some(&book_after)).await?;
+        self.audit(actor, AuditAction::Borrow, AuditEntity::User, user_id, Some(&user_before), Some(&user_after)).await?;
 
-        Ok((user, book))
+        Ok((bson::from_bson(bson::Bson::Document(user_after))?, bson::from_bson(bson::Bson::Document(book_after))?))
     }
 
     ///
@@ -272,32 +981,55 @@ impl Mongo {
     /// * `self` - the mongo struct
     /// * `id` - the id of the book
     /// * `user_id` - the id of the user
+    /// * `actor` - the id of the user performing the change
     /// # Return
-    /// * `Result<(User, Book), Box<dyn Error>>` - a tuple of user and book or an error
+    /// * `Result<(User, Book), Box<dyn Error>>` - a tuple of user and book, or `409` unless the book is lent to the user
     ///
-    pub async fn return_book(&self, id: &str, user_id: &str) -> Result<(User, Book), Box<dyn Error>> {
-        let collection_book: Collection<Document> = self.client.database(&self.config.db_name).collection("books");
-        let collection_user: Collection<Document> = self.client.database(&self.config.db_name).collection("users");
-        let cursor = collection_book.find_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, None).await?;
-        let mut book: Book = bson::from_bson(bson::Bson::Document(cursor.unwrap()))?;
-        let cursor = collection_user.find_one(doc! {"_id": bson::oid::ObjectId::parse_str(user_id).unwrap()}, None).await?;
-        let mut user: User = bson::from_bson(bson::Bson::Document(cursor.unwrap()))?;
-
-        if book.availability {
-            return Err("Book not borrowed".into());
+    pub async fn return_book(&self, id: &str, user_id: &str, actor: Option<&str>) -> Result<(User, Book), Box<dyn Error>> {
+        let _timer = db_timer("return_book");
+        let collection_book: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let collection_user: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
+        let book_oid = parse_id(id, "Book")?;
+        let user_oid = parse_id(user_id, "User")?;
+        let book_before = match collection_book.find_one(not_deleted(doc! {"_id": book_oid}), None).await? {
+            Some(book) => book,
+            None => return Err(HttpError::new(Status::NotFound, "Book not found").into()),
+        };
+        let user_before = match collection_user.find_one(not_deleted(doc! {"_id": user_oid}), None).await? {
+            Some(user) => user,
+            None => return Err(HttpError::new(Status::NotFound, "User not found").into()),
+        };
+        let book: Book = bson::from_bson(bson::Bson::Document(book_before.clone()))?;
+        match &book.loan {
+            Some(loan) if loan.user_id == user_id => {}
+            Some(_) => return Err(HttpError::new(Status::Conflict, "Book is borrowed by another user").into()),
+            None => return Err(HttpError::new(Status::Conflict, "Book not borrowed").into()),
         }
-        book.availability = true;
-        user.borrowed_books.retain(|x| x != id);
-
-        let doc = bson::to_bson(&book)?;
-        let doc = doc.as_document().unwrap();
-        collection_book.update_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, doc! {"$set": doc}, None).await?;
 
-        let doc = bson::to_bson(&user)?;
-        let doc = doc.as_document().unwrap();
-        collection_user.update_one(doc! {"_id": bson::oid::ObjectId::parse_str(user_id).unwrap()}, doc! {"$set": doc}, None).await?;
+        let now = bson::DateTime::now().to_chrono();
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        let book_after = collection_book.find_one_and_update(
+            not_deleted(doc! {"_id": book_oid, "loan.user_id": user_id}),
+            doc! {"$set": {"availability": true, "updated_at": bson::DateTime::from_chrono(now)}, "$unset": {"loan": ""}, "$inc": {"version": 1}},
+            options.clone(),
+        ).await?;
+        let book_after = match book_after {
+            Some(book) => book,
+            None => return Err(HttpError::new(Status::Conflict, "Book not borrowed").into()),
+        };
+        let user_after = collection_user.find_one_and_update(
+            doc! {"_id": user_oid},
+            doc! {"$pull": {"borrowed_books": id}, "$set": {"updated_at": bson::DateTime::from_chrono(now)}, "$inc": {"version": 1}},
+            options,
+        ).await?;
+        let user_after = match user_after {
+            Some(user) => user,
+            None => return Err(HttpError::new(Status::NotFound, "User not found").into()),
+        };
+        self.audit(actor, AuditAction::Return, AuditEntity::Book, id, Some(&book_before), Some(&book_after)).await?;
+        self.audit(actor, AuditAction::Return, AuditEntity::User, user_id, Some(&user_before), Some(&user_after)).await?;
 
-        Ok((user, book))
+        Ok((bson::from_bson(bson::Bson::Document(user_after))?, bson::from_bson(bson::Bson::Document(book_after))?))
     }
     // end book
 
@@ -309,40 +1041,61 @@ impl Mongo {
     /// # Arguments
     /// * `self` - the mongo struct
     /// * `new_user` - the new user
+    /// * `actor` - the id of the user performing the change
     /// # Return
     /// * `Result<User, Box<dyn Error>>` - a user or an error
     ///
-    pub async fn create_user(&self, new_user: NewUser) -> Result<User, Box<dyn Error>> {
-        let user = User::from(new_user);
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("users");
-
-        let date = chrono::NaiveDate::parse_from_str(&user.birth_date, "%Y-%m-%d");
-        if date.is_err() {
-            return Err("Invalid date format".into());
-        }
-
-        let doc = bson::to_bson(&user)?;
+    pub async fn create_user(&self, new_user: NewUser, actor: Option<&str>) -> Result<User, Box<dyn Error>> {
+        let _timer = db_timer("create_user");
+        let mut user = User::from(new_user);
+        let now = bson::DateTime::now().to_chrono();
+        user.created_at = now;
+        user.updated_at = now;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
+
+        let doc = to_stored_bson(&user)?;
         let doc = doc.as_document().unwrap();
 
-        if let Some(_) = collection.find_one(doc! {"email": &user.email}, None).await? {
-            return Err("User already exist".into());
-        }
-
-        collection.insert_one(doc.clone(), None).await?;
+        let result = collection.insert_one(doc.clone(), None).await
+            .map_err(|e| conflict_on_duplicate(e, "User already exist"))?;
+        let id = result.inserted_id.as_object_id().unwrap().to_hex();
+        self.audit(actor, AuditAction::Create, AuditEntity::User, &id, None, Some(doc)).await?;
         Ok(user)
     }
 
+    ///
+    /// # get a user from database
+    /// this function get a user with id from mongo database and return a user or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the user
+    /// # Return
+    /// * `Result<User, Box<dyn Error>>` - a user or an error
+    ///
+    pub async fn get_user_by_id(&self, id: &str) -> Result<User, Box<dyn Error>> {
+        let _timer = db_timer("get_user_by_id");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
+        let cursor = collection.find_one(not_deleted(doc! {"_id": parse_id(id, "User")?}), None).await?;
+        match cursor {
+            Some(user) => Ok(bson::from_bson(bson::Bson::Document(user))?),
+            None => Err(HttpError::new(Status::NotFound, "User not found").into()),
+        }
+    }
+
     ///
     /// # get all user from database
     /// this function return all user from mongo database and return a vector of user or an error
     /// # Arguments
     /// * `self` - the mongo struct
+    /// * `time` - the created_at/updated_at sorting and filtering
     /// # Return
     /// * `Result<Vec<User>, Box<dyn Error>>` - a vector of user or an error
     ///
-    pub async fn get_all_users(&self) -> Result<Vec<User>, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("users");
-        let mut cursor = collection.find(None, None).await?;
+    pub async fn get_all_users(&self, time: &TimeFilter) -> Result<Vec<User>, Box<dyn Error>> {
+        let _timer = db_timer("get_all_users");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
+        let (filter, options) = time_query(not_deleted(doc! {}), time)?;
+        let mut cursor = collection.find(filter, options).await?;
         let mut users = Vec::new();
         while let Some(result) = cursor.next().await {
             let user = bson::from_bson(bson::Bson::Document(result?))?;
@@ -357,16 +1110,19 @@ impl Mongo {
     /// # Arguments
     /// * `self` - the mongo struct
     /// * `search` - the search query (HashMap<&str, String>)
+    /// * `time` - the created_at/updated_at sorting and filtering
     /// # Return
     /// * `Result<Vec<User>, Box<dyn Error>>` - a vector of user or an error
     ///
-    pub async fn search_user(&self, search: HashMap<&str, String>) -> Result<Vec<User>, Box<dyn Error>> {
+    pub async fn search_user(&self, search: HashMap<&str, String>, time: &TimeFilter) -> Result<Vec<User>, Box<dyn Error>> {
+        let _timer = db_timer("search_user");
         let mut query = doc! {};
         for (key, value) in search {
             query.insert(key, value);
         }
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("users");
-        let mut cursor = collection.find(query, None).await?;
+        let (query, options) = time_query(not_deleted(query), time)?;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
+        let mut cursor = collection.find(query, options).await?;
         let mut users = Vec::new();
         while let Some(result) = cursor.next().await {
             let user = bson::from_bson(bson::Bson::Document(result?))?;
@@ -381,27 +1137,128 @@ impl Mongo {
     /// # Arguments
     /// * `self` - the mongo struct
     /// * `id` - the id of the user
-    /// * `user` - the user to update (HashMap<&str, String>)
+    /// * `user` - the user to update (HashMap<&str, Value>)
+    /// * `if_match` - the versions the update is allowed on, `None` for any version
+    /// * `actor` - the id of the user performing the change
     /// # Return
-    /// * `Result<User, Box<dyn Error>>` - a user or an error
+    /// * `Result<User, Box<dyn Error>>` - a user or an error, `412 Precondition Failed` on a stale version,
+    ///   `403` for a change of role unless a librarian asks
     ///
-    pub async fn update_user(&self, id: &str, user: HashMap<&str, String>) -> Result<User, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("users");
+    pub async fn update_user(&self, id: &str, user: HashMap<&str, Value>, if_match: Option<Vec<i64>>, actor: Option<&str>) -> Result<User, Box<dyn Error>> {
+        let _timer = db_timer("update_user");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
         let mut query = doc! {};
         for (key, value) in user {
-            query.insert(key, value);
+            match value {
+                Value::Bool(b) => query.insert(key, b),
+                Value::Int(i) => query.insert(key, i),
+                Value::Text(t) => query.insert(key, t),
+                Value::Date(d) => query.insert(key, to_bson_date(d)),
+                Value::TextList(l) => query.insert(key, l),
+                Value::DocumentList(l) => query.insert(key, l),
+                Value::Null => query.insert(key, bson::Bson::Null),
+            };
+        }
+        query.insert("updated_at", bson::DateTime::now());
+        let oid = parse_id(id, "User")?;
+        let before = match collection.find_one(not_deleted(doc! {"_id": oid}), None).await? {
+            Some(before) => before,
+            None => return Err(HttpError::new(Status::NotFound, "User not found").into()),
+        };
+        // librarians moderate, restore and export personal fields, only they make a librarian
+        if query.get_str("role").is_ok_and(|role| before.get_str("role") != Ok(role)) {
+            self.ensure_librarian(actor, "change roles").await?;
         }
-        collection.update_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, doc! {"$set": query}, None).await?;
-        let cursor = collection.find_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, None).await?;
-        let user = bson::from_bson(bson::Bson::Document(cursor.unwrap()))?;
+        let mut filter = doc! {"_id": oid};
+        if let Some(versions) = if_match {
+            filter.insert("version", version_filter(&versions));
+        }
+        let result = collection.update_one(filter, doc! {"$set": query, "$inc": {"version": 1}}, None).await
+            .map_err(|e| conflict_on_duplicate(e, "Email already used by another user"))?;
+        if result.matched_count == 0 {
+            return Err(HttpError::new(Status::PreconditionFailed, "User was modified by someone else, reload it and retry").into());
+        }
+        let after = collection.find_one(doc! {"_id": oid}, None).await?.unwrap();
+        self.audit(actor, AuditAction::Update, AuditEntity::User, id, Some(&before), Some(&after)).await?;
+        let user = bson::from_bson(bson::Bson::Document(after))?;
         Ok(user)
     }
 
-    pub async fn delete_user(&self, id: &str) -> Result<User, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("users");
-        let cursor = collection.find_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, None).await?;
-        let user = bson::from_bson(bson::Bson::Document(cursor.unwrap()))?;
-        collection.delete_one(doc! {"_id": bson::oid::ObjectId::parse_str(id).unwrap()}, None).await?;
+    ///
+    /// # patch user in database
+    /// this function apply a merge patch or a JSON Patch to the editable fields of a user and return the user or an error
+    /// the user is only written if it is still at the version the patch was applied to
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the user
+    /// * `patch` - the patch
+    /// * `if_match` - the versions the patch is allowed on, `None` for any version
+    /// * `actor` - the id of the user performing the change
+    /// # Return
+    /// * `Result<User, Box<dyn Error>>` - a user or an error, `412 Precondition Failed` on a stale version
+    ///
+    pub async fn patch_user(&self, id: &str, patch: &Patch, if_match: Option<Vec<i64>>, actor: Option<&str>) -> Result<User, Box<dyn Error>> {
+        let _timer = db_timer("patch_user");
+        let user = self.get_user_by_id(id).await?;
+        if if_match.is_some_and(|versions| !versions.contains(&user.version)) {
+            return Err(HttpError::new(Status::PreconditionFailed, "User was modified by someone else, reload it and retry").into());
+        }
+        let current = UpdateUser::from(&user);
+        let patched = patch.apply_to(&current)?;
+        validate(&patched)?;
+        let changes = changed_fields(current.into_changes(), patched.into_changes(), &UpdateUser::REQUIRED)?;
+        if changes.is_empty() {
+            return Ok(user);
+        }
+        self.update_user(id, changes, Some(vec![user.version]), actor).await
+    }
+
+    ///
+    /// # delete user from database
+    /// this function move user with id to the trash and return the user or an error
+    /// a user holding books is only deleted with `force`, the books are then made available again
+    /// the helpful votes and flags of the user are removed from the comments
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the user
+    /// * `force` - delete the user even if they still hold books
+    /// * `comments` - delete the comments of the user or keep them to be anonymised on purge
+    /// * `actor` - the id of the user performing the change
+    /// # Return
+    /// * `Result<User, Box<dyn Error>>` - a user or an error
+    ///
+    pub async fn delete_user(&self, id: &str, force: bool, comments: UserCommentPolicy, actor: Option<&str>) -> Result<User, Box<dyn Error>> {
+        let _timer = db_timer("delete_user");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
+        let collection_book: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let collection_comment: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let oid = parse_id(id, "User")?;
+        let mut user: User = match collection.find_one(not_deleted(doc! {"_id": oid}), None).await? {
+            Some(user) => bson::from_bson(bson::Bson::Document(user))?,
+            None => return Err(HttpError::new(Status::NotFound, "User not found").into()),
+        };
+
+        let book_ids = user.books_to_release(force)?;
+
+        let before = to_stored_document(&user)?;
+        let deletion = Deletion { by: actor.map(str::to_string), at: bson::DateTime::now().to_chrono() };
+        let stamp = to_stored_bson(&deletion)?;
+        collection.update_one(doc! {"_id": oid}, doc! {"$set": {"deleted": stamp.clone(), "borrowed_books": []}, "$inc": {"version": 1}}, None).await?;
+        if !book_ids.is_empty() {
+            let (filter, update) = release_books(&book_ids);
+            collection_book.update_many(filter, update, None).await?;
+        }
+        if comments == UserCommentPolicy::Delete {
+            collection_comment.update_many(not_deleted(doc! {"user_id": id}), doc! {"$set": {"deleted": stamp}}, None).await?;
+        }
+        // the votes and flags of a deleted user no longer count
+        for (filter, update) in unlink_comments(id) {
+            collection_comment.update_many(filter, update, None).await?;
+        }
+        user.borrowed_books.clear();
+        user.deleted = Some(deletion);
+        user.version += 1;
+        self.audit(actor, AuditAction::Delete, AuditEntity::User, id, Some(&before), Some(&to_stored_document(&user)?)).await?;
         Ok(user)
     }
     // end user
@@ -417,28 +1274,58 @@ impl Mongo {
     /// # Arguments
     /// * `self` - the mongo struct
     /// * `comment` - the new comment
+    /// * `actor` - the id of the user performing the change
     /// # Return
     /// * `Result<Comment, Box<dyn Error>>` - a comment or an error
-    pub async fn create_comment(&self, comment: NewComment) -> Result<Comment, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("comments");
-        let comment = Comment::from(comment);
-        let doc = bson::to_bson(&comment)?;
+    pub async fn create_comment(&self, comment: NewComment, actor: Option<&str>) -> Result<Comment, Box<dyn Error>> {
+        let _timer = db_timer("create_comment");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let mut comment = Comment::from(comment);
+        if let Some(parent_id) = &comment.parent_id {
+            let parent = collection.find_one(doc! {"_id": bson::oid::ObjectId::parse_str(parent_id)?}, None).await?;
+            let parent: Comment = match parent {
+                Some(parent) => bson::from_bson(bson::Bson::Document(parent))?,
+                None => return Err(HttpError::new(Status::NotFound, "Parent comment not found").into()),
+            };
+            if parent.book_id != comment.book_id {
+                return Err(HttpError::new(Status::UnprocessableEntity, "Parent comment belongs to another book").into());
+            }
+        }
+        comment.id = Some(bson::oid::ObjectId::new());
+        let now = bson::DateTime::now().to_chrono();
+        comment.created_at = now;
+        comment.updated_at = now;
+        comment.hold_if_blocked(&self.config.moderation_words);
+        let doc = to_stored_bson(&comment)?;
         let doc = doc.as_document().unwrap();
         collection.insert_one(doc.clone(), None).await?;
+        self.audit(actor, AuditAction::Create, AuditEntity::Comment, &comment.id.unwrap().to_hex(), None, Some(doc)).await?;
+        if comment.status == CommentStatus::Held {
+            self.log_moderation_action(ModerationAction {
+                comment_id: comment.id.unwrap().to_hex(),
+                moderator_id: None,
+                action: ModerationKind::Hold,
+                reason: Some("Matched the moderation word list".to_string()),
+                date: chrono::Utc::now(),
+            }).await?;
+        }
         Ok(comment)
     }
 
     ///
     /// # get all comment from database
-    /// this function return all comment from mongo database and return a vector of comment or an error
+    /// this function return all visible comment from mongo database and return a vector of comment or an error
     /// # Arguments
     /// * `self` - the mongo struct
+    /// * `time` - the created_at/updated_at sorting and filtering
     /// # Return
     /// * `Result<Vec<Comment>, Box<dyn Error>>` - a vector of comment or an error
     ///
-    pub async fn get_all_comments(&self) -> Result<Vec<Comment>, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("comments");
-        let mut cursor = collection.find(None, None).await?;
+    pub async fn get_all_comments(&self, time: &TimeFilter) -> Result<Vec<Comment>, Box<dyn Error>> {
+        let _timer = db_timer("get_all_comments");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let (filter, options) = time_query(visible_comments(doc! {}), time)?;
+        let mut cursor = collection.find(filter, options).await?;
         let mut comments = Vec::new();
         while let Some(result) = cursor.next().await {
             let comment = bson::from_bson(bson::Bson::Document(result?))?;
@@ -449,7 +1336,7 @@ impl Mongo {
 
     ///
     /// # get all comment with book id from database
-    /// this function return all comment with book id from mongo database and return a vector of comment or an error
+    /// this function return all visible comment with book id from mongo database and return a vector of comment or an error
     /// # Arguments
     /// * `self` - the mongo struct
     /// * `book_id` - the id of the book
@@ -457,8 +1344,9 @@ impl Mongo {
     /// * `Result<Vec<Comment>, Box<dyn Error>>` - a vector of comment or an error
     ///
     pub async fn get_all_comments_with_book_id(&self, book_id: &str) -> Result<Vec<Comment>, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("comments");
-        let mut cursor = collection.find(doc! {"book_id": book_id}, None).await?;
+        let _timer = db_timer("get_all_comments_with_book_id");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let mut cursor = collection.find(visible_comments(doc! {"book_id": book_id}), None).await?;
         let mut comments = Vec::new();
         while let Some(result) = cursor.next().await {
             let comment = bson::from_bson(bson::Bson::Document(result?))?;
@@ -468,48 +1356,403 @@ impl Mongo {
     }
 
     ///
-    /// # get all comment with user id from database
-    /// this function return all comment with user id from mongo database and return a vector of comment or an error
+    /// # get comment threads with book id from database
+    /// this function return the visible top level comments of a book with their nested replies, sorted and paginated, or an error
+    /// replies are always listed oldest first under their parent, replies of a hidden comment are not listed
     /// # Arguments
     /// * `self` - the mongo struct
-    /// * `user_id` - the id of the user
+    /// * `book_id` - the id of the book
+    /// * `sort` - the order of the top level comments
+    /// * `page` - the page number, starting at 1
+    /// * `per_page` - the number of top level comments per page
     /// # Return
-    /// * `Result<Vec<Comment>, Box<dyn Error>>` - a vector of comment or an error
+    /// * `Result<Vec<CommentThread>, Box<dyn Error>>` - a vector of comment thread or an error
     ///
-    pub async fn get_all_comments_with_user_id(&self, user_id: &str) -> Result<Vec<Comment>, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("comments");
-        let mut cursor = collection.find(doc! {"user_id": user_id}, None).await?;
-        let mut comments = Vec::new();
+    pub async fn get_comment_threads_with_book_id(&self, book_id: &str, sort: CommentSort, page: u64, per_page: u64) -> Result<Vec<CommentThread>, Box<dyn Error>> {
+        let _timer = db_timer("get_comment_threads_with_book_id");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let options = FindOptions::builder()
+            .sort(sort.order())
+            .skip(page_skip(page, per_page))
+            .limit(per_page as i64)
+            .build();
+        let mut cursor = collection.find(visible_comments(doc! {"book_id": book_id, "parent_id": null}), options).await?;
+        let mut roots: Vec<Comment> = Vec::new();
         while let Some(result) = cursor.next().await {
             let comment = bson::from_bson(bson::Bson::Document(result?))?;
-            comments.push(comment);
+            roots.push(comment);
         }
-        Ok(comments)
+
+        // only the replies under the roots of the page, level by level
+        let mut replies: Vec<Comment> = Vec::new();
+        let mut parent_ids: Vec<String> = roots.iter().filter_map(|root| root.id.map(|id| id.to_hex())).collect();
+        while !parent_ids.is_empty() {
+            let filter = visible_comments(doc! {"book_id": book_id, "parent_id": {"$in": &parent_ids}});
+            let mut cursor = collection.find(filter, None).await?;
+            parent_ids = Vec::new();
+            while let Some(result) = cursor.next().await {
+                let reply: Comment = bson::from_bson(bson::Bson::Document(result?))?;
+                parent_ids.extend(reply.id.map(|id| id.to_hex()));
+                replies.push(reply);
+            }
+        }
+        Ok(CommentThread::build(roots, replies))
     }
 
     ///
-    /// # get rating by book id from database
-    /// this function return rating by book id from mongo database and return a f64 or an error
+    /// # vote a comment as helpful in database
+    /// this function add the vote of a user on a comment and return the comment or an error
+    /// a user can only vote once for a given comment, held, hidden and trashed comments are not voted for
     /// # Arguments
     /// * `self` - the mongo struct
-    /// * `book_id` - the id of the book
+    /// * `id` - the id of the comment
+    /// * `user_id` - the id of the user
     /// # Return
-    /// * `Result<f64, Box<dyn Error>>` - a f64 or an error
+    /// * `Result<Comment, Box<dyn Error>>` - a comment, `404` for an unknown user or comment,
+    ///   `403` for the own comment of the user or `409` when they already voted
     ///
-    pub async fn calculate_rating_by_book_id(&self, book_id: &str) -> Result<f64, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("comments");
-        let mut cursor = collection.find(doc! {"book_id": book_id}, None).await?;
-        let mut comments: Vec<Comment> = Vec::new();
-        while let Some(result) = cursor.next().await {
-            let comment = bson::from_bson(bson::Bson::Document(result?))?;
-            comments.push(comment);
+    pub async fn vote_comment_helpful(&self, id: &str, user_id: &str) -> Result<Comment, Box<dyn Error>> {
+        let _timer = db_timer("vote_comment_helpful");
+        if self.find_user(user_id).await?.is_none() {
+            return Err(HttpError::new(Status::NotFound, "User not found").into());
         }
-        let mut sum = 0.0;
-        for comment in &comments {
-            sum += comment.clone().rating as f64;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let oid = parse_id(id, "Comment")?;
+        let before = match collection.find_one(visible_comments(doc! {"_id": oid}), None).await? {
+            Some(before) => before,
+            None => return Err(HttpError::new(Status::NotFound, "Comment not found").into()),
+        };
+        let comment: Comment = bson::from_bson(bson::Bson::Document(before.clone()))?;
+        if comment.user_id == user_id {
+            return Err(HttpError::new(Status::Forbidden, "Cannot vote for your own comment").into());
         }
-        Ok(sum / comments.len() as f64)
-    }
+        let result = collection.update_one(
+            visible_comments(doc! {"_id": oid, "helpful_votes": {"$ne": user_id}}),
+            doc! {"$push": {"helpful_votes": user_id}, "$inc": {"helpful_count": 1}},
+            None,
+        ).await?;
+        if result.modified_count == 0 {
+            return Err(HttpError::new(Status::Conflict, "Comment already voted").into());
+        }
+        let after = collection.find_one(doc! {"_id": oid}, None).await?.unwrap();
+        self.audit(Some(user_id), AuditAction::Vote, AuditEntity::Comment, id, Some(&before), Some(&after)).await?;
+        let comment = bson::from_bson(bson::Bson::Document(after))?;
+        Ok(comment)
+    }
+
+    ///
+    /// # withdraw a helpful vote in database
+    /// this function remove the vote of a user on a comment and return the comment or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the comment
+    /// * `user_id` - the id of the user
+    /// # Return
+    /// * `Result<Comment, Box<dyn Error>>` - a comment, or `404` for a comment that is not visible or not voted by the user
+    ///
+    pub async fn unvote_comment_helpful(&self, id: &str, user_id: &str) -> Result<Comment, Box<dyn Error>> {
+        let _timer = db_timer("unvote_comment_helpful");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let oid = parse_id(id, "Comment")?;
+        let before = match collection.find_one(visible_comments(doc! {"_id": oid}), None).await? {
+            Some(before) => before,
+            None => return Err(HttpError::new(Status::NotFound, "Comment not found").into()),
+        };
+        let result = collection.update_one(
+            visible_comments(doc! {"_id": oid, "helpful_votes": user_id}),
+            doc! {"$pull": {"helpful_votes": user_id}, "$inc": {"helpful_count": -1}},
+            None,
+        ).await?;
+        if result.modified_count == 0 {
+            return Err(HttpError::new(Status::NotFound, "Comment not voted").into());
+        }
+        let after = collection.find_one(doc! {"_id": oid}, None).await?.unwrap();
+        self.audit(Some(user_id), AuditAction::Vote, AuditEntity::Comment, id, Some(&before), Some(&after)).await?;
+        let comment = bson::from_bson(bson::Bson::Document(after))?;
+        Ok(comment)
+    }
+
+    ///
+    /// # flag a comment in database
+    /// this function add a flag with a reason to a comment, putting it in the moderation queue, and return the comment or an error
+    /// a user can only flag a given comment once
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the comment
+    /// * `user_id` - the id of the user flagging the comment
+    /// * `reason` - why the comment is flagged
+    /// # Return
+    /// * `Result<Comment, Box<dyn Error>>` - a comment, `422` without reason, `404` for an unknown user or comment
+    ///   or `409` when the user already flagged it
+    ///
+    pub async fn flag_comment(&self, id: &str, user_id: &str, reason: String) -> Result<Comment, Box<dyn Error>> {
+        let _timer = db_timer("flag_comment");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let oid = parse_id(id, "Comment")?;
+        if reason.trim().is_empty() {
+            return Err(HttpError::new(Status::UnprocessableEntity, "A reason is required to flag a comment").into());
+        }
+        if self.find_user(user_id).await?.is_none() {
+            return Err(HttpError::new(Status::NotFound, "User not found").into());
+        }
+        let flag = CommentFlag {
+            user_id: user_id.to_string(),
+            reason,
+            flagged_at: chrono::Utc::now(),
+        };
+        let before = match collection.find_one(not_deleted(doc! {"_id": oid}), None).await? {
+            Some(before) => before,
+            None => return Err(HttpError::new(Status::NotFound, "Comment not found").into()),
+        };
+        let result = collection.update_one(
+            not_deleted(doc! {"_id": oid, "flags.user_id": {"$ne": user_id}}),
+            doc! {"$push": {"flags": to_stored_bson(&flag)?}},
+            None,
+        ).await?;
+        if result.matched_count == 0 {
+            return Err(HttpError::new(Status::Conflict, "Comment already flagged").into());
+        }
+        let after = collection.find_one(doc! {"_id": oid}, None).await?.unwrap();
+        self.audit(Some(user_id), AuditAction::Flag, AuditEntity::Comment, id, Some(&before), Some(&after)).await?;
+        let comment = bson::from_bson(bson::Bson::Document(after))?;
+        Ok(comment)
+    }
+
+    ///
+    /// # get the moderation queue from database
+    /// this function return the held comments and the visible comments that have been flagged, or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `actor` - the id of the librarian asking for the queue
+    /// # Return
+    /// * `Result<Vec<Comment>, Box<dyn Error>>` - a vector of comment, `403` unless a librarian asks, or an error
+    ///
+    pub async fn get_moderation_queue(&self, actor: Option<&str>) -> Result<Vec<Comment>, Box<dyn Error>> {
+        let _timer = db_timer("get_moderation_queue");
+        self.ensure_librarian(actor, "moderate comments").await?;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let options = FindOptions::builder().sort(doc! {"_id": 1}).build();
+        let mut cursor = collection.find(moderation_queue(), options).await?;
+        let mut comments = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let comment = bson::from_bson(bson::Bson::Document(result?))?;
+            comments.push(comment);
+        }
+        Ok(comments)
+    }
+
+    ///
+    /// # moderate a comment in database
+    /// this function hide, restore or delete a comment, record the action and return the comment or an error
+    /// restoring a comment clears its flags, deleting a comment moves it to the trash
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the comment
+    /// * `actor` - the id of the librarian
+    /// * `action` - the moderation action
+    /// * `reason` - an optional note kept with the action
+    /// # Return
+    /// * `Result<Comment, Box<dyn Error>>` - the comment as it was after the action, `403` unless a librarian asks, or an error
+    ///
+    pub async fn moderate_comment(&self, id: &str, actor: Option<&str>, action: ModerationKind, reason: Option<String>) -> Result<Comment, Box<dyn Error>> {
+        let _timer = db_timer("moderate_comment");
+        let moderator_id = self.ensure_librarian(actor, "moderate comments").await?;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let oid = parse_id(id, "Comment")?;
+        let mut comment: Comment = match collection.find_one(not_deleted(doc! {"_id": oid}), None).await? {
+            Some(comment) => bson::from_bson(bson::Bson::Document(comment))?,
+            None => return Err(HttpError::new(Status::NotFound, "Comment not found").into()),
+        };
+        let before = to_stored_document(&comment)?;
+        let changes = comment.moderate(action, moderator_id, bson::DateTime::now().to_chrono())?;
+        collection.update_one(doc! {"_id": oid}, doc! {"$set": changes}, None).await?;
+        let audit_action = match action {
+            ModerationKind::Delete => AuditAction::Delete,
+            _ => AuditAction::Moderate,
+        };
+        self.audit(Some(moderator_id), audit_action, AuditEntity::Comment, id, Some(&before), Some(&to_stored_document(&comment)?)).await?;
+
+        self.log_moderation_action(ModerationAction {
+            comment_id: id.to_string(),
+            moderator_id: Some(moderator_id.to_string()),
+            action,
+            reason,
+            date: chrono::Utc::now(),
+        }).await?;
+        Ok(comment)
+    }
+
+    ///
+    /// # get moderation actions from database
+    /// this function return the recorded moderation actions, most recent first, or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `actor` - the id of the librarian asking for the actions
+    /// * `comment_id` - only return the actions on this comment
+    /// # Return
+    /// * `Result<Vec<ModerationAction>, Box<dyn Error>>` - a vector of moderation action, `403` unless a librarian asks, or an error
+    ///
+    pub async fn get_moderation_actions(&self, actor: Option<&str>, comment_id: Option<&str>) -> Result<Vec<ModerationAction>, Box<dyn Error>> {
+        let _timer = db_timer("get_moderation_actions");
+        self.ensure_librarian(actor, "moderate comments").await?;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.moderation_actions);
+        let filter = match comment_id {
+            Some(comment_id) => doc! {"comment_id": comment_id},
+            None => doc! {},
+        };
+        let options = FindOptions::builder().sort(doc! {"date": -1}).build();
+        let mut cursor = collection.find(filter, options).await?;
+        let mut actions = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let action = bson::from_bson(bson::Bson::Document(result?))?;
+            actions.push(action);
+        }
+        Ok(actions)
+    }
+
+    async fn log_moderation_action(&self, action: ModerationAction) -> Result<(), Box<dyn Error>> {
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.moderation_actions);
+        let doc = to_stored_bson(&action)?;
+        let doc = doc.as_document().unwrap();
+        collection.insert_one(doc.clone(), None).await?;
+        Ok(())
+    }
+
+    ///
+    /// # find user
+    /// this function return a user that is not deleted
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `user_id` - the id of the user
+    /// # Return
+    /// * `Result<Option<User>, Box<dyn Error>>` - the user, `None` for a malformed id or a missing or deleted user
+    ///
+    async fn find_user(&self, user_id: &str) -> Result<Option<User>, Box<dyn Error>> {
+        let Ok(oid) = bson::oid::ObjectId::parse_str(user_id) else { return Ok(None) };
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
+        match collection.find_one(not_deleted(doc! {"_id": oid}), None).await? {
+            Some(user) => Ok(Some(bson::from_bson(bson::Bson::Document(user))?)),
+            None => Ok(None),
+        }
+    }
+
+    ///
+    /// # ensure librarian
+    /// this function check that the user performing a request is a librarian
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `actor` - the id of the user performing the request
+    /// * `action` - what only librarians can do, for the error message
+    /// # Return
+    /// * `Result<&str, Box<dyn Error>>` - the id of the librarian, or `403` when the user is missing, unknown or not a librarian
+    ///
+    async fn ensure_librarian<'a>(&self, actor: Option<&'a str>, action: &str) -> Result<&'a str, Box<dyn Error>> {
+        let forbidden = || HttpError::new(Status::Forbidden, format!("Only librarians can {}", action));
+        let Some(user_id) = actor else { return Err(forbidden().into()) };
+        match self.find_user(user_id).await? {
+            Some(user) if user.role == "librarian" => Ok(user_id),
+            _ => Err(forbidden().into()),
+        }
+    }
+
+    ///
+    /// # get all comment with user id from database
+    /// this function return all visible comment with user id from mongo database and return a vector of comment or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `user_id` - the id of the user
+    /// * `time` - the created_at/updated_at sorting and filtering
+    /// # Return
+    /// * `Result<Vec<Comment>, Box<dyn Error>>` - a vector of comment or an error
+    ///
+    pub async fn get_all_comments_with_user_id(&self, user_id: &str, time: &TimeFilter) -> Result<Vec<Comment>, Box<dyn Error>> {
+        let _timer = db_timer("get_all_comments_with_user_id");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let (filter, options) = time_query(visible_comments(doc! {"user_id": user_id}), time)?;
+        let mut cursor = collection.find(filter, options).await?;
+        let mut comments = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let comment = bson::from_bson(bson::Bson::Document(result?))?;
+            comments.push(comment);
+        }
+        Ok(comments)
+    }
+
+    ///
+    /// # get rating by book id from database
+    /// this function return rating by book id from mongo database and return a f64 or an error
+    /// replies are not reviews and are ignored, as are held and hidden comments
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `book_id` - the id of the book
+    /// # Return
+    /// * `Result<f64, Box<dyn Error>>` - a f64 or an error
+    ///
+    pub async fn calculate_rating_by_book_id(&self, book_id: &str) -> Result<f64, Box<dyn Error>> {
+        let _timer = db_timer("calculate_rating_by_book_id");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let mut cursor = collection.find(visible_comments(doc! {"book_id": book_id, "parent_id": null}), None).await?;
+        let mut comments: Vec<Comment> = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let comment = bson::from_bson(bson::Bson::Document(result?))?;
+            comments.push(comment);
+        }
+        let mut sum = 0.0;
+        for comment in &comments {
+            sum += comment.rating.unwrap_or_default() as f64;
+        }
+        Ok(sum / comments.len() as f64)
+    }
+
+    ///
+    /// # get rating by work id from database
+    /// this function return the average rating of the reviews of all the editions of a work, or an error
+    /// replies are not reviews and are ignored, as are held and hidden comments
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `work_id` - the id of the work
+    /// # Return
+    /// * `Result<Option<f64>, Box<dyn Error>>` - the rating, `None` without review, or `404` when there is no such work
+    ///
+    pub async fn calculate_rating_by_work_id(&self, work_id: &str) -> Result<Option<f64>, Box<dyn Error>> {
+        let _timer = db_timer("calculate_rating_by_work_id");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.works);
+        let found = match bson::oid::ObjectId::parse_str(work_id) {
+            Ok(id) => collection.find_one(doc! {"_id": id}, None).await?.is_some(),
+            Err(_) => false,
+        };
+        if !found {
+            return Err(HttpError::new(Status::NotFound, "Work not found").into());
+        }
+        let book_ids: Vec<String> = self.get_editions(work_id).await?.into_iter().map(|edition| edition.book_id).collect();
+        let (rating, _) = average_rating(self.review_totals(&book_ids).await?.into_values());
+        Ok(rating)
+    }
+
+    ///
+    /// # review totals
+    /// this function return the sum of the ratings and the number of reviews of books, a book without review is left out
+    /// replies are not reviews and are ignored, as are held and hidden comments
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `book_ids` - the ids of the books
+    /// # Return
+    /// * `Result<HashMap<String, (f64, u64)>, Box<dyn Error>>` - the sum and the number of reviews, keyed by book id, or an error
+    ///
+    async fn review_totals(&self, book_ids: &[String]) -> Result<HashMap<String, (f64, u64)>, Box<dyn Error>> {
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let pipeline = vec![
+            doc! { "$match": visible_comments(doc! {"book_id": {"$in": book_ids}, "parent_id": null}) },
+            doc! { "$group": { "_id": "$book_id", "sum": { "$sum": { "$toDouble": "$rating" } }, "count": { "$sum": 1 } } },
+        ];
+        let mut totals = HashMap::new();
+        let mut cursor = collection.aggregate(pipeline, None).await?;
+        while let Some(result) = cursor.next().await {
+            let group = result?;
+            totals.insert(group.get_str("_id")?.to_string(), (group.get_f64("sum")?, group.get_i32("count")? as u64));
+        }
+        Ok(totals)
+    }
 
     ///
     /// # get all books by operator rating from database
@@ -521,8 +1764,9 @@ impl Mongo {
     /// * `Result<Vec<Book>, Box<dyn Error>>` - a vector of book or an error
     ///
     pub async fn get_all_books_by_operator_rating(&self, operator_rating: OperatorRating) -> Result<Vec<Book>, Box<dyn Error>> {
+        let _timer = db_timer("get_all_books_by_operator_rating");
 
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("books");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
 
         let operator = match operator_rating {
             OperatorRating::Equal(value) => doc! { "$eq": value },
@@ -534,6 +1778,11 @@ impl Mongo {
         };
 
         let pipeline = vec![
+            doc! {
+                "$match": {
+                    "deleted": null
+                }
+            },
             doc! {
                 "$addFields": {
                     "book_id_str": { "$toString": "$_id" }
@@ -541,9 +1790,18 @@ impl Mongo {
             },
             doc! {
                 "$lookup": {
-                    "from": "comments",
-                    "localField": "book_id_str",
-                    "foreignField": "book_id",
+                    "from": self.config.collections.comments.as_str(),
+                    "let": { "book_id": "$book_id_str" },
+                    "pipeline": [
+                        {
+                            "$match": {
+                                "$expr": { "$eq": ["$book_id", "$$book_id"] },
+                                "parent_id": null,
+                                "status": { "$nin": ["held", "hidden"] },
+                                "deleted": null
+                            }
+                        }
+                    ],
                     "as": "comments"
                 }
             },
@@ -556,13 +1814,8 @@ impl Mongo {
             doc! {
                 "$group": {
                     "_id": "$_id",
-                    "title": { "$first": "$title" },
-                    "author": { "$first": "$author" },
-                    "year": { "$first": "$year" },
-                    "resume": { "$first": "$resume" },
-                    "availability": { "$first": "$availability" },
-                    "average_rating": { "$avg": "$comments.rating" },
-                    "gender_id": { "$first": "$gender_id" }
+                    "book": { "$first": "$$ROOT" },
+                    "average_rating": { "$avg": "$comments.rating" }
                 }
             },
             doc! {
@@ -571,17 +1824,13 @@ impl Mongo {
                 }
             },
             doc! {
-                "$project": {
-                    "_id": 1,
-                    "title": 1,
-                    "author": 1,
-                    "year": 1,
-                    "resume": 1,
-                    "availability": 1,
-                    "average_rating": 1,
-                    "gender_id": 1
+                "$replaceRoot": {
+                    "newRoot": "$book"
                 }
             },
+            doc! {
+                "$unset": ["book_id_str", "comments"]
+            },
         ];
 
 
@@ -604,20 +1853,25 @@ impl Mongo {
     /// # Arguments
     /// * `self` - the mongo struct
     /// * `genre` - the genre to create
+    /// * `actor` - the id of the user performing the change
     /// # Return
     /// * `Result<Genre, Box<dyn Error>>` - a genre or an error
     ///
-    pub async fn create_genre(&self, genre: Genre) -> Result<Genre, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("genres");
-
-        let doc = bson::to_bson(&genre)?;
+    pub async fn create_genre(&self, genre: NewGenre, actor: Option<&str>) -> Result<Genre, Box<dyn Error>> {
+        let _timer = db_timer("create_genre");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.genres);
+        let mut genre = Genre::from(genre);
+        let now = bson::DateTime::now().to_chrono();
+        genre.created_at = now;
+        genre.updated_at = now;
+
+        let doc = to_stored_bson(&genre)?;
         let doc = doc.as_document().unwrap();
 
-        if let Some(_) = collection.find_one(doc! {"name": &genre.name}, None).await? {
-            return Err("Genre already exist".into());
-        }
-
-        collection.insert_one(doc.clone(), None).await?;
+        let result = collection.insert_one(doc.clone(), None).await
+            .map_err(|e| conflict_on_duplicate(e, "Genre already exist"))?;
+        let id = result.inserted_id.as_object_id().unwrap().to_hex();
+        self.audit(actor, AuditAction::Create, AuditEntity::Genre, &id, None, Some(doc)).await?;
         Ok(genre)
     }
 
@@ -626,12 +1880,15 @@ impl Mongo {
     /// this function return all genres from mongo database and return a vector of genre or an error
     /// # Arguments
     /// * `self` - the mongo struct
+    /// * `time` - the created_at/updated_at sorting and filtering
     /// # Return
     /// * `Result<Vec<Genre>, Box<dyn Error>>` - a vector of genre or an error
     ///
-    pub async fn get_all_genres(&self) -> Result<Vec<Genre>, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("genres");
-        let mut cursor = collection.find(None, None).await?;
+    pub async fn get_all_genres(&self, time: &TimeFilter) -> Result<Vec<Genre>, Box<dyn Error>> {
+        let _timer = db_timer("get_all_genres");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.genres);
+        let (filter, options) = time_query(doc! {}, time)?;
+        let mut cursor = collection.find(filter, options).await?;
         let mut genres = Vec::new();
         while let Some(result) = cursor.next().await {
             let genre = bson::from_bson(bson::Bson::Document(result?))?;
@@ -650,7 +1907,8 @@ impl Mongo {
     /// * `Result<Vec<Book>, Box<dyn Error>>` - a vector of book or an error
     ///
     pub async fn get_books_by_genre(&self, genre_name: &str) -> Result<Vec<Book>, Box<dyn Error>> {
-        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection("genres");
+        let _timer = db_timer("get_books_by_genre");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.genres);
         let pipeline = vec![
             doc! {
                 "$match": {
@@ -659,25 +1917,15 @@ impl Mongo {
             },
             doc! {
                 "$lookup": {
-                    "from": "books",
+                    "from": self.config.collections.books.as_str(),
                     "let": { "genre_name": "$name", "genre_id": { "$toString": "$_id" } },
                     "pipeline": [
                         {
                             "$match": {
                                 "$expr": {
-                                    "$eq": ["$gender_id", "$$genre_id"]
-                                }
-                            }
-                        },
-                        {
-                            "$project": {
-                                "_id": 1,
-                                "title": 1,
-                                "author": 1,
-                                "year": 1,
-                                "resume": 1,
-                                "availability": 1,
-                                "gender_id": "$$genre_name"
+                                    "$in": ["$$genre_id", { "$ifNull": ["$genre_ids", []] }]
+                                },
+                                "deleted": null
                             }
                         }
                     ],
@@ -703,4 +1951,1250 @@ impl Mongo {
         Ok(books)
     }
     // end genre
+
+    // author
+
+    ///
+    /// # create author in database
+    /// this function create an author in mongo database and return the author or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `author` - the author to create
+    /// * `actor` - the id of the user performing the change
+    /// # Return
+    /// * `Result<Author, Box<dyn Error>>` - the author, with its id, or an error
+    ///
+    pub async fn create_author(&self, author: NewAuthor, actor: Option<&str>) -> Result<Author, Box<dyn Error>> {
+        let _timer = db_timer("create_author");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.authors);
+        let mut author = Author::from(author);
+        let now = bson::DateTime::now().to_chrono();
+        author.created_at = now;
+        author.updated_at = now;
+        let mut doc = to_stored_document(&author)?;
+        doc.insert("name_keys", name_keys(&author.name, &author.aliases));
+        let result = collection.insert_one(doc.clone(), None).await?;
+        author.id = result.inserted_id.as_object_id();
+        let id = result.inserted_id.as_object_id().unwrap().to_hex();
+        self.audit(actor, AuditAction::Create, AuditEntity::Author, &id, None, Some(&doc)).await?;
+        Ok(author)
+    }
+
+    ///
+    /// # get authors from database
+    /// this function return the authors whose name or an alias contains a text, or every author, or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `name` - the text searched in the names and aliases, ignoring case, punctuation and spaces
+    /// * `time` - the created_at/updated_at sorting and filtering
+    /// # Return
+    /// * `Result<Vec<Author>, Box<dyn Error>>` - a vector of author or an error
+    ///
+    pub async fn get_authors(&self, name: Option<&str>, time: &TimeFilter) -> Result<Vec<Author>, Box<dyn Error>> {
+        let _timer = db_timer("get_authors");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.authors);
+        let filter = match name {
+            Some(name) => doc! {"name_keys": {"$regex": escape_regex(&name_key(name))}},
+            None => doc! {},
+        };
+        let (filter, options) = time_query(filter, time)?;
+        let mut cursor = collection.find(filter, options).await?;
+        let mut authors = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let author = bson::from_bson(bson::Bson::Document(result?))?;
+            authors.push(author);
+        }
+        Ok(authors)
+    }
+
+    ///
+    /// # get author page from database
+    /// this function return an author with the books crediting them, by year, and the rating of their reviews
+    /// replies are not reviews and are ignored, as are held and hidden comments
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the author
+    /// # Return
+    /// * `Result<AuthorPage, Box<dyn Error>>` - the author page, or `404` when there is no such author
+    ///
+    pub async fn get_author_page(&self, id: &str) -> Result<AuthorPage, Box<dyn Error>> {
+        let _timer = db_timer("get_author_page");
+        let db = self.client.database(&self.config.db_name);
+        let collection: Collection<Document> = db.collection(&self.config.collections.authors);
+        let collection_book: Collection<Document> = db.collection(&self.config.collections.books);
+        let author: Author = match collection.find_one(doc! {"_id": parse_id(id, "Author")?}, None).await? {
+            Some(author) => bson::from_bson(bson::Bson::Document(author))?,
+            None => return Err(HttpError::new(Status::NotFound, "Author not found").into()),
+        };
+
+        let options = FindOptions::builder().sort(doc! {"year": 1, "title": 1}).build();
+        let mut cursor = collection_book.find(not_deleted(doc! {"authors.author_id": id}), options).await?;
+        let mut books = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let document = result?;
+            let book_id = document.get_object_id("_id")?.to_hex();
+            let book: Book = bson::from_bson(bson::Bson::Document(document))?;
+            books.push((book_id, book));
+        }
+
+        let book_ids: Vec<String> = books.iter().map(|(book_id, _)| book_id.clone()).collect();
+        let ratings = self.review_totals(&book_ids).await?;
+
+        let mut bibliography = Vec::new();
+        for (book_id, book) in &books {
+            let (sum, reviews) = ratings.get(book_id).copied().unwrap_or_default();
+            // a book is listed once for every role of the author in it
+            for credit in book.authors.iter().filter(|credit| credit.author_id == id) {
+                bibliography.push(BibliographyEntry {
+                    book_id: book_id.clone(),
+                    title: book.title.clone(),
+                    year: book.year,
+                    role: credit.role,
+                    rating: (reviews > 0).then(|| sum / reviews as f64),
+                    reviews,
+                });
+            }
+        }
+        let (sum, reviews) = ratings.values().fold((0.0, 0), |(sum, reviews), (book_sum, book_reviews)| (sum + book_sum, reviews + book_reviews));
+        Ok(AuthorPage { author, bibliography, rating: (reviews > 0).then(|| sum / reviews as f64), reviews })
+    }
+
+    ///
+    /// # update author in database
+    /// this function update an author with id in mongo database and return the author or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the author
+    /// * `author` - the fields to set (HashMap<&str, Value>)
+    /// * `actor` - the id of the user performing the change
+    /// # Return
+    /// * `Result<Author, Box<dyn Error>>` - the author, `404` when there is no such author
+    ///   or `422` when the update would make them die before being born
+    ///
+    pub async fn update_author(&self, id: &str, author: HashMap<&str, Value>, actor: Option<&str>) -> Result<Author, Box<dyn Error>> {
+        let _timer = db_timer("update_author");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.authors);
+        let oid = parse_id(id, "Author")?;
+        let before = match collection.find_one(doc! {"_id": oid}, None).await? {
+            Some(before) => before,
+            None => return Err(HttpError::new(Status::NotFound, "Author not found").into()),
+        };
+        if author.is_empty() {
+            return Ok(bson::from_bson(bson::Bson::Document(before))?);
+        }
+        let year = |field: &str| match author.get(field) {
+            Some(Value::Int(year)) => Some(*year),
+            _ => before.get_i32(field).ok(),
+        };
+        if let Err(error) = life(year("birth_year"), year("death_year")) {
+            let message = error.message.map(|message| message.to_string()).unwrap_or_default();
+            return Err(FieldErrors(BTreeMap::from([("death_year".to_string(), vec![message])])).into());
+        }
+        let mut query = doc! {};
+        for (key, value) in author {
+            match value {
+                Value::Int(i) => query.insert(key, i),
+                Value::Text(t) => query.insert(key, t),
+                Value::TextList(l) => query.insert(key, l),
+                _ => continue,
+            };
+        }
+        if query.contains_key("name") || query.contains_key("aliases") {
+            let current: Author = bson::from_bson(bson::Bson::Document(before.clone()))?;
+            let name = query.get_str("name").map(str::to_string).unwrap_or(current.name);
+            let aliases = match query.get_array("aliases") {
+                Ok(aliases) => aliases.iter().filter_map(bson::Bson::as_str).map(str::to_string).collect(),
+                Err(_) => current.aliases,
+            };
+            query.insert("name_keys", name_keys(&name, &aliases));
+        }
+        query.insert("updated_at", bson::DateTime::now());
+        collection.update_one(doc! {"_id": oid}, doc! {"$set": query}, None).await?;
+        let after = collection.find_one(doc! {"_id": oid}, None).await?.unwrap();
+        self.audit(actor, AuditAction::Update, AuditEntity::Author, id, Some(&before), Some(&after)).await?;
+        Ok(bson::from_bson(bson::Bson::Document(after))?)
+    }
+
+    ///
+    /// # delete author from database
+    /// this function delete an author with id from mongo database and return the author or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the author
+    /// * `actor` - the id of the user performing the change
+    /// # Return
+    /// * `Result<Author, Box<dyn Error>>` - the deleted author, `404` when there is no such author
+    ///   or `409` while a book, even in the trash, credits them
+    ///
+    pub async fn delete_author(&self, id: &str, actor: Option<&str>) -> Result<Author, Box<dyn Error>> {
+        let _timer = db_timer("delete_author");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.authors);
+        let collection_book: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let oid = parse_id(id, "Author")?;
+        let before = match collection.find_one(doc! {"_id": oid}, None).await? {
+            Some(before) => before,
+            None => return Err(HttpError::new(Status::NotFound, "Author not found").into()),
+        };
+        if collection_book.find_one(doc! {"authors.author_id": id}, None).await?.is_some() {
+            return Err(HttpError::new(Status::Conflict, "Author is credited on books, remove them from the books first").into());
+        }
+        collection.delete_one(doc! {"_id": oid}, None).await?;
+        self.audit(actor, AuditAction::Delete, AuditEntity::Author, id, Some(&before), None).await?;
+        Ok(bson::from_bson(bson::Bson::Document(before))?)
+    }
+
+    ///
+    /// # check authors
+    /// this function check that the authors credited on a book exist
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `author_ids` - the ids of the authors
+    /// # Return
+    /// * `Result<(), Box<dyn Error>>` - nothing, or a `422` naming the unknown authors
+    ///
+    async fn check_authors(&self, author_ids: &[String]) -> Result<(), Box<dyn Error>> {
+        if author_ids.is_empty() {
+            return Ok(());
+        }
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.authors);
+        let oids = author_ids.iter().map(bson::oid::ObjectId::parse_str).collect::<Result<Vec<_>, _>>()?;
+        let mut cursor = collection.find(doc! {"_id": {"$in": oids}}, None).await?;
+        let mut found = HashSet::new();
+        while let Some(result) = cursor.next().await {
+            found.insert(result?.get_object_id("_id")?.to_hex());
+        }
+        let unknown: Vec<String> = author_ids.iter()
+            .filter(|id| !found.contains(*id))
+            .map(|id| format!("unknown author {}", id))
+            .collect();
+        match unknown.is_empty() {
+            true => Ok(()),
+            false => Err(FieldErrors(BTreeMap::from([("authors".to_string(), unknown)])).into()),
+        }
+    }
+    // end author
+
+    // work
+
+    ///
+    /// # create work in database
+    /// this function create a work in mongo database and return the work or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `work` - the work to create
+    /// * `actor` - the id of the user performing the change
+    /// # Return
+    /// * `Result<Work, Box<dyn Error>>` - the work, with its id, or an error
+    ///
+    pub async fn create_work(&self, work: NewWork, actor: Option<&str>) -> Result<Work, Box<dyn Error>> {
+        let _timer = db_timer("create_work");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.works);
+        let mut work = Work::from(work);
+        let now = bson::DateTime::now().to_chrono();
+        work.created_at = now;
+        work.updated_at = now;
+        let doc = to_stored_document(&work)?;
+        let result = collection.insert_one(doc.clone(), None).await?;
+        work.id = result.inserted_id.as_object_id();
+        let id = result.inserted_id.as_object_id().unwrap().to_hex();
+        self.audit(actor, AuditAction::Create, AuditEntity::Work, &id, None, Some(&doc)).await?;
+        Ok(work)
+    }
+
+    ///
+    /// # get works from database
+    /// this function return the works whose title contains a text, or every work, or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `title` - the text searched in the titles, case insensitive
+    /// * `time` - the created_at/updated_at sorting and filtering
+    /// # Return
+    /// * `Result<Vec<Work>, Box<dyn Error>>` - a vector of work or an error
+    ///
+    pub async fn get_works(&self, title: Option<&str>, time: &TimeFilter) -> Result<Vec<Work>, Box<dyn Error>> {
+        let _timer = db_timer("get_works");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.works);
+        let filter = match title {
+            Some(title) => doc! {"title": {"$regex": escape_regex(title), "$options": "i"}},
+            None => doc! {},
+        };
+        let (filter, options) = time_query(filter, time)?;
+        let mut cursor = collection.find(filter, options).await?;
+        let mut works = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let work = bson::from_bson(bson::Bson::Document(result?))?;
+            works.push(work);
+        }
+        Ok(works)
+    }
+
+    ///
+    /// # get work page from database
+    /// this function return a work with its editions, by year, and the rating of the reviews of all of them
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the work
+    /// # Return
+    /// * `Result<WorkPage, Box<dyn Error>>` - the work page, or `404` when there is no such work
+    ///
+    pub async fn get_work_page(&self, id: &str) -> Result<WorkPage, Box<dyn Error>> {
+        let _timer = db_timer("get_work_page");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.works);
+        let work: Work = match collection.find_one(doc! {"_id": parse_id(id, "Work")?}, None).await? {
+            Some(work) => bson::from_bson(bson::Bson::Document(work))?,
+            None => return Err(HttpError::new(Status::NotFound, "Work not found").into()),
+        };
+        let editions = self.get_editions(id).await?;
+        let book_ids: Vec<String> = editions.iter().map(|edition| edition.book_id.clone()).collect();
+        let (rating, reviews) = average_rating(self.review_totals(&book_ids).await?.into_values());
+        Ok(WorkPage { work, editions, rating, reviews })
+    }
+
+    ///
+    /// # get editions from database
+    /// this function return the books that are editions of a work, by year, or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `work_id` - the id of the work
+    /// # Return
+    /// * `Result<Vec<Edition>, Box<dyn Error>>` - the editions or an error
+    ///
+    async fn get_editions(&self, work_id: &str) -> Result<Vec<Edition>, Box<dyn Error>> {
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let options = FindOptions::builder().sort(doc! {"year": 1, "_id": 1}).build();
+        let mut cursor = collection.find(not_deleted(doc! {"work_id": work_id}), options).await?;
+        let mut editions = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let document = result?;
+            let book_id = document.get_object_id("_id")?.to_hex();
+            let book: Book = bson::from_bson(bson::Bson::Document(document))?;
+            editions.push(Edition::new(book_id, &book));
+        }
+        Ok(editions)
+    }
+
+    ///
+    /// # update work in database
+    /// this function update a work with id in mongo database and return the work or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the work
+    /// * `work` - the fields to set (HashMap<&str, Value>)
+    /// * `actor` - the id of the user performing the change
+    /// # Return
+    /// * `Result<Work, Box<dyn Error>>` - the work, or `404` when there is no such work
+    ///
+    pub async fn update_work(&self, id: &str, work: HashMap<&str, Value>, actor: Option<&str>) -> Result<Work, Box<dyn Error>> {
+        let _timer = db_timer("update_work");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.works);
+        let oid = parse_id(id, "Work")?;
+        let before = match collection.find_one(doc! {"_id": oid}, None).await? {
+            Some(before) => before,
+            None => return Err(HttpError::new(Status::NotFound, "Work not found").into()),
+        };
+        if work.is_empty() {
+            return Ok(bson::from_bson(bson::Bson::Document(before))?);
+        }
+        let mut query = doc! {};
+        for (key, value) in work {
+            match value {
+                Value::Int(i) => query.insert(key, i),
+                Value::Text(t) => query.insert(key, t),
+                _ => continue,
+            };
+        }
+        query.insert("updated_at", bson::DateTime::now());
+        collection.update_one(doc! {"_id": oid}, doc! {"$set": query}, None).await?;
+        let after = collection.find_one(doc! {"_id": oid}, None).await?.unwrap();
+        self.audit(actor, AuditAction::Update, AuditEntity::Work, id, Some(&before), Some(&after)).await?;
+        Ok(bson::from_bson(bson::Bson::Document(after))?)
+    }
+
+    ///
+    /// # delete work from database
+    /// this function delete a work with id from mongo database and return the work or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the work
+    /// * `actor` - the id of the user performing the change
+    /// # Return
+    /// * `Result<Work, Box<dyn Error>>` - the deleted work, `404` when there is no such work
+    ///   or `409` while a book, even in the trash, is one of its editions
+    ///
+    pub async fn delete_work(&self, id: &str, actor: Option<&str>) -> Result<Work, Box<dyn Error>> {
+        let _timer = db_timer("delete_work");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.works);
+        let collection_book: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let oid = parse_id(id, "Work")?;
+        let before = match collection.find_one(doc! {"_id": oid}, None).await? {
+            Some(before) => before,
+            None => return Err(HttpError::new(Status::NotFound, "Work not found").into()),
+        };
+        if collection_book.find_one(doc! {"work_id": id}, None).await?.is_some() {
+            return Err(HttpError::new(Status::Conflict, "Work has editions, remove them from the work first").into());
+        }
+        collection.delete_one(doc! {"_id": oid}, None).await?;
+        self.audit(actor, AuditAction::Delete, AuditEntity::Work, id, Some(&before), None).await?;
+        Ok(bson::from_bson(bson::Bson::Document(before))?)
+    }
+
+    ///
+    /// # check work
+    /// this function check that the work a book is an edition of exists
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `work_id` - the id of the work
+    /// # Return
+    /// * `Result<(), Box<dyn Error>>` - nothing, or a `422` when there is no such work
+    ///
+    async fn check_work(&self, work_id: &str) -> Result<(), Box<dyn Error>> {
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.works);
+        match collection.find_one(doc! {"_id": bson::oid::ObjectId::parse_str(work_id)?}, None).await? {
+            Some(_) => Ok(()),
+            None => Err(FieldErrors(BTreeMap::from([("work_id".to_string(), vec![format!("unknown work {}", work_id)])])).into()),
+        }
+    }
+    // end work
+
+    // subject
+
+    ///
+    /// # create subject in database
+    /// this function create a subject heading in mongo database and return the subject or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `subject` - the subject to create
+    /// * `actor` - the id of the user performing the change, who must be a librarian
+    /// # Return
+    /// * `Result<Subject, Box<dyn Error>>` - the subject, with its id, `403` unless a librarian asks or `409` for an existing heading
+    ///
+    pub async fn create_subject(&self, subject: NewSubject, actor: Option<&str>) -> Result<Subject, Box<dyn Error>> {
+        let _timer = db_timer("create_subject");
+        self.ensure_librarian(actor, "manage subjects").await?;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.subjects);
+        let mut subject = Subject::from(subject);
+        let now = bson::DateTime::now().to_chrono();
+        subject.created_at = now;
+        subject.updated_at = now;
+        let doc = to_stored_document(&subject)?;
+        let result = collection.insert_one(doc.clone(), None).await
+            .map_err(|e| conflict_on_duplicate(e, "A subject with this heading already exists"))?;
+        subject.id = result.inserted_id.as_object_id();
+        let id = result.inserted_id.as_object_id().unwrap().to_hex();
+        self.audit(actor, AuditAction::Create, AuditEntity::Subject, &id, None, Some(&doc)).await?;
+        Ok(subject)
+    }
+
+    ///
+    /// # get subjects from database
+    /// this function return the subject headings of the vocabulary or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `time` - the created_at/updated_at sorting and filtering
+    /// # Return
+    /// * `Result<Vec<Subject>, Box<dyn Error>>` - a vector of subject or an error
+    ///
+    pub async fn get_subjects(&self, time: &TimeFilter) -> Result<Vec<Subject>, Box<dyn Error>> {
+        let _timer = db_timer("get_subjects");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.subjects);
+        let (filter, options) = time_query(doc! {}, time)?;
+        let mut cursor = collection.find(filter, options).await?;
+        let mut subjects = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let subject = bson::from_bson(bson::Bson::Document(result?))?;
+            subjects.push(subject);
+        }
+        Ok(subjects)
+    }
+
+    ///
+    /// # get all books by subject from database
+    /// this function return the books with a subject heading, in call number order, books without call number last
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `heading` - the subject heading
+    /// # Return
+    /// * `Result<Vec<Book>, Box<dyn Error>>` - a vector of book, or `404` when there is no such heading
+    ///
+    pub async fn get_books_by_subject(&self, heading: &str) -> Result<Vec<Book>, Box<dyn Error>> {
+        let _timer = db_timer("get_books_by_subject");
+        let db = self.client.database(&self.config.db_name);
+        let collection: Collection<Document> = db.collection(&self.config.collections.subjects);
+        let collection_book: Collection<Document> = db.collection(&self.config.collections.books);
+        let subject = match collection.find_one(doc! {"heading": heading}, None).await? {
+            Some(subject) => subject,
+            None => return Err(HttpError::new(Status::NotFound, "Subject not found").into()),
+        };
+        let pipeline = vec![
+            doc! { "$match": not_deleted(doc! {"subject_ids": subject.get_object_id("_id")?.to_hex()}) },
+            doc! { "$set": { "unshelved": { "$eq": [{ "$ifNull": ["$call_number", null] }, null] } } },
+            doc! { "$sort": { "unshelved": 1, "call_number": 1, "title": 1 } },
+            doc! { "$unset": "unshelved" },
+        ];
+        let mut cursor = collection_book.aggregate(pipeline, None).await?;
+        let mut books = Vec::new();
+        while let Some(result) = cursor.next().await {
+            books.push(bson::from_bson(bson::Bson::Document(result?))?);
+        }
+        Ok(books)
+    }
+
+    ///
+    /// # update subject in database
+    /// this function update the heading or the scope note of a subject with id and return the subject or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the subject
+    /// * `subject` - the fields to set (HashMap<&str, Value>)
+    /// * `actor` - the id of the user performing the change, who must be a librarian
+    /// # Return
+    /// * `Result<Subject, Box<dyn Error>>` - the subject, `403` unless a librarian asks, `404` when there is no such subject
+    ///   or `409` for the heading of another subject
+    ///
+    pub async fn update_subject(&self, id: &str, subject: HashMap<&str, Value>, actor: Option<&str>) -> Result<Subject, Box<dyn Error>> {
+        let _timer = db_timer("update_subject");
+        self.ensure_librarian(actor, "manage subjects").await?;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.subjects);
+        let oid = parse_id(id, "Subject")?;
+        let before = match collection.find_one(doc! {"_id": oid}, None).await? {
+            Some(before) => before,
+            None => return Err(HttpError::new(Status::NotFound, "Subject not found").into()),
+        };
+        if subject.is_empty() {
+            return Ok(bson::from_bson(bson::Bson::Document(before))?);
+        }
+        let mut query = doc! {};
+        for (key, value) in subject {
+            if let Value::Text(t) = value {
+                query.insert(key, t);
+            }
+        }
+        query.insert("updated_at", bson::DateTime::now());
+        collection.update_one(doc! {"_id": oid}, doc! {"$set": query}, None).await
+            .map_err(|e| conflict_on_duplicate(e, "A subject with this heading already exists"))?;
+        let after = collection.find_one(doc! {"_id": oid}, None).await?.unwrap();
+        self.audit(actor, AuditAction::Update, AuditEntity::Subject, id, Some(&before), Some(&after)).await?;
+        Ok(bson::from_bson(bson::Bson::Document(after))?)
+    }
+
+    ///
+    /// # delete subject from database
+    /// this function delete a subject heading with id from mongo database and return the subject or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the subject
+    /// * `actor` - the id of the user performing the change, who must be a librarian
+    /// # Return
+    /// * `Result<Subject, Box<dyn Error>>` - the deleted subject, `403` unless a librarian asks, `404` when there is no such subject
+    ///   or `409` while a book, even in the trash, has it
+    ///
+    pub async fn delete_subject(&self, id: &str, actor: Option<&str>) -> Result<Subject, Box<dyn Error>> {
+        let _timer = db_timer("delete_subject");
+        self.ensure_librarian(actor, "manage subjects").await?;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.subjects);
+        let collection_book: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let oid = parse_id(id, "Subject")?;
+        let before = match collection.find_one(doc! {"_id": oid}, None).await? {
+            Some(before) => before,
+            None => return Err(HttpError::new(Status::NotFound, "Subject not found").into()),
+        };
+        if collection_book.find_one(doc! {"subject_ids": id}, None).await?.is_some() {
+            return Err(HttpError::new(Status::Conflict, "Subject is used by books, remove it from the books first").into());
+        }
+        collection.delete_one(doc! {"_id": oid}, None).await?;
+        self.audit(actor, AuditAction::Delete, AuditEntity::Subject, id, Some(&before), None).await?;
+        Ok(bson::from_bson(bson::Bson::Document(before))?)
+    }
+
+    ///
+    /// # check subjects
+    /// this function check that the subject headings of a book exist in the vocabulary
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `subject_ids` - the ids of the subjects
+    /// # Return
+    /// * `Result<(), Box<dyn Error>>` - nothing, or a `422` naming the unknown subjects
+    ///
+    async fn check_subjects(&self, subject_ids: &[String]) -> Result<(), Box<dyn Error>> {
+        if subject_ids.is_empty() {
+            return Ok(());
+        }
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.subjects);
+        let oids = subject_ids.iter().map(bson::oid::ObjectId::parse_str).collect::<Result<Vec<_>, _>>()?;
+        let mut cursor = collection.find(doc! {"_id": {"$in": oids}}, None).await?;
+        let mut found = HashSet::new();
+        while let Some(result) = cursor.next().await {
+            found.insert(result?.get_object_id("_id")?.to_hex());
+        }
+        let unknown: Vec<String> = subject_ids.iter()
+            .filter(|id| !found.contains(*id))
+            .map(|id| format!("unknown subject {}", id))
+            .collect();
+        match unknown.is_empty() {
+            true => Ok(()),
+            false => Err(FieldErrors(BTreeMap::from([("subject_ids".to_string(), unknown)])).into()),
+        }
+    }
+    // end subject
+
+    // trash
+
+    ///
+    /// # get deleted books from database
+    /// this function return the books in the trash, most recently deleted first, or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// # Return
+    /// * `Result<Vec<Book>, Box<dyn Error>>` - a vector of book or an error
+    ///
+    pub async fn get_deleted_books(&self) -> Result<Vec<Book>, Box<dyn Error>> {
+        let _timer = db_timer("get_deleted_books");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let options = FindOptions::builder().sort(doc! {"deleted.at": -1}).build();
+        let mut cursor = collection.find(Deletion::in_trash(doc! {}), options).await?;
+        let mut books = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let book = bson::from_bson(bson::Bson::Document(result?))?;
+            books.push(book);
+        }
+        Ok(books)
+    }
+
+    ///
+    /// # restore a book from the trash
+    /// this function restore a deleted book with the comments deleted along with it and return the book or an error
+    /// the book is made available again unless a user still holds it, only librarians restore books,
+    /// refused with 409 when another book got its ISBN since
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the book
+    /// * `actor` - the id of the user performing the change
+    /// # Return
+    /// * `Result<Book, Box<dyn Error>>` - a book or an error
+    ///
+    pub async fn restore_book(&self, id: &str, actor: Option<&str>) -> Result<Book, Box<dyn Error>> {
+        let _timer = db_timer("restore_book");
+        self.ensure_librarian(actor, "restore books").await?;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.books);
+        let collection_user: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
+        let collection_comment: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let oid = parse_id(id, "Book")?;
+        let mut book: Book = match collection.find_one(Deletion::in_trash(doc! {"_id": oid}), None).await? {
+            Some(book) => bson::from_bson(bson::Bson::Document(book))?,
+            None => return Err(HttpError::new(Status::NotFound, "Book not found in trash").into()),
+        };
+        let before = to_stored_document(&book)?;
+
+        let on_loan = collection_user.find_one(not_deleted(doc! {"borrowed_books": id}), None).await?.is_some();
+        let (deletion, update) = book.restore(on_loan, bson::DateTime::now().to_chrono());
+        let deletion = deletion.unwrap();
+        collection.update_one(doc! {"_id": oid}, update, None).await
+            .map_err(|e| conflict_on_duplicate(e, "A book with this ISBN already exists"))?;
+        collection_comment.update_many(
+            deletion.along(doc! {"book_id": id}),
+            doc! {"$unset": {"deleted": ""}},
+            None,
+        ).await?;
+        self.audit(actor, AuditAction::Restore, AuditEntity::Book, id, Some(&before), Some(&to_stored_document(&book)?)).await?;
+        Ok(book)
+    }
+
+    ///
+    /// # get deleted users from database
+    /// this function return the users in the trash, most recently deleted first, or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// # Return
+    /// * `Result<Vec<User>, Box<dyn Error>>` - a vector of user or an error
+    ///
+    pub async fn get_deleted_users(&self) -> Result<Vec<User>, Box<dyn Error>> {
+        let _timer = db_timer("get_deleted_users");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
+        let options = FindOptions::builder().sort(doc! {"deleted.at": -1}).build();
+        let mut cursor = collection.find(Deletion::in_trash(doc! {}), options).await?;
+        let mut users = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let user = bson::from_bson(bson::Bson::Document(result?))?;
+            users.push(user);
+        }
+        Ok(users)
+    }
+
+    ///
+    /// # restore a user from the trash
+    /// this function restore a deleted user with the comments deleted along with it and return the user or an error
+    /// only librarians restore users, refused with 409 when another user registered the email since
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the user
+    /// * `actor` - the id of the user performing the change
+    /// # Return
+    /// * `Result<User, Box<dyn Error>>` - a user or an error
+    ///
+    pub async fn restore_user(&self, id: &str, actor: Option<&str>) -> Result<User, Box<dyn Error>> {
+        let _timer = db_timer("restore_user");
+        self.ensure_librarian(actor, "restore users").await?;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.users);
+        let collection_comment: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let oid = parse_id(id, "User")?;
+        let mut user: User = match collection.find_one(Deletion::in_trash(doc! {"_id": oid}), None).await? {
+            Some(user) => bson::from_bson(bson::Bson::Document(user))?,
+            None => return Err(HttpError::new(Status::NotFound, "User not found in trash").into()),
+        };
+        let before = to_stored_document(&user)?;
+        let deletion = user.deleted.take().unwrap();
+
+        user.updated_at = bson::DateTime::now().to_chrono();
+        user.version += 1;
+        collection.update_one(
+            doc! {"_id": oid},
+            doc! {"$unset": {"deleted": ""}, "$set": {"updated_at": bson::DateTime::from_chrono(user.updated_at)}, "$inc": {"version": 1}},
+            None,
+        ).await
+            .map_err(|e| conflict_on_duplicate(e, "Email already used by another user"))?;
+        collection_comment.update_many(
+            deletion.along(doc! {"user_id": id}),
+            doc! {"$unset": {"deleted": ""}},
+            None,
+        ).await?;
+        self.audit(actor, AuditAction::Restore, AuditEntity::User, id, Some(&before), Some(&to_stored_document(&user)?)).await?;
+        Ok(user)
+    }
+
+    ///
+    /// # get deleted comments from database
+    /// this function return the comments in the trash, most recently deleted first, or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// # Return
+    /// * `Result<Vec<Comment>, Box<dyn Error>>` - a vector of comment or an error
+    ///
+    pub async fn get_deleted_comments(&self) -> Result<Vec<Comment>, Box<dyn Error>> {
+        let _timer = db_timer("get_deleted_comments");
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let options = FindOptions::builder().sort(doc! {"deleted.at": -1}).build();
+        let mut cursor = collection.find(Deletion::in_trash(doc! {}), options).await?;
+        let mut comments = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let comment = bson::from_bson(bson::Bson::Document(result?))?;
+            comments.push(comment);
+        }
+        Ok(comments)
+    }
+
+    ///
+    /// # restore a comment from the trash
+    /// this function restore a deleted comment and return the comment or an error, only librarians restore comments
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `id` - the id of the comment
+    /// * `actor` - the id of the user performing the change
+    /// # Return
+    /// * `Result<Comment, Box<dyn Error>>` - a comment or an error
+    ///
+    pub async fn restore_deleted_comment(&self, id: &str, actor: Option<&str>) -> Result<Comment, Box<dyn Error>> {
+        let _timer = db_timer("restore_deleted_comment");
+        self.ensure_librarian(actor, "restore comments").await?;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.comments);
+        let oid = parse_id(id, "Comment")?;
+        let mut comment: Comment = match collection.find_one(Deletion::in_trash(doc! {"_id": oid}), None).await? {
+            Some(comment) => bson::from_bson(bson::Bson::Document(comment))?,
+            None => return Err(HttpError::new(Status::NotFound, "Comment not found in trash").into()),
+        };
+        let before = to_stored_document(&comment)?;
+        comment.deleted = None;
+        comment.updated_at = bson::DateTime::now().to_chrono();
+        collection.update_one(
+            doc! {"_id": oid},
+            doc! {"$unset": {"deleted": ""}, "$set": {"updated_at": bson::DateTime::from_chrono(comment.updated_at)}},
+            None,
+        ).await?;
+        self.audit(actor, AuditAction::Restore, AuditEntity::Comment, id, Some(&before), Some(&to_stored_document(&comment)?)).await?;
+        Ok(comment)
+    }
+
+    ///
+    /// # purge the trash
+    /// this function permanently delete the books, users and comments deleted before a date and return how many documents were removed, or an error
+    /// the comments of purged books are removed, the remaining comments of purged users are anonymised
+    /// each purged document gets a delete entry without actor in the audit log
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `before` - documents deleted before this date are purged
+    /// # Return
+    /// * `Result<u64, Box<dyn Error>>` - the number of purged documents or an error
+    ///
+    pub async fn purge_deleted(&self, before: chrono::DateTime<chrono::Utc>) -> Result<u64, Box<dyn Error>> {
+        let _timer = db_timer("purge_deleted");
+        let db = self.client.database(&self.config.db_name);
+        let collection_book: Collection<Document> = db.collection(&self.config.collections.books);
+        let collection_user: Collection<Document> = db.collection(&self.config.collections.users);
+        let collection_comment: Collection<Document> = db.collection(&self.config.collections.comments);
+        let expired = Deletion::expired(before);
+        let ids = |mut cursor: Cursor<Document>| async move {
+            let mut ids = Vec::new();
+            while let Some(result) = cursor.next().await {
+                ids.push(result?.get_object_id("_id")?);
+            }
+            Ok::<_, Box<dyn Error>>(ids)
+        };
+        let mut purged = Vec::new();
+
+        let book_ids = ids(collection_book.find(expired.clone(), None).await?).await?;
+        for book_id in &book_ids {
+            let filter = doc! {"book_id": book_id.to_hex()};
+            let comment_ids = ids(collection_comment.find(filter.clone(), None).await?).await?;
+            collection_comment.delete_many(doc! {"_id": {"$in": &comment_ids}}, None).await?;
+            purged.extend(comment_ids.into_iter().map(|id| (AuditEntity::Comment, id)));
+        }
+        collection_book.delete_many(doc! {"_id": {"$in": &book_ids}}, None).await?;
+        purged.extend(book_ids.into_iter().map(|id| (AuditEntity::Book, id)));
+
+        let user_ids = ids(collection_user.find(expired.clone(), None).await?).await?;
+        for user_id in &user_ids {
+            collection_comment.update_many(
+                not_deleted(doc! {"user_id": user_id.to_hex()}),
+                doc! {"$set": {"user_id": DELETED_USER_ID, "updated_at": bson::DateTime::now()}},
+                None,
+            ).await?;
+        }
+        collection_user.delete_many(doc! {"_id": {"$in": &user_ids}}, None).await?;
+        purged.extend(user_ids.into_iter().map(|id| (AuditEntity::User, id)));
+
+        let comment_ids = ids(collection_comment.find(expired, None).await?).await?;
+        collection_comment.delete_many(doc! {"_id": {"$in": &comment_ids}}, None).await?;
+        purged.extend(comment_ids.into_iter().map(|id| (AuditEntity::Comment, id)));
+
+        // the entries record what was purged, not the purged data
+        for (entity, id) in &purged {
+            self.audit(None, AuditAction::Delete, *entity, &id.to_hex(), None, None).await?;
+        }
+        Ok(purged.len() as u64)
+    }
+    // end trash
+
+    // audit
+
+    ///
+    /// # audit a change in database
+    /// this function append an entry with the changed fields to the audit log, or return an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `actor` - the id of the user performing the change
+    /// * `action` - the kind of change
+    /// * `entity` - the kind of document changed
+    /// * `entity_id` - the id of the document changed
+    /// * `before` - the document before the change, `None` on creation
+    /// * `after` - the document after the change
+    /// # Return
+    /// * `Result<(), Box<dyn Error>>` - nothing or an error
+    ///
+    async fn audit(&self, actor: Option<&str>, action: AuditAction, entity: AuditEntity, entity_id: &str, before: Option<&Document>, after: Option<&Document>) -> Result<(), Box<dyn Error>> {
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.audit);
+        let entry = AuditEntry {
+            actor: actor.map(str::to_string),
+            action,
+            entity,
+            entity_id: entity_id.to_string(),
+            date: bson::DateTime::now().to_chrono(),
+            changes: diff(before, after),
+            request_id: self.request_id.clone(),
+        };
+        collection.insert_one(to_stored_document(&entry)?, None).await?;
+        Ok(())
+    }
+
+    ///
+    /// # get audit entries from database
+    /// this function return the audit entries matching the filter, most recent first, or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `filter` - the entity, actor and time range to filter on
+    /// * `actor` - the id of the user reading the log, who must be a librarian
+    /// # Return
+    /// * `Result<Vec<AuditEntry>, Box<dyn Error>>` - a vector of audit entry, `403` unless a librarian asks or `422` for an invalid date
+    ///
+    pub async fn get_audit_entries(&self, filter: &AuditFilter, actor: Option<&str>) -> Result<Vec<AuditEntry>, Box<dyn Error>> {
+        let _timer = db_timer("get_audit_entries");
+        self.ensure_librarian(actor, "read the audit log").await?;
+        let collection: Collection<Document> = self.client.database(&self.config.db_name).collection(&self.config.collections.audit);
+        let mut query = doc! {};
+        if let Some(entity) = filter.entity {
+            query.insert("entity", to_stored_bson(&entity)?);
+        }
+        if let Some(entity_id) = &filter.entity_id {
+            query.insert("entity_id", entity_id);
+        }
+        if let Some(actor) = &filter.actor {
+            query.insert("actor", actor);
+        }
+        let mut range = doc! {};
+        if let Some(from) = &filter.from {
+            range.insert("$gte", parse_date("from", from)?);
+        }
+        if let Some(to) = &filter.to {
+            range.insert("$lt", parse_date("to", to)?);
+        }
+        if !range.is_empty() {
+            query.insert("date", range);
+        }
+        let options = FindOptions::builder()
+            .sort(doc! {"date": -1})
+            .limit(filter.limit.unwrap_or(100).clamp(1, 1000))
+            .build();
+        let mut cursor = collection.find(query, options).await?;
+        let mut entries = Vec::new();
+        while let Some(result) = cursor.next().await {
+            let entry = bson::from_bson(bson::Bson::Document(result?))?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+    // end audit
+
+    // migration
+
+    ///
+    /// # migrate the database
+    /// this function apply the pending migrations and return their records or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// # Return
+    /// * `Result<Vec<MigrationRecord>, Box<dyn Error>>` - the records of the applied migrations or an error
+    ///
+    pub async fn migrate(&self) -> Result<Vec<MigrationRecord>, Box<dyn Error>> {
+        let _timer = db_timer("migrate");
+        migration::run(&self.client.database(&self.config.db_name), &self.config.collections).await
+    }
+
+    ///
+    /// # get migration status
+    /// this function return the migrations started on the database or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// # Return
+    /// * `Result<Vec<MigrationRecord>, Box<dyn Error>>` - the migration records or an error
+    ///
+    pub async fn get_migrations(&self) -> Result<Vec<MigrationRecord>, Box<dyn Error>> {
+        let _timer = db_timer("get_migrations");
+        migration::status(&self.client.database(&self.config.db_name), &self.config.collections).await
+    }
+    // end migration
+
+    // import
+
+    ///
+    /// # import books in database
+    /// this function resolve the genres of the rows by name, check them and insert the valid ones in batches,
+    /// the invalid rows are left out and reported
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `rows` - the rows of the import file
+    /// * `dry_run` - only check the rows, nothing is written
+    /// * `actor` - the id of the user performing the import
+    /// # Return
+    /// * `Result<ImportReport, Box<dyn Error>>` - the report of the import or an error
+    ///
+    pub async fn import_books(&self, rows: Vec<ImportRow>, dry_run: bool, actor: Option<&str>) -> Result<ImportReport, Box<dyn Error>> {
+        let _timer = db_timer("import_books");
+        let db = self.client.database(&self.config.db_name);
+        let collection: Collection<Document> = db.collection(&self.config.collections.books);
+        let collection_genre: Collection<Document> = db.collection(&self.config.collections.genres);
+        let collection_author: Collection<Document> = db.collection(&self.config.collections.authors);
+        let collection_audit: Collection<Document> = db.collection(&self.config.collections.audit);
+
+        // the credit of a book is linked to the author having it as name or alias, unless several authors have it
+        let mut authors: HashMap<String, Option<String>> = HashMap::new();
+        let mut cursor = collection_author.find(doc! {}, None).await?;
+        while let Some(author) = cursor.next().await {
+            let author: Author = bson::from_bson(bson::Bson::Document(author?))?;
+            let id = author.id.map(|id| id.to_hex());
+            for key in name_keys(&author.name, &author.aliases) {
+                authors.entry(key)
+                    .and_modify(|other| if *other != id { *other = None })
+                    .or_insert(id.clone());
+            }
+        }
+
+        let mut genres = HashMap::new();
+        let mut cursor = collection_genre.find(doc! {}, None).await?;
+        while let Some(genre) = cursor.next().await {
+            let genre = genre?;
+            if let (Ok(name), Ok(id)) = (genre.get_str("name"), genre.get_object_id("_id")) {
+                genres.insert(name.to_lowercase(), id.to_hex());
+            }
+        }
+
+        let mut report = ImportReport { dry_run, rows: rows.len(), imported: 0, errors: Vec::new(), warnings: Vec::new(), failure: None };
+        let now = bson::DateTime::now().to_chrono();
+        let mut books = Vec::new();
+        for row in rows {
+            let book = match row.book {
+                Ok(book) => book,
+                Err(error) => {
+                    report.errors.push(RowError { line: row.line, error, fields: Default::default() });
+                    continue;
+                }
+            };
+            match book.into_new_book(&genres) {
+                Ok(book) => {
+                    let mut book = Book::from(book);
+                    book.created_at = now;
+                    book.updated_at = now;
+                    if let Some(Some(author_id)) = authors.get(&name_key(&book.author)) {
+                        book.authors.push(BookAuthor { author_id: author_id.clone(), role: AuthorRole::Author });
+                    }
+                    books.push((row.line, book));
+                    report.warnings.extend(row.warnings.into_iter().map(|warning| RowWarning { line: row.line, warning }));
+                }
+                Err(errors) => report.errors.push(RowError { line: row.line, error: "Validation failed".to_string(), fields: errors.0 }),
+            }
+        }
+
+        // an ISBN already in database, or on an earlier row, leaves the row out
+        let isbns: Vec<&String> = books.iter().filter_map(|(_, book)| book.isbn_13.as_ref()).collect();
+        let mut taken = HashSet::new();
+        if !isbns.is_empty() {
+            let mut cursor = collection.find(not_deleted(doc! {"isbn_13": {"$in": isbns}}), None).await?;
+            while let Some(book) = cursor.next().await {
+                if let Ok(isbn) = book?.get_str("isbn_13") {
+                    taken.insert(isbn.to_string());
+                }
+            }
+        }
+        let mut docs = Vec::new();
+        for (line, book) in books {
+            if let Some(isbn) = &book.isbn_13 {
+                if !taken.insert(isbn.clone()) {
+                    let fields = BTreeMap::from([("isbn".to_string(), vec!["a book with this ISBN already exists".to_string()])]);
+                    report.errors.push(RowError { line, error: "Duplicate ISBN".to_string(), fields });
+                    continue;
+                }
+            }
+            // the id is set here to find out which books of a failed batch were written
+            let mut doc = to_stored_document(&book)?;
+            doc.insert("_id", bson::oid::ObjectId::new());
+            docs.push((line, doc));
+        }
+        report.errors.sort_by_key(|error| error.line);
+        if dry_run {
+            report.imported = docs.len();
+            return Ok(report);
+        }
+
+        // a failed batch stops the import, the books written so far are kept and reported
+        for batch in docs.chunks(IMPORT_BATCH_SIZE) {
+            let written = collection.insert_many(batch.iter().map(|(_, doc)| doc), None).await;
+            let mut inserted = HashSet::new();
+            match &written {
+                Ok(_) => inserted.extend(batch.iter().filter_map(|(_, doc)| doc.get_object_id("_id").ok())),
+                Err(_) => {
+                    let ids: Vec<&bson::Bson> = batch.iter().filter_map(|(_, doc)| doc.get("_id")).collect();
+                    let mut cursor = collection.find(doc! {"_id": {"$in": ids}}, None).await?;
+                    while let Some(book) = cursor.next().await {
+                        inserted.insert(book?.get_object_id("_id")?);
+                    }
+                }
+            }
+            let mut entries = Vec::new();
+            for (_, doc) in batch {
+                let id = doc.get_object_id("_id")?;
+                if !inserted.contains(&id) {
+                    continue;
+                }
+                let mut created = doc.clone();
+                created.remove("_id");
+                let entry = AuditEntry {
+                    actor: actor.map(str::to_string),
+                    action: AuditAction::Create,
+                    entity: AuditEntity::Book,
+                    entity_id: id.to_hex(),
+                    date: now,
+                    changes: diff(None, Some(&created)),
+                    request_id: self.request_id.clone(),
+                };
+                entries.push(to_stored_document(&entry)?);
+            }
+            if !entries.is_empty() {
+                collection_audit.insert_many(entries, None).await?;
+            }
+            report.imported += inserted.len();
+            if let Err(e) = written {
+                let line = batch.iter()
+                    .find(|(_, doc)| doc.get_object_id("_id").is_ok_and(|id| !inserted.contains(&id)))
+                    .map_or(batch[0].0, |(line, _)| *line);
+                report.failure = Some(ImportFailure { line, error: e.to_string() });
+                break;
+            }
+        }
+        Ok(report)
+    }
+    // end import
+
+    // export
+
+    ///
+    /// # authorise an export
+    /// this function check that the actor may export the personal fields of an entity, which only librarians do;
+    /// anonymised exports and entities without personal fields are open to all
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `entity` - the exported entity
+    /// * `filter` - the options of the export
+    /// * `actor` - the id of the user asking for the export
+    /// # Return
+    /// * `Result<(), Box<dyn Error>>` - nothing or a `403 Forbidden`
+    ///
+    pub async fn authorise_export(&self, entity: ExportEntity, filter: &ExportFilter, actor: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let _timer = db_timer("authorise_export");
+        if filter.anonymised() || entity.personal_fields().is_empty() {
+            return Ok(());
+        }
+        self.ensure_librarian(actor, &format!("export {} without anonymise", entity)).await?;
+        Ok(())
+    }
+
+    ///
+    /// # export from database
+    /// this function open a cursor over the documents of an export, sorted by id, the documents in the trash are left out
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// * `entity` - the exported entity
+    /// * `filter` - the filters of the export, and if the personal fields are left out
+    /// # Return
+    /// * `Result<Cursor<Document>, Box<dyn Error>>` - the cursor, `422` for a filter not applying to the entity, `404` for an unknown genre
+    ///
+    pub async fn export(&self, entity: ExportEntity, filter: &ExportFilter) -> Result<Cursor<Document>, Box<dyn Error>> {
+        let _timer = db_timer("export");
+        let db = self.client.database(&self.config.db_name);
+        let applies = [
+            ("genre", filter.genre.is_some(), matches!(entity, ExportEntity::Books | ExportEntity::Loans)),
+            ("user_id", filter.user_id.is_some(), matches!(entity, ExportEntity::Comments | ExportEntity::Loans)),
+            ("book_id", filter.book_id.is_some(), entity == ExportEntity::Comments),
+        ];
+        if let Some((name, _, _)) = applies.iter().find(|(_, set, applies)| *set && !applies) {
+            return Err(HttpError::new(Status::UnprocessableEntity, format!("Filter {} does not apply to {}", name, entity)).into());
+        }
+        let marc = filter.format.is_some_and(|format| format.is_marc());
+        if marc && entity != ExportEntity::Books {
+            return Err(HttpError::new(Status::UnprocessableEntity, format!("MARC formats only apply to books, not to {}", entity)).into());
+        }
+
+        let mut query = doc! {};
+        if let Some(name) = &filter.genre {
+            let collection_genre: Collection<Document> = db.collection(&self.config.collections.genres);
+            let genre = match collection_genre.find_one(doc! {"name": name}, None).await? {
+                Some(genre) => genre,
+                None => return Err(HttpError::new(Status::NotFound, "Genre not found").into()),
+            };
+            query.insert("genre_ids", genre.get_object_id("_id")?.to_hex());
+        }
+        if let Some(book_id) = &filter.book_id {
+            query.insert("book_id", book_id);
+        }
+        if let Some(user_id) = &filter.user_id {
+            let field = if entity == ExportEntity::Loans { "loan.user_id" } else { "user_id" };
+            query.insert(field, user_id);
+        }
+        let mut hidden = doc! {};
+        if filter.anonymised() {
+            for field in entity.personal_fields() {
+                hidden.insert(*field, 0);
+            }
+        }
+        let options = FindOptions::builder()
+            .sort(doc! {"_id": 1})
+            .projection(if hidden.is_empty() { None } else { Some(hidden.clone()) })
+            .build();
+
+        let cursor = match entity {
+            // MARC records hold the names of the genres
+            ExportEntity::Books if marc => {
+                let pipeline = vec![
+                    doc! {"$match": not_deleted(query)},
+                    doc! {"$sort": {"_id": 1}},
+                    doc! {"$lookup": {
+                        "from": &self.config.collections.genres,
+                        "let": {"genre_ids": {"$ifNull": ["$genre_ids", []]}},
+                        "pipeline": [{"$match": {"$expr": {"$in": [{"$toString": "$_id"}, "$$genre_ids"]}}}],
+                        "as": "genres",
+                    }},
+                    doc! {"$set": {"genres": "$genres.name"}},
+                ];
+                db.collection::<Document>(&self.config.collections.books).aggregate(pipeline, None).await?
+            }
+            ExportEntity::Books => db.collection::<Document>(&self.config.collections.books).find(not_deleted(query), options).await?,
+            ExportEntity::Users => db.collection::<Document>(&self.config.collections.users).find(not_deleted(query), options).await?,
+            ExportEntity::Comments => db.collection::<Document>(&self.config.collections.comments).find(not_deleted(query), options).await?,
+            ExportEntity::Genres => db.collection::<Document>(&self.config.collections.genres).find(query, options).await?,
+            ExportEntity::Authors => db.collection::<Document>(&self.config.collections.authors).find(query, options).await?,
+            ExportEntity::Works => db.collection::<Document>(&self.config.collections.works).find(query, options).await?,
+            ExportEntity::Subjects => db.collection::<Document>(&self.config.collections.subjects).find(query, options).await?,
+            ExportEntity::Loans => {
+                query.insert("loan", doc! {"$exists": true});
+                let mut pipeline = vec![
+                    doc! {"$match": not_deleted(query)},
+                    doc! {"$sort": {"_id": 1}},
+                    doc! {"$project": {
+                        "_id": 0,
+                        "book_id": {"$toString": "$_id"},
+                        "title": 1,
+                        "user_id": "$loan.user_id",
+                        "at": "$loan.at",
+                        "due": "$loan.due",
+                    }},
+                ];
+                if !hidden.is_empty() {
+                    pipeline.push(doc! {"$project": hidden});
+                }
+                db.collection::<Document>(&self.config.collections.books).aggregate(pipeline, None).await?
+            }
+        };
+        Ok(cursor)
+    }
+    // end export
+
+    // health
+
+    ///
+    /// # ping the database
+    /// this function check that the database answers and return nothing or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// # Return
+    /// * `Result<(), Box<dyn Error>>` - nothing or an error
+    ///
+    pub async fn ping(&self) -> Result<(), Box<dyn Error>> {
+        let _timer = db_timer("ping");
+        self.client.database(&self.config.db_name).run_command(doc! {"ping": 1}, None).await?;
+        Ok(())
+    }
+    // end health
+
+    // metrics
+
+    ///
+    /// # get library stats from database
+    /// this function count the books on loan, the overdue loans, the users not in the trash
+    /// and the comments of the last 24 hours, and return them or an error
+    /// # Arguments
+    /// * `self` - the mongo struct
+    /// # Return
+    /// * `Result<LibraryStats, Box<dyn Error>>` - the counts or an error
+    ///
+    pub async fn get_library_stats(&self) -> Result<LibraryStats, Box<dyn Error>> {
+        let _timer = db_timer("get_library_stats");
+        let db = self.client.database(&self.config.db_name);
+        let collection_book: Collection<Document> = db.collection(&self.config.collections.books);
+        let collection_user: Collection<Document> = db.collection(&self.config.collections.users);
+        let collection_comment: Collection<Document> = db.collection(&self.config.collections.comments);
+        let now = chrono::Utc::now();
+        Ok(LibraryStats {
+            books_on_loan: collection_book.count_documents(not_deleted(doc! {"availability": false}), None).await?,
+            overdue_loans: collection_book.count_documents(not_deleted(doc! {"loan.due": {"$lt": bson::DateTime::from_chrono(now)}}), None).await?,
+            active_users: collection_user.count_documents(not_deleted(doc! {}), None).await?,
+            comments_last_day: collection_comment.count_documents(not_deleted(doc! {"created_at": {"$gte": bson::DateTime::from_chrono(now - chrono::Duration::days(1))}}), None).await?,
+        })
+    }
+    // end metrics
 }
\ No newline at end of file
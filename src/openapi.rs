@@ -0,0 +1,141 @@
+use rocket::Route;
+use rocket::serde::json::{json, serde_json, Value as JsonValue};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{Info, OpenApi, Parameter, ParameterValue};
+use rocket_okapi::request::RequestHeaderInput;
+use rocket_okapi::settings::OpenApiSettings;
+use rocket_okapi::swagger_ui::{make_swagger_ui, SwaggerUIConfig};
+use rocket_okapi::{get_openapi_route, openapi_get_routes_spec};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use crate::book::*;
+use crate::user::*;
+use crate::genre::*;
+use crate::author::*;
+use crate::work::*;
+use crate::subject::*;
+use crate::comment::*;
+use crate::audit::*;
+use crate::health::*;
+use crate::metrics::*;
+use crate::export::*;
+
+/// path of the OpenAPI document
+pub const OPENAPI_PATH: &str = "/openapi.json";
+/// base path of the Swagger UI
+pub const DOCS_PATH: &str = "/docs";
+
+///
+/// # api
+/// this function return every route of the API with the OpenAPI document generated from them
+/// # Return
+/// * `(Vec<Route>, OpenApi)` - the routes and the OpenAPI document
+///
+pub fn api() -> (Vec<Route>, OpenApi) {
+    let settings = OpenApiSettings { json_path: OPENAPI_PATH.to_string(), ..Default::default() };
+    let (routes, mut spec) = openapi_get_routes_spec![settings:
+        create_book, get_books, get_book, get_book_by_isbn, get_series, get_books_by_series, browse_shelf, search_book, delete_book, update_book, patch_book, borrow_book, return_book, import_books,
+        create_user, get_users, get_user, delete_user, update_user, patch_user, search_user,
+        create_genre, get_genres, get_books_by_genre,
+        create_subject, get_subjects, get_books_by_subject, update_subject, delete_subject,
+        create_author, get_authors, get_author, update_author, delete_author,
+        create_work, get_works, get_work, update_work, delete_work, search_works, get_rating_by_work_id,
+        create_comment, get_comments, get_comments_by_book_id, get_comments_by_user_id, get_rating_by_book_id, get_all_books_by_search_rating, vote_comment_helpful, unvote_comment_helpful,
+        flag_comment, get_moderation_queue, get_moderation_actions, hide_comment, restore_comment, delete_comment,
+        get_deleted_books, restore_book, get_deleted_users, restore_user, get_deleted_comments, restore_deleted_comment,
+        get_audit_entries,
+        export,
+        get_health, get_ready, get_metrics,
+    ];
+    spec.info = Info {
+        title: "bibliotheca".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        description: Some("REST API of the library: books, works, authors, users, genres, subjects, comments and their moderation.".to_string()),
+        ..Default::default()
+    };
+    (routes, spec)
+}
+
+///
+/// # docs
+/// this function return the route serving the OpenAPI document and the routes of the Swagger UI reading it
+/// # Arguments
+/// * `spec` - the OpenAPI document
+/// # Return
+/// * `(Route, Vec<Route>)` - the route of the document, to mount at `/`, and the routes of the UI, to mount at `DOCS_PATH`
+///
+pub fn docs(spec: OpenApi) -> (Route, Vec<Route>) {
+    let settings = OpenApiSettings { json_path: OPENAPI_PATH.to_string(), ..Default::default() };
+    let ui = SwaggerUIConfig { url: OPENAPI_PATH.to_string(), ..Default::default() };
+    (get_openapi_route(spec, &settings), make_swagger_ui(&ui).into())
+}
+
+///
+/// # header parameter
+/// this function document an optional header read by a request guard
+/// # Arguments
+/// * `gen` - the OpenAPI generator
+/// * `name` - the name of the header
+/// * `description` - the description of the header
+/// # Return
+/// * `RequestHeaderInput` - the header parameter
+///
+pub fn header_parameter(gen: &mut OpenApiGenerator, name: &str, description: &str) -> RequestHeaderInput {
+    RequestHeaderInput::Parameter(Parameter {
+        name: name.to_string(),
+        location: "header".to_string(),
+        description: Some(description.to_string()),
+        required: false,
+        deprecated: false,
+        allow_empty_value: false,
+        value: ParameterValue::Schema {
+            style: None,
+            explode: None,
+            allow_reserved: false,
+            schema: gen.json_schema::<String>(),
+            example: None,
+            examples: None,
+        },
+        extensions: Default::default(),
+    })
+}
+
+///
+/// # schema
+/// this function read a JSON schema
+/// # Arguments
+/// * `value` - the schema as JSON
+/// # Return
+/// * `Schema` - the schema
+///
+fn schema(value: JsonValue) -> Schema {
+    serde_json::from_value(value).unwrap()
+}
+
+///
+/// # object id
+/// this function return the schema of an ObjectId, sent as extended JSON
+/// # Arguments
+/// * `_` - the schema generator
+/// # Return
+/// * `Schema` - `{"$oid": "<24 hex digits>"}`
+///
+pub fn object_id(_: &mut SchemaGenerator) -> Schema {
+    schema(json!({
+        "type": "object",
+        "required": ["$oid"],
+        "properties": { "$oid": { "type": "string", "pattern": "^[0-9a-f]{24}$" } }
+    }))
+}
+
+///
+/// # document
+/// this function return the schema of a free form document
+/// # Arguments
+/// * `_` - the schema generator
+/// # Return
+/// * `Schema` - any object
+///
+pub fn document(_: &mut SchemaGenerator) -> Schema {
+    schema(json!({ "type": "object", "additionalProperties": true }))
+}
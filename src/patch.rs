@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::error::Error;
+use rocket::data::{Data, FromData, Outcome};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::serde::json::{self, Json, Value as JsonValue};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{MediaType, RequestBody};
+use rocket_okapi::request::OpenApiFromData;
+use schemars::JsonSchema;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use crate::error::HttpError;
+use crate::Value;
+
+/// One operation of a JSON Patch (RFC 6902). Paths are JSON Pointers (RFC 6901).
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: JsonValue },
+    Remove { path: String },
+    Replace { path: String, value: JsonValue },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: JsonValue },
+}
+
+/// A partial update, either a JSON Merge Patch (RFC 7396) or a JSON Patch (RFC 6902).
+/// As request data it is read according to its content type,
+/// `application/merge-patch+json` or `application/json-patch+json`, any other type is answered 415.
+#[derive(Debug, Clone)]
+pub enum Patch {
+    Merge(JsonValue),
+    Json(Vec<PatchOperation>),
+}
+
+/// media type of a JSON Merge Patch
+pub const MERGE_PATCH: &str = "merge-patch+json";
+/// media type of a JSON Patch
+pub const JSON_PATCH: &str = "json-patch+json";
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for Patch {
+    type Error = json::Error<'r>;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r, Self> {
+        let sub = match request.content_type() {
+            Some(content_type) if content_type.top() == "application" => content_type.sub().as_str(),
+            _ => "",
+        };
+        match sub {
+            MERGE_PATCH => Json::<JsonValue>::from_data(request, data).await.map(|patch| Patch::Merge(patch.into_inner())),
+            JSON_PATCH => Json::<Vec<PatchOperation>>::from_data(request, data).await.map(|patch| Patch::Json(patch.into_inner())),
+            _ => Outcome::Forward((data, Status::UnsupportedMediaType)),
+        }
+    }
+}
+
+impl<'r> OpenApiFromData<'r> for Patch {
+    fn request_body(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<RequestBody> {
+        let mut body = RequestBody { required: true, ..Default::default() };
+        let merge = MediaType { schema: Some(gen.json_schema::<JsonValue>()), ..Default::default() };
+        let operations = MediaType { schema: Some(gen.json_schema::<Vec<PatchOperation>>()), ..Default::default() };
+        body.content.insert(format!("application/{}", MERGE_PATCH), merge);
+        body.content.insert(format!("application/{}", JSON_PATCH), operations);
+        Ok(body)
+    }
+}
+
+///
+/// # unprocessable
+/// this function build a `422 Unprocessable Entity` error
+/// # Arguments
+/// * `message` - the message sent to the client
+/// # Return
+/// * `Box<dyn Error>` - the error
+///
+fn unprocessable(message: impl Into<String>) -> Box<dyn Error> {
+    HttpError::new(Status::UnprocessableEntity, message).into()
+}
+
+impl Patch {
+
+    ///
+    /// # apply
+    /// this function apply the patch to a JSON document, the document is left unchanged on error
+    /// # Arguments
+    /// * `self` - the patch
+    /// * `target` - the document to patch
+    /// # Return
+    /// * `Result<(), Box<dyn Error>>` - nothing, `422` on an invalid operation or `409` on a failed `test`
+    ///
+    pub fn apply(&self, target: &mut JsonValue) -> Result<(), Box<dyn Error>> {
+        match self {
+            Patch::Merge(patch) => {
+                merge(target, patch);
+                Ok(())
+            }
+            Patch::Json(operations) => {
+                let mut patched = target.clone();
+                for operation in operations {
+                    apply_operation(&mut patched, operation)?;
+                }
+                *target = patched;
+                Ok(())
+            }
+        }
+    }
+
+    ///
+    /// # apply to
+    /// this function apply the patch to the editable fields of a document and read them back,
+    /// so a patch producing a wrong type or an unknown field is refused
+    /// # Arguments
+    /// * `self` - the patch
+    /// * `current` - the current editable fields
+    /// # Return
+    /// * `Result<T, Box<dyn Error>>` - the patched fields or an error
+    ///
+    pub fn apply_to<T: Serialize + DeserializeOwned>(&self, current: &T) -> Result<T, Box<dyn Error>> {
+        let mut document = json::to_value(current)?;
+        let fields: Vec<String> = match &document {
+            JsonValue::Object(map) => map.keys().cloned().collect(),
+            _ => Vec::new(),
+        };
+        self.apply(&mut document)?;
+        match &document {
+            JsonValue::Object(map) => {
+                if let Some(unknown) = map.keys().find(|key| !fields.contains(key)) {
+                    return Err(unprocessable(format!("Unknown field {}", unknown)));
+                }
+            }
+            _ => return Err(unprocessable("The patched document must be an object")),
+        }
+        json::from_value(document).map_err(|e| unprocessable(e.to_string()))
+    }
+}
+
+///
+/// # merge
+/// this function apply a JSON Merge Patch: `null` removes a member, objects are merged recursively
+/// and any other value replaces the target
+/// # Arguments
+/// * `target` - the document to patch
+/// * `patch` - the merge patch
+///
+pub fn merge(target: &mut JsonValue, patch: &JsonValue) {
+    let JsonValue::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = JsonValue::Object(Default::default());
+    }
+    let map = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            map.remove(key);
+        } else {
+            merge(map.entry(key.clone()).or_insert(JsonValue::Null), value);
+        }
+    }
+}
+
+///
+/// # split pointer
+/// this function split a JSON Pointer into the pointer of its parent and its last unescaped token
+/// # Arguments
+/// * `path` - the JSON Pointer
+/// # Return
+/// * `Result<(&str, String), Box<dyn Error>>` - the parent pointer and the last token, or an error for the root or an invalid pointer
+///
+fn split_pointer(path: &str) -> Result<(&str, String), Box<dyn Error>> {
+    if !path.starts_with('/') {
+        return Err(unprocessable(format!("Invalid path {}", path)));
+    }
+    let index = path.rfind('/').unwrap();
+    let token = path[index + 1..].replace("~1", "/").replace("~0", "~");
+    Ok((&path[..index], token))
+}
+
+///
+/// # array index
+/// this function parse a JSON Pointer token as an index below `len`
+/// # Arguments
+/// * `token` - the token
+/// * `len` - the exclusive upper bound
+/// * `path` - the JSON Pointer, for the error message
+/// # Return
+/// * `Result<usize, Box<dyn Error>>` - the index or an error
+///
+fn array_index(token: &str, len: usize, path: &str) -> Result<usize, Box<dyn Error>> {
+    match token.parse::<usize>() {
+        Ok(index) if index < len && (token == "0" || !token.starts_with('0')) => Ok(index),
+        _ => Err(unprocessable(format!("Invalid array index in {}", path))),
+    }
+}
+
+///
+/// # add
+/// this function add a value at a JSON Pointer, `-` appends to an array
+/// # Arguments
+/// * `target` - the document to patch
+/// * `path` - the JSON Pointer
+/// * `value` - the value to add
+/// # Return
+/// * `Result<(), Box<dyn Error>>` - nothing or an error
+///
+fn add(target: &mut JsonValue, path: &str, value: JsonValue) -> Result<(), Box<dyn Error>> {
+    if path.is_empty() {
+        *target = value;
+        return Ok(());
+    }
+    let (parent, token) = split_pointer(path)?;
+    match target.pointer_mut(parent) {
+        Some(JsonValue::Object(map)) => {
+            map.insert(token, value);
+        }
+        Some(JsonValue::Array(array)) => {
+            if token == "-" {
+                array.push(value);
+            } else {
+                let index = array_index(&token, array.len() + 1, path)?;
+                array.insert(index, value);
+            }
+        }
+        _ => return Err(unprocessable(format!("Path {} does not exist", parent))),
+    }
+    Ok(())
+}
+
+///
+/// # remove
+/// this function remove the value at a JSON Pointer
+/// # Arguments
+/// * `target` - the document to patch
+/// * `path` - the JSON Pointer
+/// # Return
+/// * `Result<JsonValue, Box<dyn Error>>` - the removed value or an error
+///
+fn remove(target: &mut JsonValue, path: &str) -> Result<JsonValue, Box<dyn Error>> {
+    let (parent, token) = split_pointer(path)?;
+    let removed = match target.pointer_mut(parent) {
+        Some(JsonValue::Object(map)) => map.remove(&token),
+        Some(JsonValue::Array(array)) => {
+            let index = array_index(&token, array.len(), path)?;
+            Some(array.remove(index))
+        }
+        _ => None,
+    };
+    removed.ok_or_else(|| unprocessable(format!("Path {} does not exist", path)))
+}
+
+///
+/// # json equal
+/// this function compare two JSON values as the `test` operation does: numbers are equal when their values are,
+/// so `1` equals `1.0`, arrays and objects are compared member by member
+/// # Arguments
+/// * `a` - the first value
+/// * `b` - the second value
+/// # Return
+/// * `bool` - true if the values are equal
+///
+fn json_equal(a: &JsonValue, b: &JsonValue) -> bool {
+    match (a, b) {
+        (JsonValue::Number(a), JsonValue::Number(b)) => match (a.as_i64(), b.as_i64(), a.as_u64(), b.as_u64()) {
+            (Some(a), Some(b), _, _) => a == b,
+            (_, _, Some(a), Some(b)) => a == b,
+            _ => a.as_f64() == b.as_f64(),
+        },
+        (JsonValue::Array(a), JsonValue::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_equal(a, b)),
+        (JsonValue::Object(a), JsonValue::Object(b)) => {
+            a.len() == b.len() && a.iter().all(|(key, a)| b.get(key).is_some_and(|b| json_equal(a, b)))
+        }
+        _ => a == b,
+    }
+}
+
+///
+/// # apply operation
+/// this function apply one JSON Patch operation
+/// # Arguments
+/// * `target` - the document to patch
+/// * `operation` - the operation
+/// # Return
+/// * `Result<(), Box<dyn Error>>` - nothing or an error
+///
+fn apply_operation(target: &mut JsonValue, operation: &PatchOperation) -> Result<(), Box<dyn Error>> {
+    match operation {
+        PatchOperation::Add { path, value } => add(target, path, value.clone()),
+        PatchOperation::Remove { path } => remove(target, path).map(|_| ()),
+        PatchOperation::Replace { path, value } => {
+            match target.pointer_mut(path) {
+                Some(current) => *current = value.clone(),
+                None => return Err(unprocessable(format!("Path {} does not exist", path))),
+            }
+            Ok(())
+        }
+        PatchOperation::Move { from, path } => {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(unprocessable(format!("Cannot move {} into itself", from)));
+            }
+            let value = remove(target, from)?;
+            add(target, path, value)
+        }
+        PatchOperation::Copy { from, path } => {
+            let value = match target.pointer(from) {
+                Some(value) => value.clone(),
+                None => return Err(unprocessable(format!("Path {} does not exist", from))),
+            };
+            add(target, path, value)
+        }
+        PatchOperation::Test { path, value } => {
+            if !target.pointer(path).is_some_and(|current| json_equal(current, value)) {
+                return Err(HttpError::new(Status::Conflict, format!("Test failed on {}", path)).into());
+            }
+            Ok(())
+        }
+    }
+}
+
+///
+/// # changed fields
+/// this function compare the fields of a document before and after a patch and return the ones to write
+/// removed fields are written as null, unless they are required
+/// # Arguments
+/// * `current` - the fields before the patch
+/// * `patched` - the fields after the patch
+/// * `required` - the fields that cannot be removed
+/// # Return
+/// * `Result<HashMap<&str, Value>, Box<dyn Error>>` - the changed fields or an error
+///
+pub fn changed_fields(current: HashMap<&'static str, Value>, mut patched: HashMap<&'static str, Value>, required: &[&str]) -> Result<HashMap<&'static str, Value>, Box<dyn Error>> {
+    let mut changes = HashMap::new();
+    for (key, value) in current {
+        match patched.remove(key) {
+            Some(new) if new != value => {
+                changes.insert(key, new);
+            }
+            Some(_) => {}
+            None if required.contains(&key) => return Err(unprocessable(format!("{} cannot be removed", key))),
+            None => {
+                changes.insert(key, Value::Null);
+            }
+        }
+    }
+    changes.extend(patched);
+    Ok(changes)
+}
@@ -0,0 +1,116 @@
+use std::convert::Infallible;
+use std::time::Instant;
+use rocket::{Data, Request, Response};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::serde_json;
+use serde::Serialize;
+
+/// header carrying the correlation id of a request, read from the client or generated
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// The correlation id of a request, sent back in `X-Request-Id` and written in its log line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+
+    ///
+    /// # of
+    /// this function return the id of a request: the `X-Request-Id` header when it is a valid id,
+    /// a new id otherwise, the same id is returned for the whole request
+    /// # Arguments
+    /// * `request` - the request
+    /// # Return
+    /// * `&RequestId` - the id of the request
+    ///
+    pub fn of<'r>(request: &'r Request<'_>) -> &'r RequestId {
+        request.local_cache(|| {
+            let id = request.headers().get_one(REQUEST_ID_HEADER)
+                .map(str::trim)
+                .filter(|id| is_valid(id))
+                .map(str::to_string)
+                .unwrap_or_else(|| bson::oid::ObjectId::new().to_hex());
+            RequestId(id)
+        })
+    }
+}
+
+///
+/// # is valid
+/// this function check that an id sent by a client is safe to log and send back
+/// # Arguments
+/// * `id` - the id
+/// # Return
+/// * `bool` - true for 1 to 128 letters, digits, `-`, `_`, `.` or `:`
+///
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestId::of(request).clone())
+    }
+}
+
+/// The error a request failed with, kept for its log line.
+pub struct RequestError(pub Option<String>);
+
+/// start time of a request, kept in the request local cache
+struct RequestStart(Instant);
+
+/// One structured log line, written for every request.
+#[derive(Serialize)]
+struct RequestLine<'a> {
+    time: String,
+    request_id: &'a str,
+    method: &'a str,
+    path: &'a str,
+    route: Option<String>,
+    status: u16,
+    latency_ms: f64,
+    user: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// Fairing giving every request an `X-Request-Id` and writing one JSON line per request on stdout.
+pub struct RequestLog;
+
+#[rocket::async_trait]
+impl Fairing for RequestLog {
+    fn info(&self) -> Info {
+        Info { name: "Request log", kind: Kind::Request | Kind::Response }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+        RequestId::of(request);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let start = request.local_cache(|| RequestStart(Instant::now()));
+        let id = RequestId::of(request);
+        let error = request.local_cache(|| RequestError(None));
+        response.set_raw_header(REQUEST_ID_HEADER, id.0.clone());
+        let line = RequestLine {
+            time: chrono::Utc::now().to_rfc3339(),
+            request_id: &id.0,
+            method: request.method().as_str(),
+            path: request.uri().path().as_str(),
+            route: request.route().map(|route| route.uri.to_string()),
+            status: response.status().code,
+            latency_ms: start.0.elapsed().as_secs_f64() * 1000.0,
+            user: request.headers().get_one("X-User-Id"),
+            error: error.0.as_deref(),
+        };
+        match serde_json::to_string(&line) {
+            Ok(line) => println!("{}", line),
+            Err(e) => rocket::error!("request log failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use crate::mongo::Mongo;
+use rocket::{get, post, put, delete};
+use rocket_okapi::openapi;
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use validator::Validate;
+use rocket::serde::json::Json;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use crate::book::Book;
+use crate::{TimeFilter, Value};
+use crate::actor::Actor;
+use crate::error::ApiError;
+use crate::validation::Validated;
+
+/// A subject heading of the controlled vocabulary, books reference it in `subject_ids`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Subject {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "crate::openapi::object_id")]
+    pub id: Option<ObjectId>,
+    /// the authorised heading, like `Wizards -- Fiction`, unique
+    pub heading: String,
+    /// how the heading is to be used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope_note: Option<String>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub created_at: DateTime<Utc>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct NewSubject {
+    #[validate(length(min = 1, max = 200))]
+    pub heading: String,
+    #[serde(default)]
+    #[validate(length(min = 1, max = 1000))]
+    pub scope_note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct UpdateSubject {
+    #[validate(length(min = 1, max = 200))]
+    pub heading: Option<String>,
+    #[validate(length(min = 1, max = 1000))]
+    pub scope_note: Option<String>,
+}
+
+impl From<NewSubject> for Subject {
+    fn from(value: NewSubject) -> Self {
+        Subject {
+            id: None,
+            heading: value.heading.trim().to_string(),
+            scope_note: value.scope_note,
+            created_at: DateTime::default(),
+            updated_at: DateTime::default(),
+        }
+    }
+}
+
+impl UpdateSubject {
+
+    ///
+    /// # into changes
+    /// this function return the fields set in the update, keyed by their name in database
+    /// # Arguments
+    /// * `self` - the update
+    /// # Return
+    /// * `HashMap<&str, Value>` - the fields to set
+    ///
+    pub fn into_changes(self) -> HashMap<&'static str, Value> {
+        let mut changes = HashMap::new();
+        if let Some(heading) = self.heading {
+            changes.insert("heading", Value::Text(heading.trim().to_string()));
+        }
+        if let Some(scope_note) = self.scope_note {
+            changes.insert("scope_note", Value::Text(scope_note));
+        }
+        changes
+    }
+}
+
+/// create a subject heading, only librarians manage the vocabulary
+#[openapi(tag = "Subjects")]
+#[post("/api/subject", data = "<subject>")]
+pub async fn create_subject(subject: Validated<NewSubject>, actor: Actor, db: Mongo) -> Result<Json<Subject>, ApiError> {
+    let subject = db.create_subject(subject.into_inner(), actor.id()).await?;
+    Ok(Json(subject))
+}
+
+/// list subject headings, sorted and filtered on created_at/updated_at
+#[openapi(tag = "Subjects")]
+#[get("/api/subject?<time..>")]
+pub async fn get_subjects(time: TimeFilter, db: Mongo) -> Result<Json<Vec<Subject>>, ApiError> {
+    let subjects = db.get_subjects(&time).await?;
+    Ok(Json(subjects))
+}
+
+/// list all books by subject heading, in call number order
+#[openapi(tag = "Subjects")]
+#[get("/api/subject/<heading>")]
+pub async fn get_books_by_subject(heading: &str, db: Mongo) -> Result<Json<Vec<Book>>, ApiError> {
+    let books = db.get_books_by_subject(heading).await?;
+    Ok(Json(books))
+}
+
+/// update the heading or the scope note of a subject, only librarians manage the vocabulary
+#[openapi(tag = "Subjects")]
+#[put("/api/subject/<id>", data = "<subject>")]
+pub async fn update_subject(id: &str, subject: Validated<UpdateSubject>, actor: Actor, db: Mongo) -> Result<Json<Subject>, ApiError> {
+    let subject = db.update_subject(id, subject.into_inner().into_changes(), actor.id()).await?;
+    Ok(Json(subject))
+}
+
+/// delete a subject heading, only librarians manage the vocabulary, refused with 409 while a book has it
+#[openapi(tag = "Subjects")]
+#[delete("/api/subject/<id>")]
+pub async fn delete_subject(id: &str, actor: Actor, db: Mongo) -> Result<Json<Subject>, ApiError> {
+    let subject = db.delete_subject(id, actor.id()).await?;
+    Ok(Json(subject))
+}
@@ -1,47 +1,143 @@
 use std::collections::HashMap;
+use std::error::Error;
+use bson::{doc, Document};
+use bson::oid::ObjectId;
 use serde::{Serialize, Deserialize};
-use rocket::form::FromForm;
+use schemars::JsonSchema;
+use validator::Validate;
+use rocket::{FromForm, FromFormField};
 use rocket::serde::json::Json;
-use rocket::State;
 use crate::mongo::Mongo;
-use std::error::Error;
-use rocket::response::Debug;
+use rocket::{get, post, put, patch, delete};
+use rocket::http::Status;
+use rocket_okapi::openapi;
+use chrono::{DateTime, NaiveDate, Utc};
+use crate::{Deletion, TimeFilter, Value};
+use crate::actor::Actor;
+use crate::error::{ApiError, HttpError};
+use crate::etag::{IfMatch, IfNoneMatch, Tagged};
+use crate::patch::Patch;
+use crate::validation::Validated;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct User {
     pub first_name: String,
     pub last_name: String,
     pub email: String,
-    pub birth_date: String,
+    #[schemars(with = "NaiveDate")]
+    #[serde(with = "crate::date::naive_date")]
+    pub birth_date: NaiveDate,
     pub borrowed_books: Vec<String>,
     pub role: String,
+    /// incremented on every change, sent as the `ETag` of the document
+    #[serde(default)]
+    pub version: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<Deletion>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub created_at: DateTime<Utc>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromForm)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
 pub struct NewUser {
+    #[validate(length(min = 1, max = 100))]
     pub first_name: String,
+    #[validate(length(min = 1, max = 100))]
     pub last_name: String,
+    #[validate(email, length(max = 254))]
     pub email: String,
-    pub birth_date: String,
+    #[validate(custom(function = "crate::validation::birth_date"))]
+    pub birth_date: NaiveDate,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromForm)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromForm, JsonSchema, Validate)]
 pub struct SearchUser {
+    #[validate(length(min = 1, max = 100))]
     pub first_name: Option<String>,
+    #[validate(length(min = 1, max = 100))]
     pub last_name: Option<String>,
+    #[validate(length(min = 1, max = 254))]
     pub email: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromForm)]
+/// The editable fields of a user, `borrowed_books` only changes when a book is borrowed or returned.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
 pub struct UpdateUser {
+    #[validate(length(min = 1, max = 100))]
     pub first_name: Option<String>,
+    #[validate(length(min = 1, max = 100))]
     pub last_name: Option<String>,
+    #[validate(email, length(max = 254))]
     pub email: Option<String>,
-    pub birth_date: Option<String>,
-    pub borrowed_books: Option<Vec<String>>,
+    #[validate(custom(function = "crate::validation::birth_date"))]
+    pub birth_date: Option<NaiveDate>,
+    /// only librarians change the role of a user
+    #[validate(custom(function = "crate::validation::role"))]
     pub role: Option<String>,
 }
 
+/// user id kept on the comments of a deleted user when they are anonymised
+pub const DELETED_USER_ID: &str = "000000000000000000000000";
+
+/// what happens to the comments of a user when the user is deleted.
+/// deleted comments go to the trash with the user, anonymised comments are anonymised when the user is purged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField, JsonSchema)]
+#[schemars(rename_all = "lowercase")]
+pub enum UserCommentPolicy {
+    Anonymise,
+    Delete,
+}
+
+impl From<&User> for UpdateUser {
+    fn from(value: &User) -> Self {
+        UpdateUser {
+            first_name: Some(value.first_name.clone()),
+            last_name: Some(value.last_name.clone()),
+            email: Some(value.email.clone()),
+            birth_date: Some(value.birth_date),
+            role: Some(value.role.clone()),
+        }
+    }
+}
+
+impl UpdateUser {
+
+    /// fields of a user that a patch cannot remove
+    pub const REQUIRED: [&'static str; 5] = ["first_name", "last_name", "email", "birth_date", "role"];
+
+    ///
+    /// # into changes
+    /// this function return the fields set in the update, keyed by their name in database
+    /// # Arguments
+    /// * `self` - the update
+    /// # Return
+    /// * `HashMap<&str, Value>` - the fields to set
+    ///
+    pub fn into_changes(self) -> HashMap<&'static str, Value> {
+        let mut changes = HashMap::new();
+        if let Some(first_name) = self.first_name {
+            changes.insert("first_name", Value::Text(first_name));
+        }
+        if let Some(last_name) = self.last_name {
+            changes.insert("last_name", Value::Text(last_name));
+        }
+        if let Some(email) = self.email {
+            changes.insert("email", Value::Text(email));
+        }
+        if let Some(birth_date) = self.birth_date {
+            changes.insert("birth_date", Value::Date(birth_date));
+        }
+        if let Some(role) = self.role {
+            changes.insert("role", Value::Text(role));
+        }
+        changes
+    }
+}
+
 impl From<NewUser> for User {
     fn from(value: NewUser) -> Self {
         User {
@@ -51,34 +147,144 @@ impl From<NewUser> for User {
             birth_date: value.birth_date,
             borrowed_books: Vec::new(),
             role: "user".to_string(),
+            version: 1,
+            deleted: None,
+            created_at: DateTime::default(),
+            updated_at: DateTime::default(),
         }
     }
 }
 
-#[rocket::post("/api/user", data = "<user>")]
-pub async fn create_user(user: Json<NewUser>, db: &State<Mongo>) -> Result<Json<User>, Debug<Box<dyn Error>>> {
-    let new_user = db.create_user(user.into_inner()).await?;
+impl User {
+
+    ///
+    /// # age
+    /// this function compute the age of the user in full years at the current date
+    /// # Return
+    /// * `i32` - the age of the user
+    ///
+    pub fn age(&self) -> i32 {
+        self.age_on(Utc::now().date_naive())
+    }
+
+    ///
+    /// # age on
+    /// this function compute the age of the user in full years at a date, a year is only full on the birthday
+    /// # Arguments
+    /// * `date` - the date
+    /// # Return
+    /// * `i32` - the age of the user, 0 before their birth
+    ///
+    pub fn age_on(&self, date: NaiveDate) -> i32 {
+        date.years_since(self.birth_date).unwrap_or(0) as i32
+    }
+
+    ///
+    /// # books to release
+    /// this function return the ids of the books the user holds, made available again when the user is deleted
+    /// # Arguments
+    /// * `self` - the user
+    /// * `force` - delete the user even if they still hold books
+    /// # Return
+    /// * `Result<Vec<ObjectId>, Box<dyn Error>>` - the ids, or `409` for a user holding books without force
+    ///
+    pub fn books_to_release(&self, force: bool) -> Result<Vec<ObjectId>, Box<dyn Error>> {
+        if !self.borrowed_books.is_empty() && !force {
+            return Err(HttpError::new(Status::Conflict, "User still holds borrowed books, use force to delete it").into());
+        }
+        Ok(self.borrowed_books.iter().map(ObjectId::parse_str).collect::<Result<Vec<_>, _>>()?)
+    }
+}
+
+///
+/// # release books
+/// this function return the update making books available again, with the filter of the books it applies to
+/// # Arguments
+/// * `book_ids` - the ids of the books held by a deleted user
+/// # Return
+/// * `(Document, Document)` - the filter and the update
+///
+pub fn release_books(book_ids: &[ObjectId]) -> (Document, Document) {
+    (
+        doc! {"_id": {"$in": book_ids}},
+        doc! {"$set": {"availability": true, "updated_at": bson::DateTime::now()}, "$unset": {"loan": ""}, "$inc": {"version": 1}},
+    )
+}
+
+///
+/// # unlink comments
+/// this function return the updates removing the helpful votes and the flags of a deleted user from the comments,
+/// each with the filter of the comments it applies to, so that only the comments the user voted for lose a vote
+/// # Arguments
+/// * `user_id` - the id of the deleted user
+/// # Return
+/// * `[(Document, Document); 2]` - the filters and the updates
+///
+pub fn unlink_comments(user_id: &str) -> [(Document, Document); 2] {
+    [
+        (doc! {"helpful_votes": user_id}, doc! {"$pull": {"helpful_votes": user_id}, "$inc": {"helpful_count": -1}}),
+        (doc! {"flags.user_id": user_id}, doc! {"$pull": {"flags": {"user_id": user_id}}}),
+    ]
+}
+
+/// create a user
+#[openapi(tag = "Users")]
+#[post("/api/user", data = "<user>")]
+pub async fn create_user(user: Validated<NewUser>, actor: Actor, db: Mongo) -> Result<Json<User>, ApiError> {
+    let new_user = db.create_user(user.into_inner(), actor.id()).await?;
     Ok(Json(new_user))
 }
 
-#[rocket::get("/api/user")]
-pub async fn get_users(db: &State<Mongo>) -> Result<Json<Vec<User>>, Debug<Box<dyn Error>>> {
-    let users = db.get_all_users().await?;
+/// list users, sorted and filtered on created_at/updated_at
+#[openapi(tag = "Users")]
+#[get("/api/user?<time..>")]
+pub async fn get_users(time: TimeFilter, db: Mongo) -> Result<Json<Vec<User>>, ApiError> {
+    let users = db.get_all_users(&time).await?;
     Ok(Json(users))
 }
 
-#[rocket::delete("/api/user/<id>")]
-pub async fn delete_user(id: &str, db: &State<Mongo>) -> Result<Json<User>, Debug<Box<dyn Error>>> {
-    let user = db.delete_user(id).await?;
+/// get a user, sent with its ETag, 304 when If-None-Match matches it
+#[openapi(tag = "Users")]
+#[get("/api/user/<id>")]
+pub async fn get_user(id: &str, if_none_match: IfNoneMatch, db: Mongo) -> Result<Tagged<User>, ApiError> {
+    let user = db.get_user_by_id(id).await?;
+    let version = user.version;
+    Ok(Tagged::new(user, version).unless(&if_none_match))
+}
+
+/// move a user to the trash, a user holding books is only deleted with force
+#[openapi(tag = "Users")]
+#[delete("/api/user/<id>?<force>&<comments>")]
+pub async fn delete_user(id: &str, force: Option<bool>, comments: Option<UserCommentPolicy>, actor: Actor, db: Mongo) -> Result<Json<User>, ApiError> {
+    let comments = comments.unwrap_or(UserCommentPolicy::Anonymise);
+    let user = db.delete_user(id, force.unwrap_or(false), comments, actor.id()).await?;
     Ok(Json(user))
 }
 
-#[rocket::post("/api/user/search", data = "<user>")]
-pub async fn search_user(user: Json<SearchUser>, db: &State<Mongo>) -> Result<Json<Vec<User>>, Debug<Box<dyn Error>>> {
+/// list deleted users
+#[openapi(tag = "Trash")]
+#[get("/api/user/trash")]
+pub async fn get_deleted_users(db: Mongo) -> Result<Json<Vec<User>>, ApiError> {
+    let users = db.get_deleted_users().await?;
+    Ok(Json(users))
+}
+
+/// restore a deleted user, only librarians restore from the trash, refused with 409 when the email was taken since
+#[openapi(tag = "Trash")]
+#[post("/api/user/trash/<id>/restore")]
+pub async fn restore_user(id: &str, actor: Actor, db: Mongo) -> Result<Json<User>, ApiError> {
+    let user = db.restore_user(id, actor.id()).await?;
+    Ok(Json(user))
+}
+
+/// search users by first name, last name or email
+#[openapi(tag = "Users")]
+#[post("/api/user/search?<time..>", data = "<user>")]
+pub async fn search_user(user: Validated<SearchUser>, time: TimeFilter, db: Mongo) -> Result<Json<Vec<User>>, ApiError> {
 
     let mut hashmap = HashMap::new();
     if user.first_name.is_none() && user.last_name.is_none() && user.email.is_none() {
-        return Err(Debug(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "No search criteria provided"))));
+        return Err(ApiError(HttpError::new(Status::BadRequest, "No search criteria provided").into()));
     }
     match &user.first_name {
         Some(first_name) => hashmap.insert("first_name", first_name.clone()),
@@ -92,40 +298,33 @@ pub async fn search_user(user: Json<SearchUser>, db: &State<Mongo>) -> Result<Js
         Some(email) => hashmap.insert("email", email.clone()),
         None => None,
     };
-    let users = db.search_user(hashmap).await?;
+    let users = db.search_user(hashmap, &time).await?;
     Ok(Json(users))
 }
 
-#[rocket::put("/api/user/<id>", data = "<user>")]
-pub async fn update_user(id: &str, user: Json<UpdateUser>, db: &State<Mongo>) -> Result<Json<User>, Debug<Box<dyn Error>>> {
-    let mut hashmap = HashMap::new();
+/// update user, refused with 412 when If-Match is not the current version and with 403 for a change of role unless a librarian asks
+#[openapi(tag = "Users")]
+#[put("/api/user/<id>", data = "<user>")]
+pub async fn update_user(id: &str, user: Validated<UpdateUser>, if_match: IfMatch, actor: Actor, db: Mongo) -> Result<Tagged<User>, ApiError> {
+    let hashmap = user.into_inner().into_changes();
 
-    match &user.first_name {
-        Some(first_name) => hashmap.insert("first_name", first_name.clone()),
-        None => None,
-    };
-    match &user.last_name {
-        Some(last_name) => hashmap.insert("last_name", last_name.clone()),
-        None => None,
-    };
-    match &user.email {
-        Some(email) => hashmap.insert("email", email.clone()),
-        None => None,
-    };
-    match &user.birth_date {
-        Some(birth_date) => hashmap.insert("birth_date", birth_date.clone()),
-        None => None,
-    };
-    if user.borrowed_books.is_some() {
-        for book in user.borrowed_books.clone().unwrap() {
-            hashmap.insert("borrowed_books", book.clone());
-        }
-    };
-    match &user.role {
-        Some(role) => hashmap.insert("role", role.clone()),
-        None => None,
-    };
+    if hashmap.is_empty() {
+        let user = db.get_user_by_id(id).await?;
+        if_match.check(user.version, "User")?;
+        let version = user.version;
+        return Ok(Tagged::new(user, version));
+    }
+    let updated_user = db.update_user(id, hashmap, if_match.versions(), actor.id()).await?;
+    let version = updated_user.version;
+    Ok(Tagged::new(updated_user, version))
+}
 
-    let updated_user = db.update_user(id, hashmap).await?;
-    Ok(Json(updated_user))
+/// patch user with a JSON Merge Patch (RFC 7396) or a JSON Patch (RFC 6902), chosen by the content type,
+/// e.g. `[{"op": "replace", "path": "/email", "value": "ada@example.org"}]`
+#[openapi(tag = "Users")]
+#[patch("/api/user/<id>", data = "<patch>")]
+pub async fn patch_user(id: &str, patch: Patch, if_match: IfMatch, actor: Actor, db: Mongo) -> Result<Tagged<User>, ApiError> {
+    let user = db.patch_user(id, &patch, if_match.versions(), actor.id()).await?;
+    let version = user.version;
+    Ok(Tagged::new(user, version))
 }
\ No newline at end of file
@@ -0,0 +1,410 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+use rocket::catch;
+use rocket::data::{Data, FromData, Outcome};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::serde::json::{self, Json};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::RequestBody;
+use rocket_okapi::request::OpenApiFromData;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+use crate::error::{ErrorBody, HttpError};
+use crate::request_log::RequestError;
+
+/// roles a user can be given
+pub const ROLES: [&str; 2] = ["user", "librarian"];
+
+/// ISO 639-1 codes of the languages
+pub const LANGUAGES: &[&str] = &[
+    "aa", "ab", "ae", "af", "ak", "am", "an", "ar", "as", "av", "ay", "az", "ba", "be", "bg", "bi", "bm", "bn", "bo", "br",
+    "bs", "ca", "ce", "ch", "co", "cr", "cs", "cu", "cv", "cy", "da", "de", "dv", "dz", "ee", "el", "en", "eo", "es", "et",
+    "eu", "fa", "ff", "fi", "fj", "fo", "fr", "fy", "ga", "gd", "gl", "gn", "gu", "gv", "ha", "he", "hi", "ho", "hr", "ht",
+    "hu", "hy", "hz", "ia", "id", "ie", "ig", "ii", "ik", "io", "is", "it", "iu", "ja", "jv", "ka", "kg", "ki", "kj", "kk",
+    "kl", "km", "kn", "ko", "kr", "ks", "ku", "kv", "kw", "ky", "la", "lb", "lg", "li", "ln", "lo", "lt", "lu", "lv", "mg",
+    "mh", "mi", "mk", "ml", "mn", "mr", "ms", "mt", "my", "na", "nb", "nd", "ne", "ng", "nl", "nn", "no", "nr", "nv", "ny",
+    "oc", "oj", "om", "or", "os", "pa", "pi", "pl", "ps", "pt", "qu", "rm", "rn", "ro", "ru", "rw", "sa", "sc", "sd", "se",
+    "sg", "si", "sk", "sl", "sm", "sn", "so", "sq", "sr", "ss", "st", "su", "sv", "sw", "ta", "te", "tg", "th", "ti", "tk",
+    "tl", "tn", "to", "tr", "ts", "tt", "tw", "ty", "ug", "uk", "ur", "uz", "ve", "vi", "vo", "wa", "wo", "xh", "yi", "yo",
+    "za", "zh", "zu",
+];
+
+/// operators accepted by the search by rating
+pub const RATING_OPERATORS: [&str; 6] = ["=", "!=", ">", ">=", "<", "<="];
+
+/// The messages of the invalid fields of a request body, keyed by the path of the field.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldErrors(pub BTreeMap<String, Vec<String>>);
+
+impl fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<String> = self.0.iter()
+            .map(|(field, messages)| format!("{}: {}", field, messages.join(", ")))
+            .collect();
+        write!(f, "Validation failed: {}", fields.join("; "))
+    }
+}
+
+impl Error for FieldErrors {}
+
+impl From<ValidationErrors> for FieldErrors {
+    fn from(value: ValidationErrors) -> Self {
+        let mut fields = BTreeMap::new();
+        collect(&mut fields, "", &value);
+        FieldErrors(fields)
+    }
+}
+
+///
+/// # collect
+/// this function flatten the errors of a struct and of its nested structs and lists
+/// # Arguments
+/// * `fields` - the messages, keyed by the path of the field
+/// * `prefix` - the path of the struct
+/// * `errors` - the errors of the struct
+///
+fn collect(fields: &mut BTreeMap<String, Vec<String>>, prefix: &str, errors: &ValidationErrors) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() { field.to_string() } else { format!("{}.{}", prefix, field) };
+        match kind {
+            // rules of the whole struct are keyed by their code
+            ValidationErrorsKind::Field(errors) if *field == "__all__" => {
+                for error in errors {
+                    let path = if prefix.is_empty() { error.code.to_string() } else { format!("{}.{}", prefix, error.code) };
+                    fields.entry(path).or_default().push(message(error));
+                }
+            }
+            ValidationErrorsKind::Field(errors) => {
+                fields.entry(path).or_default().extend(errors.iter().map(message));
+            }
+            ValidationErrorsKind::Struct(errors) => collect(fields, &path, errors),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect(fields, &format!("{}[{}]", path, index), errors);
+                }
+            }
+        }
+    }
+}
+
+///
+/// # message
+/// this function return the message sent to the client for a failed rule
+/// # Arguments
+/// * `error` - the failed rule
+/// # Return
+/// * `String` - its message, or one written from its code and parameters
+///
+fn message(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+    let param = |name: &str| error.params.get(name).map(|value| value.to_string());
+    match (error.code.as_ref(), param("min"), param("max")) {
+        ("length", Some(min), Some(max)) => format!("must be between {} and {} characters", min, max),
+        ("length", Some(min), None) => format!("must be at least {} characters", min),
+        ("length", None, Some(max)) => format!("must be at most {} characters", max),
+        ("range", Some(min), Some(max)) => format!("must be between {} and {}", min, max),
+        ("range", Some(min), None) => format!("must be at least {}", min),
+        ("range", None, Some(max)) => format!("must be at most {}", max),
+        ("email", _, _) => "must be a valid email address".to_string(),
+        (code, _, _) => format!("is invalid ({})", code),
+    }
+}
+
+///
+/// # invalid
+/// this function build a failed rule with its message
+/// # Arguments
+/// * `code` - the code of the rule
+/// * `message` - the message sent to the client
+/// # Return
+/// * `ValidationError` - the failed rule
+///
+fn invalid(code: &'static str, message: String) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(message.into());
+    error
+}
+
+///
+/// # object id
+/// this function check that an id is an ObjectId
+/// # Arguments
+/// * `id` - the id
+/// # Return
+/// * `Result<(), ValidationError>` - nothing, or an error unless it is 24 hexadecimal digits
+///
+pub fn object_id(id: &str) -> Result<(), ValidationError> {
+    match bson::oid::ObjectId::parse_str(id) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(invalid("object_id", "must be an ObjectId of 24 hexadecimal digits".to_string())),
+    }
+}
+
+///
+/// # parse id
+/// this function parse the id of a path, an id that is not an ObjectId names no document
+/// # Arguments
+/// * `id` - the id
+/// * `name` - the name of the document, as in `Book`
+/// # Return
+/// * `Result<ObjectId, HttpError>` - the ObjectId, or `404` naming the document
+///
+pub fn parse_id(id: &str, name: &str) -> Result<bson::oid::ObjectId, HttpError> {
+    bson::oid::ObjectId::parse_str(id).map_err(|_| HttpError::new(Status::NotFound, format!("{} not found", name)))
+}
+
+///
+/// # object ids
+/// this function check that every id of a list is an ObjectId
+/// # Arguments
+/// * `ids` - the ids
+/// # Return
+/// * `Result<(), ValidationError>` - nothing, or an error naming the first invalid id
+///
+pub fn object_ids(ids: &[String]) -> Result<(), ValidationError> {
+    match ids.iter().position(|id| object_id(id).is_err()) {
+        Some(index) => Err(invalid("object_id", format!("item {} must be an ObjectId of 24 hexadecimal digits", index))),
+        None => Ok(()),
+    }
+}
+
+///
+/// # role
+/// this function check that a role is one of `ROLES`
+/// # Arguments
+/// * `role` - the role
+/// # Return
+/// * `Result<(), ValidationError>` - nothing or an error
+///
+pub fn role(role: &str) -> Result<(), ValidationError> {
+    match ROLES.contains(&role) {
+        true => Ok(()),
+        false => Err(invalid("role", format!("must be one of {}", ROLES.join(", ")))),
+    }
+}
+
+///
+/// # birth date
+/// this function check that a birth date is not in the future
+/// # Arguments
+/// * `date` - the birth date
+/// # Return
+/// * `Result<(), ValidationError>` - nothing or an error
+///
+pub fn birth_date(date: &chrono::NaiveDate) -> Result<(), ValidationError> {
+    match *date <= chrono::Utc::now().date_naive() {
+        true => Ok(()),
+        false => Err(invalid("birth_date", "cannot be in the future".to_string())),
+    }
+}
+
+///
+/// # rating operator
+/// this function check that an operator is one of `RATING_OPERATORS`
+/// # Arguments
+/// * `operator` - the operator
+/// # Return
+/// * `Result<(), ValidationError>` - nothing or an error
+///
+pub fn rating_operator(operator: &str) -> Result<(), ValidationError> {
+    match RATING_OPERATORS.contains(&operator) {
+        true => Ok(()),
+        false => Err(invalid("operator", format!("must be one of {}", RATING_OPERATORS.join(" ")))),
+    }
+}
+
+///
+/// # isbn 10
+/// this function check the check digit of an ISBN-10
+/// # Arguments
+/// * `isbn` - the ISBN, hyphens and spaces are ignored
+/// # Return
+/// * `Result<(), ValidationError>` - nothing or an error
+///
+pub fn isbn_10(isbn: &str) -> Result<(), ValidationError> {
+    match crate::isbn::is_isbn_10(&crate::isbn::normalise(isbn)) {
+        true => Ok(()),
+        false => Err(invalid("isbn_10", "must be an ISBN-10 of 9 digits and a check digit".to_string())),
+    }
+}
+
+///
+/// # isbn 13
+/// this function check the prefix and the check digit of an ISBN-13
+/// # Arguments
+/// * `isbn` - the ISBN, hyphens and spaces are ignored
+/// # Return
+/// * `Result<(), ValidationError>` - nothing or an error
+///
+pub fn isbn_13(isbn: &str) -> Result<(), ValidationError> {
+    match crate::isbn::is_isbn_13(&crate::isbn::normalise(isbn)) {
+        true => Ok(()),
+        false => Err(invalid("isbn_13", "must be an ISBN-13 starting with 978 or 979 with a valid check digit".to_string())),
+    }
+}
+
+///
+/// # isbn pair
+/// this function check that an ISBN-10 and an ISBN-13 given together are the same book
+/// # Arguments
+/// * `isbn_10` - the ISBN-10
+/// * `isbn_13` - the ISBN-13
+/// # Return
+/// * `Result<(), ValidationError>` - nothing, or an error keyed `isbn`
+///
+pub fn isbn_pair(isbn_10: Option<&str>, isbn_13: Option<&str>) -> Result<(), ValidationError> {
+    let (Some(isbn_10), Some(isbn_13)) = (isbn_10, isbn_13) else { return Ok(()) };
+    match crate::isbn::to_isbn_13(&crate::isbn::normalise(isbn_10)) == crate::isbn::normalise(isbn_13) {
+        true => Ok(()),
+        false => Err(invalid("isbn", "isbn_10 and isbn_13 must be the same book".to_string())),
+    }
+}
+
+///
+/// # volume in series
+/// this function check that a book has a volume number only when it belongs to a series
+/// # Arguments
+/// * `series` - the series
+/// * `volume` - the volume number
+/// # Return
+/// * `Result<(), ValidationError>` - nothing, or an error keyed `volume`
+///
+pub fn volume_in_series(series: Option<&str>, volume: Option<i32>) -> Result<(), ValidationError> {
+    match (series, volume) {
+        (None, Some(_)) => Err(invalid("volume", "requires series".to_string())),
+        _ => Ok(()),
+    }
+}
+
+///
+/// # language
+/// this function check that a language is an ISO 639 code: one of `LANGUAGES`, or three letters for ISO 639-2 and 639-3
+/// # Arguments
+/// * `language` - the code, case insensitive
+/// # Return
+/// * `Result<(), ValidationError>` - nothing or an error
+///
+pub fn language(language: &str) -> Result<(), ValidationError> {
+    let language = language.to_lowercase();
+    let valid = match language.len() {
+        2 => LANGUAGES.contains(&language.as_str()),
+        3 => language.bytes().all(|c| c.is_ascii_lowercase()),
+        _ => false,
+    };
+    match valid {
+        true => Ok(()),
+        false => Err(invalid("language", "must be an ISO 639-1 code of 2 letters or an ISO 639-2 code of 3 letters".to_string())),
+    }
+}
+
+///
+/// # call number
+/// this function check that a call number is a Dewey Decimal class, optionally followed by a cutter
+/// # Arguments
+/// * `call_number` - the call number, case and spacing are ignored
+/// # Return
+/// * `Result<(), ValidationError>` - nothing or an error
+///
+pub fn call_number(call_number: &str) -> Result<(), ValidationError> {
+    match crate::call_number::is_dewey(&crate::call_number::normalise(call_number)) {
+        true => Ok(()),
+        false => Err(invalid("call_number", "must be a Dewey Decimal class of 3 digits and optional decimals, like 823.912 ROW".to_string())),
+    }
+}
+
+///
+/// # validate
+/// this function run the rules of a value built outside of a data guard, like a patched document
+/// # Arguments
+/// * `value` - the value
+/// # Return
+/// * `Result<(), Box<dyn Error>>` - nothing, or the `FieldErrors` answered with `422`
+///
+pub fn validate<T: Validate>(value: &T) -> Result<(), Box<dyn Error>> {
+    value.validate().map_err(|errors| FieldErrors::from(errors).into())
+}
+
+/// Why a request body was refused, kept in the request local cache for the `422` catcher.
+struct Rejection(Option<ErrorBody>);
+
+/// Error of the `Validated` data guard.
+#[derive(Debug)]
+pub enum ValidatedError<'r> {
+    Json(json::Error<'r>),
+    Fields(FieldErrors),
+}
+
+/// A JSON request body checked against the validation rules of its type.
+/// The body is read like `Json<T>`; when a rule fails the request is answered `422`
+/// with the messages of the invalid fields.
+#[derive(Debug, Clone)]
+pub struct Validated<T>(pub T);
+
+impl<T> Validated<T> {
+
+    ///
+    /// # into inner
+    /// this function return the validated value
+    /// # Arguments
+    /// * `self` - the guard
+    /// # Return
+    /// * `T` - the value
+    ///
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Validated<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned + Validate> FromData<'r> for Validated<T> {
+    type Error = ValidatedError<'r>;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r, Self> {
+        match Json::<T>::from_data(request, data).await {
+            Outcome::Success(value) => match value.validate() {
+                Ok(()) => Outcome::Success(Validated(value.into_inner())),
+                Err(errors) => {
+                    let errors = FieldErrors::from(errors);
+                    request.local_cache(|| RequestError(Some(errors.to_string())));
+                    request.local_cache(|| Rejection(Some(ErrorBody::fields(errors.clone()))));
+                    Outcome::Error((Status::UnprocessableEntity, ValidatedError::Fields(errors)))
+                }
+            },
+            Outcome::Error((status, error)) => {
+                request.local_cache(|| RequestError(Some(error.to_string())));
+                request.local_cache(|| Rejection(Some(ErrorBody::new(error.to_string()))));
+                Outcome::Error((status, ValidatedError::Json(error)))
+            }
+            Outcome::Forward(forward) => Outcome::Forward(forward),
+        }
+    }
+}
+
+impl<'r, T: JsonSchema + DeserializeOwned + Validate> OpenApiFromData<'r> for Validated<T> {
+    fn request_body(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<RequestBody> {
+        Json::<T>::request_body(gen)
+    }
+}
+
+/// answer a refused request body with its error and the messages of the invalid fields
+#[catch(422)]
+pub fn unprocessable_entity(request: &Request) -> Json<ErrorBody> {
+    match &request.local_cache(|| Rejection(None)).0 {
+        Some(body) => Json(body.clone()),
+        None => Json(ErrorBody::new("Unprocessable entity")),
+    }
+}
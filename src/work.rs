@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use crate::mongo::Mongo;
+use rocket::{get, post, put, delete};
+use rocket_okapi::openapi;
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use validator::Validate;
+use rocket::serde::json::Json;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use crate::{TimeFilter, Value};
+use crate::actor::Actor;
+use crate::book::{Book, BookFormat};
+use crate::error::ApiError;
+use crate::validation::Validated;
+
+/// A work: the text shared by its editions, translations and reprints, which are books referencing it by `work_id`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Work {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "crate::openapi::object_id")]
+    pub id: Option<ObjectId>,
+    /// original title
+    pub title: String,
+    /// the credit as printed on the first edition
+    pub author: String,
+    /// year of first publication
+    #[serde(default)]
+    pub year: Option<i32>,
+    /// ISO 639 code of the original language
+    #[serde(default)]
+    pub original_language: Option<String>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub created_at: DateTime<Utc>,
+    #[schemars(with = "DateTime<Utc>")]
+    #[serde(with = "crate::date::date_time")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct NewWork {
+    #[validate(length(min = 1, max = 200))]
+    pub title: String,
+    #[validate(length(min = 1, max = 200))]
+    pub author: String,
+    #[validate(range(min = -3000, max = 2100))]
+    pub year: Option<i32>,
+    #[validate(custom(function = "crate::validation::language"))]
+    pub original_language: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Validate)]
+pub struct UpdateWork {
+    #[validate(length(min = 1, max = 200))]
+    pub title: Option<String>,
+    #[validate(length(min = 1, max = 200))]
+    pub author: Option<String>,
+    #[validate(range(min = -3000, max = 2100))]
+    pub year: Option<i32>,
+    #[validate(custom(function = "crate::validation::language"))]
+    pub original_language: Option<String>,
+}
+
+/// A book as an edition of a work.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Edition {
+    pub book_id: String,
+    pub title: String,
+    pub year: i32,
+    pub availability: bool,
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub publisher: Option<String>,
+    #[serde(default)]
+    pub edition: Option<String>,
+    #[serde(default)]
+    pub format: Option<BookFormat>,
+    #[serde(default)]
+    pub isbn_13: Option<String>,
+}
+
+/// A work with its editions, by year, and the rating of the reviews of all of them.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WorkPage {
+    pub work: Work,
+    pub editions: Vec<Edition>,
+    /// average rating of the reviews, `None` without review
+    pub rating: Option<f64>,
+    /// number of reviews
+    pub reviews: u64,
+}
+
+/// A hit of a search collapsed by work: the editions found of one work, or a book without work as its only edition.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WorkHit {
+    /// `None` for a book without work
+    pub work_id: Option<String>,
+    /// title and author of the work, or of the book without work
+    pub title: String,
+    pub author: String,
+    pub editions: Vec<Edition>,
+}
+
+impl From<NewWork> for Work {
+    fn from(value: NewWork) -> Self {
+        Work {
+            id: None,
+            title: value.title,
+            author: value.author,
+            year: value.year,
+            original_language: value.original_language.map(|language| language.to_lowercase()),
+            created_at: DateTime::default(),
+            updated_at: DateTime::default(),
+        }
+    }
+}
+
+impl Edition {
+
+    ///
+    /// # new
+    /// this function describe a book as an edition
+    /// # Arguments
+    /// * `book_id` - the id of the book
+    /// * `book` - the book
+    /// # Return
+    /// * `Edition` - the edition
+    ///
+    pub fn new(book_id: String, book: &Book) -> Self {
+        Edition {
+            book_id,
+            title: book.title.clone(),
+            year: book.year,
+            availability: book.availability,
+            language: book.language.clone(),
+            publisher: book.publisher.clone(),
+            edition: book.edition.clone(),
+            format: book.format,
+            isbn_13: book.isbn_13.clone(),
+        }
+    }
+}
+
+impl WorkHit {
+
+    ///
+    /// # collapse
+    /// this function collapse the books found to one hit per work, in the order of their first edition found
+    /// # Arguments
+    /// * `books` - the books found, with their id, in order
+    /// # Return
+    /// * `Vec<WorkHit>` - the hits, a book without work is a hit of its own
+    ///
+    pub fn collapse(books: impl IntoIterator<Item = (String, Book)>) -> Vec<WorkHit> {
+        let mut hits: Vec<WorkHit> = Vec::new();
+        let mut hit_of_work: HashMap<String, usize> = HashMap::new();
+        for (book_id, book) in books {
+            let edition = Edition::new(book_id, &book);
+            if let Some(index) = book.work_id.as_ref().and_then(|work_id| hit_of_work.get(work_id)) {
+                hits[*index].editions.push(edition);
+                continue;
+            }
+            if let Some(work_id) = &book.work_id {
+                hit_of_work.insert(work_id.clone(), hits.len());
+            }
+            hits.push(WorkHit { work_id: book.work_id, title: book.title, author: book.author, editions: vec![edition] });
+        }
+        hits
+    }
+}
+
+///
+/// # average rating
+/// this function return the average rating of reviews from the sum of the ratings and the number of reviews of each book
+/// # Arguments
+/// * `totals` - the sum of the ratings and the number of reviews of each book
+/// # Return
+/// * `(Option<f64>, u64)` - the average rating, `None` without review, and the number of reviews
+///
+pub fn average_rating(totals: impl IntoIterator<Item = (f64, u64)>) -> (Option<f64>, u64) {
+    let (sum, reviews) = totals.into_iter().fold((0.0, 0), |(sum, reviews), (book_sum, book_reviews)| (sum + book_sum, reviews + book_reviews));
+    ((reviews > 0).then(|| sum / reviews as f64), reviews)
+}
+
+impl UpdateWork {
+
+    ///
+    /// # into changes
+    /// this function return the fields set in the update, keyed by their name in database
+    /// # Arguments
+    /// * `self` - the update
+    /// # Return
+    /// * `HashMap<&str, Value>` - the fields to set
+    ///
+    pub fn into_changes(self) -> HashMap<&'static str, Value> {
+        let mut changes = HashMap::new();
+        if let Some(title) = self.title {
+            changes.insert("title", Value::Text(title));
+        }
+        if let Some(author) = self.author {
+            changes.insert("author", Value::Text(author));
+        }
+        if let Some(year) = self.year {
+            changes.insert("year", Value::Int(year));
+        }
+        if let Some(original_language) = self.original_language {
+            changes.insert("original_language", Value::Text(original_language.to_lowercase()));
+        }
+        changes
+    }
+}
+
+/// create a work, its editions are the books given its id as `work_id`
+#[openapi(tag = "Works")]
+#[post("/api/work", data = "<work>")]
+pub async fn create_work(work: Validated<NewWork>, actor: Actor, db: Mongo) -> Result<Json<Work>, ApiError> {
+    let work = db.create_work(work.into_inner(), actor.id()).await?;
+    Ok(Json(work))
+}
+
+/// list works whose title contains `title`, case insensitive, sorted and filtered on created_at/updated_at
+#[openapi(tag = "Works")]
+#[get("/api/work?<title>&<time..>")]
+pub async fn get_works(title: Option<&str>, time: TimeFilter, db: Mongo) -> Result<Json<Vec<Work>>, ApiError> {
+    let works = db.get_works(title, &time).await?;
+    Ok(Json(works))
+}
+
+/// get a work with its editions and the rating of the reviews of all of them
+#[openapi(tag = "Works")]
+#[get("/api/work/<id>")]
+pub async fn get_work(id: &str, db: Mongo) -> Result<Json<WorkPage>, ApiError> {
+    let page = db.get_work_page(id).await?;
+    Ok(Json(page))
+}
+
+/// update a work
+#[openapi(tag = "Works")]
+#[put("/api/work/<id>", data = "<work>")]
+pub async fn update_work(id: &str, work: Validated<UpdateWork>, actor: Actor, db: Mongo) -> Result<Json<Work>, ApiError> {
+    let work = db.update_work(id, work.into_inner().into_changes(), actor.id()).await?;
+    Ok(Json(work))
+}
+
+/// delete a work, refused with 409 while a book is one of its editions
+#[openapi(tag = "Works")]
+#[delete("/api/work/<id>")]
+pub async fn delete_work(id: &str, actor: Actor, db: Mongo) -> Result<Json<Work>, ApiError> {
+    let work = db.delete_work(id, actor.id()).await?;
+    Ok(Json(work))
+}
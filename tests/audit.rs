@@ -0,0 +1,22 @@
+use bibliotheca::audit::diff;
+use bson::{doc, Bson};
+
+#[test]
+fn diff_lists_the_changed_fields_only() {
+    let before = doc! {"title": "Dune", "year": 1965, "resume": "", "updated_at": 1};
+    let after = doc! {"title": "Dune", "year": 1966, "pages": 412, "updated_at": 2};
+    let changes = diff(Some(&before), Some(&after));
+    assert_eq!(changes, doc! {
+        "year": {"before": 1965, "after": 1966},
+        "resume": {"before": "", "after": Bson::Null},
+        "pages": {"before": Bson::Null, "after": 412},
+    });
+}
+
+#[test]
+fn diff_of_a_creation_or_deletion_has_every_field() {
+    let book = doc! {"title": "Dune", "updated_at": 1};
+    assert_eq!(diff(None, Some(&book)), doc! {"title": {"before": Bson::Null, "after": "Dune"}});
+    assert_eq!(diff(Some(&book), None), doc! {"title": {"before": "Dune", "after": Bson::Null}});
+    assert!(diff(Some(&book), Some(&book)).is_empty());
+}
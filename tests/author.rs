@@ -0,0 +1,42 @@
+use bibliotheca::author::{name_key, name_keys, AuthorRole, BookAuthor, NewAuthor};
+use bibliotheca::book::{Book, NewBook};
+use bibliotheca::validation::{validate, FieldErrors};
+use rocket::serde::json::serde_json;
+
+#[test]
+fn life_dates_and_aliases_are_checked() {
+    let author: NewAuthor = serde_json::from_str(r#"{"name": "J. K. Rowling", "aliases": ["J.K. Rowling", "Robert Galbraith"], "birth_year": 1965}"#).unwrap();
+    assert!(validate(&author).is_ok());
+
+    let author: NewAuthor = serde_json::from_str(r#"{"name": "Victor Hugo", "aliases": [""]}"#).unwrap();
+    let error = validate(&author).unwrap_err();
+    assert_eq!(error.downcast_ref::<FieldErrors>().unwrap().0["aliases"], ["item 0 must be between 1 and 200 characters"]);
+
+    let author: NewAuthor = serde_json::from_str(r#"{"name": "Victor Hugo", "birth_year": 1885, "death_year": 1802}"#).unwrap();
+    let error = validate(&author).unwrap_err();
+    assert_eq!(error.downcast_ref::<FieldErrors>().unwrap().0["death_year"], ["must not be before birth_year"]);
+}
+
+#[test]
+fn books_credit_authors_with_a_role() {
+    let book: NewBook = serde_json::from_str(r#"{"title": "Les Misérables", "author": "Victor Hugo", "year": 1862, "resume": "",
+        "authors": [{"author_id": "65f0a0a0a0a0a0a0a0a0a0a0"}, {"author_id": "65f0a0a0a0a0a0a0a0a0a0a1", "role": "translator"}]}"#).unwrap();
+    assert!(validate(&book).is_ok());
+    let book = Book::from(book);
+    assert_eq!(book.authors[0], BookAuthor { author_id: "65f0a0a0a0a0a0a0a0a0a0a0".to_string(), role: AuthorRole::Author });
+    assert_eq!(book.authors[1].role, AuthorRole::Translator);
+
+    let book: NewBook = serde_json::from_str(r#"{"title": "Dune", "author": "Herbert", "year": 1965, "resume": "", "authors": [{"author_id": "nope", "role": "editor"}]}"#).unwrap();
+    let error = validate(&book).unwrap_err();
+    let fields = &error.downcast_ref::<FieldErrors>().unwrap().0;
+    assert!(fields.contains_key("authors[0].author_id"));
+}
+
+#[test]
+fn names_match_ignoring_case_punctuation_and_spaces() {
+    assert_eq!(name_key("J.K. Rowling"), name_key("j. k.  rowling"));
+    assert_eq!(name_key("Saint-Exupéry"), "saintexupéry");
+    assert_eq!(name_key("???"), "???");
+    let aliases = ["J. K. Rowling".to_string(), "Robert Galbraith".to_string()];
+    assert_eq!(name_keys("J.K. Rowling", &aliases), ["jkrowling", "robertgalbraith"]);
+}
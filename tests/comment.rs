@@ -0,0 +1,55 @@
+use bibliotheca::comment::{page_skip, Comment, CommentSort, CommentThread};
+use bson::doc;
+use bson::oid::ObjectId;
+use rocket::serde::json::serde_json;
+
+fn oid(id: u8) -> ObjectId {
+    ObjectId::from_bytes([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, id])
+}
+
+fn comment(id: u8, parent: Option<u8>, minute: u8) -> Comment {
+    let date = format!("2024-03-01T10:{:02}:00.000Z", minute);
+    serde_json::from_value(serde_json::json!({
+        "_id": {"$oid": oid(id).to_hex()}, "user_id": "65f0a0a0a0a0a0a0a0a0a0a0", "book_id": "65f0a0a0a0a0a0a0a0a0a0a1",
+        "comment": format!("Comment {id}"), "parent_id": parent.map(|parent| oid(parent).to_hex()),
+        "created_at": date, "updated_at": date,
+    })).unwrap()
+}
+
+fn texts(threads: &[CommentThread]) -> Vec<String> {
+    threads.iter().map(|thread| thread.comment.comment.clone()).collect()
+}
+
+#[test]
+fn replies_are_nested_oldest_first() {
+    let roots = vec![comment(2, None, 5), comment(1, None, 0)];
+    let replies = vec![comment(5, Some(1), 9), comment(3, Some(1), 1), comment(4, Some(3), 2), comment(6, Some(2), 6)];
+    let threads = CommentThread::build(roots, replies);
+    // the roots keep their order, the replies are sorted by date under their parent
+    assert_eq!(texts(&threads), ["Comment 2", "Comment 1"]);
+    assert_eq!(texts(&threads[0].replies), ["Comment 6"]);
+    assert_eq!(texts(&threads[1].replies), ["Comment 3", "Comment 5"]);
+    assert_eq!(texts(&threads[1].replies[0].replies), ["Comment 4"]);
+}
+
+#[test]
+fn replies_of_an_unlisted_comment_are_left_out() {
+    // the parent 7 is hidden or on another page
+    let threads = CommentThread::build(vec![comment(1, None, 0)], vec![comment(8, Some(7), 1)]);
+    assert_eq!(threads.len(), 1);
+    assert!(threads[0].replies.is_empty());
+}
+
+#[test]
+fn top_level_comments_are_sorted_with_a_tie_break() {
+    assert_eq!(CommentSort::Helpful.order(), doc! {"helpful_count": -1, "_id": -1});
+    assert_eq!(CommentSort::Recent.order(), doc! {"created_at": -1, "_id": -1});
+}
+
+#[test]
+fn pages_skip_the_comments_before_them() {
+    assert_eq!(page_skip(1, 20), 0);
+    assert_eq!(page_skip(3, 20), 40);
+    assert_eq!(page_skip(0, 20), 0);
+    assert_eq!(page_skip(u64::MAX, 100), i64::MAX as u64);
+}
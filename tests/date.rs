@@ -0,0 +1,50 @@
+use bibliotheca::date::to_stored_document;
+use bibliotheca::genre::Genre;
+use bibliotheca::user::User;
+use bson::Bson;
+use chrono::{NaiveDate, TimeZone, Utc};
+use rocket::serde::json::serde_json;
+
+fn user() -> User {
+    serde_json::from_str(r#"{"first_name": "Ada", "last_name": "Lovelace", "email": "ada@example.org",
+        "birth_date": "1815-12-10", "borrowed_books": [], "role": "member",
+        "created_at": "2024-03-01T10:00:00.000Z", "updated_at": "2024-03-02T11:30:00.000Z"}"#).unwrap()
+}
+
+#[test]
+fn api_dates_are_plain_text() {
+    let user = user();
+    assert_eq!(user.birth_date, NaiveDate::from_ymd_opt(1815, 12, 10).unwrap());
+    assert_eq!(user.created_at, Utc.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap());
+    let json = serde_json::to_value(&user).unwrap();
+    assert_eq!(json["birth_date"], "1815-12-10");
+    assert_eq!(json["created_at"], "2024-03-01T10:00:00.000Z");
+    assert_eq!(json["updated_at"], "2024-03-02T11:30:00.000Z");
+}
+
+#[test]
+fn stored_dates_are_bson_dates() {
+    let user = user();
+    let doc = to_stored_document(&user).unwrap();
+    assert!(matches!(doc.get("birth_date"), Some(Bson::DateTime(_))));
+    assert!(matches!(doc.get("created_at"), Some(Bson::DateTime(_))));
+    let read: User = bson::from_document(doc).unwrap();
+    assert_eq!(read.birth_date, user.birth_date);
+    assert_eq!(read.updated_at, user.updated_at);
+}
+
+#[test]
+fn missing_dates_are_rejected() {
+    assert!(serde_json::from_str::<Genre>(r#"{"name": "Fantasy"}"#).is_err());
+    assert!(serde_json::from_str::<Genre>(r#"{"name": "Fantasy", "created_at": "yesterday", "updated_at": "today"}"#).is_err());
+}
+
+#[test]
+fn ages_count_full_years_from_the_birthday() {
+    let user = user();
+    let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    assert_eq!(user.age_on(date(1833, 12, 9)), 17);
+    assert_eq!(user.age_on(date(1833, 12, 10)), 18);
+    assert_eq!(user.age_on(date(1833, 12, 11)), 18);
+    assert_eq!(user.age_on(date(1800, 1, 1)), 0);
+}
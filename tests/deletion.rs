@@ -0,0 +1,60 @@
+use bibliotheca::book::{unlink_borrowers, Book, NewBook};
+use bibliotheca::error::HttpError;
+use bibliotheca::user::{release_books, unlink_comments, User};
+use bson::doc;
+use bson::oid::ObjectId;
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+
+const BOOK_ID: &str = "65f0a0a0a0a0a0a0a0a0a0a0";
+const USER_ID: &str = "65f0a0a0a0a0a0a0a0a0a0a1";
+
+fn book() -> Book {
+    let book: NewBook = serde_json::from_str(r#"{"title": "Dune", "author": "Frank Herbert", "year": 1965, "resume": ""}"#).unwrap();
+    Book::from(book)
+}
+
+fn user(borrowed_books: &[&str]) -> User {
+    serde_json::from_value(serde_json::json!({"first_name": "Ada", "last_name": "Lovelace", "email": "ada@example.org",
+        "birth_date": "1815-12-10", "borrowed_books": borrowed_books, "role": "user",
+        "created_at": "2024-03-01T10:00:00.000Z", "updated_at": "2024-03-01T10:00:00.000Z"})).unwrap()
+}
+
+#[test]
+fn books_on_loan_are_only_deleted_with_force() {
+    let mut book = book();
+    assert!(book.check_deletion(false, false).is_ok());
+    // listed by a user, lent, or not available: the book is on loan
+    assert_eq!(book.check_deletion(true, false).unwrap_err().status, Status::Conflict);
+    book.availability = false;
+    assert_eq!(book.check_deletion(false, false).unwrap_err().status, Status::Conflict);
+    assert!(book.check_deletion(false, true).is_ok());
+}
+
+#[test]
+fn users_holding_books_are_only_deleted_with_force() {
+    assert!(user(&[]).books_to_release(false).unwrap().is_empty());
+    let error = user(&[BOOK_ID]).books_to_release(false).unwrap_err();
+    assert_eq!(error.downcast_ref::<HttpError>().unwrap().status, Status::Conflict);
+    assert_eq!(user(&[BOOK_ID]).books_to_release(true).unwrap(), [ObjectId::parse_str(BOOK_ID).unwrap()]);
+}
+
+#[test]
+fn references_to_deleted_documents_are_removed() {
+    let (filter, update) = unlink_borrowers(BOOK_ID);
+    assert_eq!(filter, doc! {"borrowed_books": BOOK_ID});
+    assert_eq!(update.get_document("$pull").unwrap(), &doc! {"borrowed_books": BOOK_ID});
+
+    let book_id = ObjectId::parse_str(BOOK_ID).unwrap();
+    let (filter, update) = release_books(&[book_id]);
+    assert_eq!(filter, doc! {"_id": {"$in": [book_id]}});
+    assert!(update.get_document("$set").unwrap().get_bool("availability").unwrap());
+    assert_eq!(update.get_document("$unset").unwrap(), &doc! {"loan": ""});
+
+    // only the comments the user voted for lose a vote
+    let [(votes, unvote), (flags, unflag)] = unlink_comments(USER_ID);
+    assert_eq!(votes, doc! {"helpful_votes": USER_ID});
+    assert_eq!(unvote, doc! {"$pull": {"helpful_votes": USER_ID}, "$inc": {"helpful_count": -1}});
+    assert_eq!(flags, doc! {"flags.user_id": USER_ID});
+    assert_eq!(unflag, doc! {"$pull": {"flags": {"user_id": USER_ID}}});
+}
@@ -0,0 +1,14 @@
+use bibliotheca::error::HttpError;
+use bibliotheca::etag::{EntityTags, IfMatch};
+use rocket::http::Status;
+
+#[test]
+fn unchanged_documents_still_check_if_match() {
+    assert!(IfMatch(None).check(4, "Book").is_ok());
+    assert!(IfMatch(Some(EntityTags::parse("*", false))).check(4, "Book").is_ok());
+    assert!(IfMatch(Some(EntityTags::parse("\"3\", \"4\"", false))).check(4, "Book").is_ok());
+    let error = IfMatch(Some(EntityTags::parse("\"3\"", false))).check(4, "Book").unwrap_err();
+    let error = error.downcast_ref::<HttpError>().unwrap();
+    assert_eq!(error.status, Status::PreconditionFailed);
+    assert_eq!(error.message, "Book was modified by someone else, reload it and retry");
+}
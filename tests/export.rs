@@ -0,0 +1,59 @@
+use bson::{doc, Document};
+use bibliotheca::config::MarcMapping;
+use bibliotheca::export::{encode, header, ExportEntity, ExportFilter, ExportFormat};
+
+fn book() -> Document {
+    doc! {
+        "_id": bson::oid::ObjectId::parse_str("65f0a0a0a0a0a0a0a0a0a0a0").unwrap(),
+        "title": "Dune, the novel",
+        "author": "Frank Herbert",
+        "year": 1965,
+        "genre_ids": ["65f0a0a0a0a0a0a0a0a0a0a1", "65f0a0a0a0a0a0a0a0a0a0a2"],
+        "isbn_10": "0441013597",
+        "isbn_13": "9780441013593",
+        "loan": { "user_id": "65f0a0a0a0a0a0a0a0a0a0a3" },
+    }
+}
+
+#[test]
+fn csv_rows_follow_the_header() {
+    let filter = ExportFilter { format: Some(ExportFormat::Csv), ..Default::default() };
+    let header = String::from_utf8(header(ExportEntity::Books, &filter).unwrap()).unwrap();
+    assert!(header.starts_with("_id,title,author,year,"));
+    let row = String::from_utf8(encode(ExportEntity::Books, &filter, &MarcMapping::default(), book()).unwrap()).unwrap();
+    assert!(row.starts_with("65f0a0a0a0a0a0a0a0a0a0a0,\"Dune, the novel\",Frank Herbert,1965,,,65f0a0a0a0a0a0a0a0a0a0a1;65f0a0a0a0a0a0a0a0a0a0a2,,0441013597,9780441013593,,,,,,,,,,,65f0a0a0a0a0a0a0a0a0a0a3,"));
+}
+
+#[test]
+fn anonymised_csv_has_no_personal_column() {
+    let filter = ExportFilter { format: Some(ExportFormat::Csv), anonymise: Some(true), ..Default::default() };
+    let header = String::from_utf8(header(ExportEntity::Users, &filter).unwrap()).unwrap();
+    assert_eq!(header, "_id,role,borrowed_books,created_at,updated_at\n");
+}
+
+#[test]
+fn jsonl_and_bson_round_trip() {
+    let filter = ExportFilter { format: Some(ExportFormat::Jsonl), ..Default::default() };
+    let line = String::from_utf8(encode(ExportEntity::Books, &filter, &MarcMapping::default(), book()).unwrap()).unwrap();
+    assert!(line.ends_with('\n') && !line.trim_end().contains('\n'));
+    assert!(line.contains("{\"$oid\":\"65f0a0a0a0a0a0a0a0a0a0a0\"}"));
+
+    let filter = ExportFilter { format: Some(ExportFormat::Bson), ..Default::default() };
+    let bytes = encode(ExportEntity::Books, &filter, &MarcMapping::default(), book()).unwrap();
+    assert_eq!(Document::from_reader(bytes.as_slice()).unwrap(), book());
+}
+
+#[test]
+fn catalogue_records_are_exported() {
+    for name in ["authors", "works", "subjects"] {
+        let entity = ExportEntity::from_name(name).unwrap();
+        assert_eq!(entity.to_string(), name);
+        assert!(entity.personal_fields().is_empty());
+    }
+    let filter = ExportFilter { format: Some(ExportFormat::Csv), ..Default::default() };
+    let author = doc! {"_id": bson::oid::ObjectId::parse_str("65f0a0a0a0a0a0a0a0a0a0a4").unwrap(), "name": "Frank Herbert", "aliases": ["F. Herbert"], "name_keys": ["fherbert", "frankherbert"]};
+    let header = String::from_utf8(header(ExportEntity::Authors, &filter).unwrap()).unwrap();
+    assert_eq!(header, "_id,name,aliases,birth_year,death_year,created_at,updated_at\n");
+    let row = String::from_utf8(encode(ExportEntity::Authors, &filter, &MarcMapping::default(), author).unwrap()).unwrap();
+    assert_eq!(row, "65f0a0a0a0a0a0a0a0a0a0a4,Frank Herbert,F. Herbert,,,,\n");
+}
@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use bibliotheca::config::MarcMapping;
+use bibliotheca::import::{parse, ImportFormat};
+use bibliotheca::marc::{marcxml_header, write_marcxml, Record, MARCXML_FOOTER};
+
+#[test]
+fn csv_rows_are_read_with_their_line() {
+    let content = "title,author,year,genres\nLes Misérables,Victor Hugo,1862,Novel; History\nDune,Frank Herbert,not a year,\n";
+    let rows = parse(ImportFormat::Csv, content.as_bytes(), &MarcMapping::default()).unwrap();
+    assert_eq!(rows.len(), 2);
+    let book = rows[0].book.as_ref().unwrap();
+    assert_eq!((rows[0].line, book.year), (2, 1862));
+    assert_eq!(book.genres, ["Novel", "History"]);
+    assert_eq!(rows[1].line, 3);
+    assert!(rows[1].book.is_err());
+}
+
+#[test]
+fn csv_without_required_column_is_refused() {
+    assert!(parse(ImportFormat::Csv, b"title,year\nDune,1965\n", &MarcMapping::default()).is_err());
+}
+
+#[test]
+fn jsonl_rows_are_resolved_and_validated() {
+    let content = "{\"title\": \"Dune\", \"author\": \"Frank Herbert\", \"year\": 1965, \"genres\": [\"Science fiction\"]}\n\n{\"title\": \"\", \"author\": \"Nobody\", \"year\": 99999, \"genres\": [\"Poetry\"]}\n";
+    let rows = parse(ImportFormat::Jsonl, content.as_bytes(), &MarcMapping::default()).unwrap();
+    assert_eq!(rows.iter().map(|row| row.line).collect::<Vec<_>>(), [1, 3]);
+    let genres = HashMap::from([("science fiction".to_string(), "65f0a0a0a0a0a0a0a0a0a0a0".to_string())]);
+
+    let book = rows[0].book.clone().unwrap().into_new_book(&genres).unwrap();
+    assert_eq!(book.genre_ids, ["65f0a0a0a0a0a0a0a0a0a0a0"]);
+
+    let errors = rows[1].book.clone().unwrap().into_new_book(&genres).unwrap_err();
+    assert_eq!(errors.0.keys().collect::<Vec<_>>(), ["genres", "title", "year"]);
+}
+
+#[test]
+fn marc_call_numbers_other_than_dewey_are_left_out() {
+    let xml: String = ["823/.912", "PZ7.R79835"].iter().map(|call_number| {
+        let document = bson::doc! {"title": "Harry Potter", "author": "Rowling, J. K.", "year": 1997, "call_number": *call_number};
+        write_marcxml(&Record::from_book(&document, &MarcMapping::default()))
+    }).collect();
+    let rows = parse(ImportFormat::Marcxml, (marcxml_header() + &xml + MARCXML_FOOTER).as_bytes(), &MarcMapping::default()).unwrap();
+    assert_eq!(rows[0].book.as_ref().unwrap().call_number.as_deref(), Some("823.912"));
+    assert!(rows[0].warnings.is_empty());
+    assert_eq!(rows[1].book.as_ref().unwrap().call_number, None);
+    assert_eq!(rows[1].warnings, ["Call number PZ7.R79835 is not a Dewey class, left out"]);
+}
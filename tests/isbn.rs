@@ -0,0 +1,42 @@
+use bibliotheca::book::{Book, NewBook};
+use bibliotheca::isbn::{is_isbn_10, is_isbn_13, normalise, parse, to_isbn_10, to_isbn_13};
+use bibliotheca::validation::{validate, FieldErrors};
+use rocket::serde::json::serde_json;
+
+#[test]
+fn check_digits_are_verified() {
+    assert!(is_isbn_10("0441013597"));
+    assert!(is_isbn_10("080442957X"));
+    assert!(!is_isbn_10("0441013598"));
+    assert!(is_isbn_13("9780441013593"));
+    assert!(!is_isbn_13("9780441013594"));
+    assert!(!is_isbn_13("9770441013593"));
+    assert_eq!(normalise("978-0-441 01359-3"), "9780441013593");
+}
+
+#[test]
+fn forms_are_converted() {
+    assert_eq!(to_isbn_13("0441013597"), "9780441013593");
+    assert_eq!(to_isbn_10("9780441013593").as_deref(), Some("0441013597"));
+    assert_eq!(to_isbn_10("9780804429573").as_deref(), Some("080442957X"));
+    assert_eq!(to_isbn_10("9791032305690"), None);
+    assert_eq!(parse("0-441-01359-7"), Some((Some("0441013597".to_string()), "9780441013593".to_string())));
+    assert_eq!(parse("0-441-01359-8"), None);
+}
+
+#[test]
+fn new_book_stores_both_forms() {
+    let book: NewBook = serde_json::from_str(r#"{"title": "Dune", "author": "Herbert", "year": 1965, "resume": "", "isbn_10": "0-441-01359-7"}"#).unwrap();
+    assert!(validate(&book).is_ok());
+    let book = Book::from(book);
+    assert_eq!(book.isbn_10.as_deref(), Some("0441013597"));
+    assert_eq!(book.isbn_13.as_deref(), Some("9780441013593"));
+}
+
+#[test]
+fn mismatched_forms_are_refused() {
+    let book: NewBook = serde_json::from_str(r#"{"title": "Dune", "author": "Herbert", "year": 1965, "resume": "", "isbn_10": "0441013597", "isbn_13": "9780804429573"}"#).unwrap();
+    let error = validate(&book).unwrap_err();
+    let fields = &error.downcast_ref::<FieldErrors>().unwrap().0;
+    assert_eq!(fields["isbn"], ["isbn_10 and isbn_13 must be the same book"]);
+}
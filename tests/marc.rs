@@ -0,0 +1,77 @@
+use bson::doc;
+use bibliotheca::config::MarcMapping;
+use bibliotheca::marc::{read_iso2709, read_marcxml, write_iso2709, write_marcxml, marcxml_header, Field, Record, MARCXML_FOOTER};
+
+fn book() -> Record {
+    let document = doc! {
+        "_id": bson::oid::ObjectId::parse_str("65f0a0a0a0a0a0a0a0a0a0a0").unwrap(),
+        "title": "Les Misérables",
+        "author": "Hugo, Victor",
+        "year": 1862,
+        "resume": "Jean Valjean & the bishop <of Digne>",
+        "genres": ["Novel", "History"],
+    };
+    Record::from_book(&document, &MarcMapping::default())
+}
+
+#[test]
+fn books_are_written_with_the_mapping() {
+    let record = book();
+    let tags: Vec<&str> = record.fields.iter().map(Field::tag).collect();
+    assert_eq!(tags, ["001", "100", "245", "260", "520", "655", "655"]);
+    let book = record.to_import_book(&MarcMapping::default()).unwrap();
+    assert_eq!((book.title.as_str(), book.author.as_str(), book.year), ("Les Misérables", "Hugo, Victor", 1862));
+    assert_eq!(book.genres, ["Novel", "History"]);
+}
+
+#[test]
+fn iso2709_round_trip() {
+    let bytes = write_iso2709(&book()).unwrap();
+    assert_eq!(&bytes[..5], format!("{:05}", bytes.len()).as_bytes());
+    let records = read_iso2709(&[bytes.clone(), bytes].concat());
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].as_ref().unwrap().fields, book().fields);
+}
+
+#[test]
+fn iso2709_errors_are_reported_per_record() {
+    let mut broken = write_iso2709(&book()).unwrap();
+    broken[12..17].copy_from_slice(b"99999");
+    let records = read_iso2709(&[broken, write_iso2709(&book()).unwrap()].concat());
+    assert_eq!(records[0].as_ref().unwrap_err(), "Invalid base address 99999");
+    assert!(records[1].is_ok());
+}
+
+#[test]
+fn marcxml_round_trip() {
+    let xml = marcxml_header() + &write_marcxml(&book()) + MARCXML_FOOTER;
+    let records = read_marcxml(&xml).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].as_ref().unwrap(), &book());
+}
+
+#[test]
+fn isbd_punctuation_is_trimmed() {
+    let xml = r#"<marc:record xmlns:marc="http://www.loc.gov/MARC21/slim">
+        <marc:datafield tag="245" ind1="1" ind2="0"><marc:subfield code="a">Dune /</marc:subfield><marc:subfield code="c">Frank Herbert.</marc:subfield></marc:datafield>
+        <marc:datafield tag="020" ind1=" " ind2=" "><marc:subfield code="a">0441013597 (pbk.)</marc:subfield></marc:datafield>
+        <marc:datafield tag="260" ind1=" " ind2=" "><marc:subfield code="c">c1965.</marc:subfield></marc:datafield>
+    </marc:record>"#;
+    let records = read_marcxml(xml).unwrap();
+    let book = records[0].as_ref().unwrap().to_import_book(&MarcMapping::default()).unwrap();
+    assert_eq!((book.title.as_str(), book.year), ("Dune", 1965));
+    assert_eq!(book.isbn.as_deref(), Some("0441013597"));
+}
+
+#[test]
+fn marc8_records_are_only_read_in_ascii() {
+    let mut record = book();
+    record.leader.replace_range(9..10, " ");
+    let records = read_iso2709(&write_iso2709(&record).unwrap());
+    assert_eq!(records[0].as_ref().unwrap_err(), "Field 245 is MARC-8, not supported: only UTF-8 records (leader/09 a) are read");
+
+    let mut record = Record { leader: record.leader, fields: Vec::new() };
+    record.fields.push(Field::Control { tag: "001".to_string(), value: "65f0a0a0a0a0a0a0a0a0a0a0".to_string() });
+    record.fields.push(Field::Data { tag: "245".to_string(), indicators: ['1', '0'], subfields: vec![('a', "Dune".to_string())] });
+    assert_eq!(read_iso2709(&write_iso2709(&record).unwrap())[0].as_ref().unwrap().fields, record.fields);
+}
@@ -0,0 +1,45 @@
+use bson::doc;
+use bibliotheca::book::{Book, BookFormat, NewBook};
+use bibliotheca::config::MarcMapping;
+use bibliotheca::marc::Record;
+use bibliotheca::validation::{validate, FieldErrors};
+use rocket::serde::json::serde_json;
+
+#[test]
+fn metadata_is_validated() {
+    let book: NewBook = serde_json::from_str(r#"{"title": "Dune Messiah", "author": "Frank Herbert", "year": 1969, "resume": "",
+        "publisher": "Putnam", "language": "EN", "pages": 256, "format": "hardcover", "series": "Dune", "volume": 2}"#).unwrap();
+    assert!(validate(&book).is_ok());
+    let book = Book::from(book);
+    assert_eq!(book.language.as_deref(), Some("en"));
+    assert_eq!(book.format, Some(BookFormat::Hardcover));
+
+    let book: NewBook = serde_json::from_str(r#"{"title": "Dune", "author": "Frank Herbert", "year": 1965, "resume": "", "language": "xx", "pages": 0}"#).unwrap();
+    let error = validate(&book).unwrap_err();
+    let fields = &error.downcast_ref::<FieldErrors>().unwrap().0;
+    assert_eq!(fields.keys().collect::<Vec<_>>(), ["language", "pages"]);
+
+    let book: NewBook = serde_json::from_str(r#"{"title": "Dune", "author": "Frank Herbert", "year": 1965, "resume": "", "language": "fra", "volume": 1}"#).unwrap();
+    let error = validate(&book).unwrap_err();
+    assert_eq!(error.downcast_ref::<FieldErrors>().unwrap().0["volume"], ["requires series"]);
+}
+
+#[test]
+fn metadata_round_trips_through_marc() {
+    let document = doc! {
+        "title": "Dune Messiah",
+        "author": "Frank Herbert",
+        "year": 1969,
+        "publisher": "Putnam",
+        "edition": "1st edition",
+        "language": "en",
+        "pages": 256,
+        "series": "Dune",
+        "volume": 2,
+    };
+    let book = Record::from_book(&document, &MarcMapping::default()).to_import_book(&MarcMapping::default()).unwrap();
+    assert_eq!(book.publisher.as_deref(), Some("Putnam"));
+    assert_eq!(book.edition.as_deref(), Some("1st edition"));
+    assert_eq!(book.language.as_deref(), Some("en"));
+    assert_eq!((book.pages, book.series.as_deref(), book.volume), (Some(256), Some("Dune"), Some(2)));
+}
@@ -0,0 +1,74 @@
+use bibliotheca::comment::{contains_blocked_word, moderation_queue, Comment, CommentStatus, ModerationKind};
+use bibliotheca::error::HttpError;
+use bson::doc;
+use chrono::{TimeZone, Utc};
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+
+fn comment(text: &str) -> Comment {
+    serde_json::from_value(serde_json::json!({
+        "user_id": "65f0a0a0a0a0a0a0a0a0a0a0", "book_id": "65f0a0a0a0a0a0a0a0a0a0a1", "comment": text,
+        "flags": [{"user_id": "65f0a0a0a0a0a0a0a0a0a0a2", "reason": "rude", "flagged_at": "2024-03-01T10:00:00.000Z"}],
+        "created_at": "2024-03-01T10:00:00.000Z", "updated_at": "2024-03-01T10:00:00.000Z",
+    })).unwrap()
+}
+
+#[test]
+fn blocked_words_ignore_case_beyond_ascii() {
+    let words = vec!["spam".to_string(), "ärger".to_string()];
+    assert!(contains_blocked_word("Buy SPAM now", &words));
+    assert!(contains_blocked_word("So ein ÄRGER!", &words));
+    assert!(!contains_blocked_word("spammer", &words));
+}
+
+#[test]
+fn comments_with_blocked_words_are_held() {
+    let words = vec!["spam".to_string()];
+    let mut clean = comment("A fine read");
+    assert!(!clean.hold_if_blocked(&words));
+    assert_eq!(clean.status, CommentStatus::Visible);
+    let mut blocked = comment("Buy spam");
+    assert!(blocked.hold_if_blocked(&words));
+    assert_eq!(blocked.status, CommentStatus::Held);
+}
+
+#[test]
+fn hide_restore_and_delete_set_the_stored_fields() {
+    let now = Utc.with_ymd_and_hms(2024, 3, 2, 8, 0, 0).unwrap();
+    let mut comment = comment("Rude words");
+
+    let changes = comment.moderate(ModerationKind::Hide, "librarian", now).unwrap();
+    assert_eq!(changes, doc! {"status": "hidden", "updated_at": bson::DateTime::from_chrono(now)});
+    assert_eq!(comment.status, CommentStatus::Hidden);
+    assert_eq!(comment.flags.len(), 1);
+
+    // restoring clears the flags
+    let changes = comment.moderate(ModerationKind::Restore, "librarian", now).unwrap();
+    assert_eq!(changes, doc! {"status": "visible", "flags": [], "updated_at": bson::DateTime::from_chrono(now)});
+    assert_eq!(comment.status, CommentStatus::Visible);
+    assert!(comment.flags.is_empty());
+
+    // deleting moves the comment to the trash
+    let changes = comment.moderate(ModerationKind::Delete, "librarian", now).unwrap();
+    let deleted = changes.get_document("deleted").unwrap();
+    assert_eq!(deleted.get_str("by").unwrap(), "librarian");
+    assert_eq!(deleted.get_datetime("at").unwrap(), &bson::DateTime::from_chrono(now));
+    assert_eq!(comment.deleted.unwrap().by.as_deref(), Some("librarian"));
+}
+
+#[test]
+fn only_the_word_filter_holds_comments() {
+    let mut comment = comment("A fine read");
+    let error = comment.moderate(ModerationKind::Hold, "librarian", Utc::now()).unwrap_err();
+    assert_eq!(error.downcast_ref::<HttpError>().unwrap().status, Status::UnprocessableEntity);
+    assert_eq!(comment.status, CommentStatus::Visible);
+}
+
+#[test]
+fn queue_lists_held_and_flagged_comments_outside_the_trash() {
+    let queue = moderation_queue();
+    assert!(queue.get("deleted").unwrap().as_null().is_some());
+    let branches = queue.get_array("$or").unwrap();
+    assert_eq!(branches[0].as_document().unwrap(), &doc! {"status": "held"});
+    assert_eq!(branches[1].as_document().unwrap(), &doc! {"status": {"$ne": "hidden"}, "flags.0": {"$exists": true}});
+}
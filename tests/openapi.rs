@@ -0,0 +1,69 @@
+use std::fs;
+use std::path::Path;
+use bibliotheca::openapi::{api, docs, DOCS_PATH, OPENAPI_PATH};
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use rocket::serde::json::{serde_json, Value};
+
+/// the checked in OpenAPI document, regenerated with `UPDATE_OPENAPI=1 cargo test --test openapi`
+const SPEC_FILE: &str = "openapi.json";
+
+///
+/// # spec json
+/// this function generate the OpenAPI document as JSON
+/// # Return
+/// * `Value` - the document
+///
+fn spec_json() -> Value {
+    serde_json::to_value(api().1).unwrap()
+}
+
+#[test]
+fn checked_in_spec_is_up_to_date() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(SPEC_FILE);
+    let generated = serde_json::to_string_pretty(&spec_json()).unwrap() + "\n";
+    if std::env::var_os("UPDATE_OPENAPI").is_some() {
+        fs::write(&path, &generated).unwrap();
+    }
+    let checked_in = fs::read_to_string(&path).unwrap_or_default();
+    assert!(checked_in == generated, "{} is out of date, run `UPDATE_OPENAPI=1 cargo test --test openapi`", SPEC_FILE);
+}
+
+#[test]
+fn every_route_is_documented() {
+    let spec = spec_json();
+    let (routes, _) = api();
+    for route in &routes {
+        let path = route.uri.path().to_string().replace('<', "{").replace('>', "}");
+        let method = route.method.as_str().to_lowercase();
+        assert!(spec["paths"][&path][&method].is_object(), "{} {} is missing from the spec", method, path);
+    }
+    let operations: usize = spec["paths"].as_object().unwrap().values()
+        .map(|item| item.as_object().unwrap().len())
+        .sum();
+    assert_eq!(operations, routes.len(), "the spec documents operations without a route");
+}
+
+#[test]
+fn api_types_are_documented() {
+    let spec = spec_json();
+    let schemas = &spec["components"]["schemas"];
+    for name in ["Book", "NewBook", "UpdateBook", "SearchBook", "User", "Comment", "SearchByRating", "Genre"] {
+        assert!(schemas[name].is_object(), "schema {} is missing from the spec", name);
+    }
+}
+
+#[test]
+fn spec_and_ui_are_served() {
+    let (routes, spec) = api();
+    let (openapi, swagger_ui) = docs(spec);
+    let rocket = rocket::build().mount("/", routes).mount("/", vec![openapi]).mount(DOCS_PATH, swagger_ui);
+    let client = Client::untracked(rocket).unwrap();
+
+    let response = client.get(OPENAPI_PATH).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_json::<Value>().unwrap(), spec_json());
+
+    let response = client.get(format!("{}/index.html", DOCS_PATH)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use bibliotheca::error::HttpError;
+use bibliotheca::patch::{changed_fields, merge, Patch};
+use bibliotheca::Value;
+use rocket::http::Status;
+use rocket::serde::json::serde_json::{self, json, Value as JsonValue};
+
+fn apply(document: JsonValue, operations: JsonValue) -> Result<JsonValue, Status> {
+    let mut document = document;
+    let patch = Patch::Json(serde_json::from_value(operations).unwrap());
+    match patch.apply(&mut document) {
+        Ok(()) => Ok(document),
+        Err(error) => Err(error.downcast_ref::<HttpError>().unwrap().status),
+    }
+}
+
+#[test]
+fn pointers_unescape_tilde_and_slash() {
+    let document = json!({"a/b": 1, "m~n": 2});
+    let patched = apply(document, json!([
+        {"op": "replace", "path": "/a~1b", "value": 3},
+        {"op": "add", "path": "/c~0d", "value": 4},
+        {"op": "test", "path": "/m~0n", "value": 2},
+    ])).unwrap();
+    assert_eq!(patched, json!({"a/b": 3, "m~n": 2, "c~d": 4}));
+}
+
+#[test]
+fn dash_appends_and_indexes_insert() {
+    let patched = apply(json!({"tags": ["a", "c"]}), json!([
+        {"op": "add", "path": "/tags/-", "value": "d"},
+        {"op": "add", "path": "/tags/1", "value": "b"},
+        {"op": "add", "path": "/tags/4", "value": "e"},
+    ])).unwrap();
+    assert_eq!(patched, json!({"tags": ["a", "b", "c", "d", "e"]}));
+}
+
+#[test]
+fn invalid_indexes_are_refused() {
+    let document = json!({"tags": ["a", "b"]});
+    // leading zeros, out of bounds, `-` is not an element to remove
+    for path in ["/tags/01", "/tags/2", "/tags/-"] {
+        assert_eq!(apply(document.clone(), json!([{"op": "remove", "path": path}])), Err(Status::UnprocessableEntity), "{}", path);
+    }
+    assert_eq!(apply(document.clone(), json!([{"op": "add", "path": "/tags/3", "value": "c"}])), Err(Status::UnprocessableEntity));
+    assert_eq!(apply(document, json!([{"op": "remove", "path": "/tags/0"}])).unwrap(), json!({"tags": ["b"]}));
+}
+
+#[test]
+fn move_and_copy() {
+    let document = json!({"a": {"b": 1}, "c": []});
+    let patched = apply(document.clone(), json!([
+        {"op": "copy", "from": "/a/b", "path": "/c/-"},
+        {"op": "move", "from": "/a", "path": "/d"},
+    ])).unwrap();
+    assert_eq!(patched, json!({"c": [1], "d": {"b": 1}}));
+    // a value cannot be moved into one of its children, `/ab` is not a child of `/a`
+    assert_eq!(apply(document.clone(), json!([{"op": "move", "from": "/a", "path": "/a/b/c"}])), Err(Status::UnprocessableEntity));
+    assert_eq!(apply(document, json!([{"op": "move", "from": "/a", "path": "/ab"}])).unwrap(), json!({"ab": {"b": 1}, "c": []}));
+}
+
+#[test]
+fn failed_test_conflicts_and_leaves_the_document_unchanged() {
+    let mut document = json!({"title": "Dune"});
+    let patch = Patch::Json(serde_json::from_value(json!([
+        {"op": "replace", "path": "/title", "value": "Emma"},
+        {"op": "test", "path": "/title", "value": "Dune"},
+    ])).unwrap());
+    let error = patch.apply(&mut document).unwrap_err();
+    assert_eq!(error.downcast_ref::<HttpError>().unwrap().status, Status::Conflict);
+    assert_eq!(document, json!({"title": "Dune"}));
+    assert_eq!(apply(json!({}), json!([{"op": "test", "path": "/missing", "value": null}])), Err(Status::Conflict));
+}
+
+#[test]
+fn test_compares_numbers_by_value() {
+    let document = json!({"year": 1965, "ratings": [4, {"score": 2.5}]});
+    assert!(apply(document.clone(), json!([{"op": "test", "path": "/year", "value": 1965.0}])).is_ok());
+    assert!(apply(document.clone(), json!([{"op": "test", "path": "/ratings", "value": [4.0, {"score": 2.5}]}])).is_ok());
+    assert_eq!(apply(document.clone(), json!([{"op": "test", "path": "/year", "value": 1965.5}])), Err(Status::Conflict));
+    assert_eq!(apply(document, json!([{"op": "test", "path": "/year", "value": "1965"}])), Err(Status::Conflict));
+}
+
+#[test]
+fn merge_removes_null_members_and_merges_objects() {
+    let mut document = json!({"title": "Dune", "series": {"name": "Dune", "number": 1}, "tags": ["a"]});
+    merge(&mut document, &json!({"title": null, "series": {"number": 2}, "tags": ["b"], "year": 1965}));
+    assert_eq!(document, json!({"series": {"name": "Dune", "number": 2}, "tags": ["b"], "year": 1965}));
+    // a patch that is not an object replaces the document
+    merge(&mut document, &json!(["x"]));
+    assert_eq!(document, json!(["x"]));
+}
+
+#[test]
+fn changed_fields_refuse_removing_required_fields() {
+    let current = HashMap::from([("title", Value::Text("Dune".to_string())), ("resume", Value::Text("Spice".to_string())), ("year", Value::Int(1965))]);
+    let patched = HashMap::from([("title", Value::Text("Dune".to_string())), ("year", Value::Int(1966))]);
+    let changes = changed_fields(current.clone(), patched, &["title"]).unwrap();
+    // unchanged fields are left out, removed fields are written as null
+    assert_eq!(changes, HashMap::from([("resume", Value::Null), ("year", Value::Int(1966))]));
+
+    let error = changed_fields(current, HashMap::from([("year", Value::Int(1965))]), &["title"]).unwrap_err();
+    let error = error.downcast_ref::<HttpError>().unwrap();
+    assert_eq!(error.status, Status::UnprocessableEntity);
+    assert_eq!(error.message, "title cannot be removed");
+}
@@ -0,0 +1,100 @@
+use bibliotheca::book::{shelf_limit, Book, NewBook, Shelf, ShelfBook, UpdateBook, SHELF_DEFAULT, SHELF_MAX};
+use bibliotheca::call_number::{is_dewey, normalise};
+use bibliotheca::validation::{validate, FieldErrors};
+use bibliotheca::subject::UpdateSubject;
+use bibliotheca::Value;
+use rocket::serde::json::serde_json;
+
+#[test]
+fn call_numbers_are_dewey_classes() {
+    assert_eq!(normalise("  823.912   row "), "823.912 ROW");
+    assert!(is_dewey("823.912 ROW"));
+    assert!(is_dewey("005.133 K35 2018"));
+    assert!(is_dewey("500"));
+    assert!(!is_dewey("82.3"));
+    assert!(!is_dewey("823."));
+    assert!(!is_dewey("QA76.73"));
+    // the class sorts before its subdivisions, which sort in decimal order
+    let mut shelf = vec!["823.92 ROW", "823 AUS", "823.912 TOL", "823.1 CHA"];
+    shelf.sort();
+    assert_eq!(shelf, ["823 AUS", "823.1 CHA", "823.912 TOL", "823.92 ROW"]);
+}
+
+#[test]
+fn books_carry_a_call_number_and_subjects() {
+    let book: NewBook = serde_json::from_str(r#"{"title": "The Hobbit", "author": "Tolkien", "year": 1937, "resume": "",
+        "call_number": "823.912 tol", "subject_ids": ["65f0a0a0a0a0a0a0a0a0a0a0"]}"#).unwrap();
+    assert!(validate(&book).is_ok());
+    let book = Book::from(book);
+    assert_eq!(book.call_number.as_deref(), Some("823.912 TOL"));
+    assert_eq!(book.subject_ids, ["65f0a0a0a0a0a0a0a0a0a0a0"]);
+
+    let update: UpdateBook = serde_json::from_str(r#"{"call_number": "823.912  tol"}"#).unwrap();
+    assert_eq!(update.into_changes()["call_number"], Value::Text("823.912 TOL".to_string()));
+
+    let book: NewBook = serde_json::from_str(r#"{"title": "The Hobbit", "author": "Tolkien", "year": 1937, "resume": "",
+        "call_number": "PR6039", "subject_ids": ["nope"]}"#).unwrap();
+    let error = validate(&book).unwrap_err();
+    let fields = &error.downcast_ref::<FieldErrors>().unwrap().0;
+    assert!(fields.contains_key("call_number"));
+    assert!(fields.contains_key("subject_ids"));
+}
+
+#[test]
+fn subjects_are_updated() {
+    let update: UpdateSubject = serde_json::from_str(r#"{"heading": " Wizards -- Fiction "}"#).unwrap();
+    assert!(validate(&update).is_ok());
+    let changes = update.into_changes();
+    assert_eq!(changes["heading"], Value::Text("Wizards -- Fiction".to_string()));
+    assert!(!changes.contains_key("scope_note"));
+
+    let update: UpdateSubject = serde_json::from_str(r#"{"heading": ""}"#).unwrap();
+    assert!(validate(&update).is_err());
+}
+
+fn shelved(id: u8, call_number: Option<&str>) -> bson::Document {
+    let mut document = bson::doc! {
+        "_id": bson::oid::ObjectId::from_bytes([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, id]),
+        "title": format!("Book {id}"), "author": "Tolkien", "availability": true,
+    };
+    if let Some(call_number) = call_number {
+        document.insert("call_number", call_number);
+    }
+    document
+}
+
+fn titles(books: &[ShelfBook]) -> Vec<&str> {
+    books.iter().map(|book| book.title.as_str()).collect()
+}
+
+#[test]
+fn books_without_call_number_are_not_shelved() {
+    assert!(ShelfBook::from_document(&shelved(1, None)).unwrap().is_none());
+    let book = ShelfBook::from_document(&shelved(1, Some("823.912 TOL"))).unwrap().unwrap();
+    assert_eq!((book.call_number.as_str(), book.title.as_str()), ("823.912 TOL", "Book 1"));
+}
+
+#[test]
+fn shelf_lists_the_closest_books_in_shelf_order() {
+    // read backwards from the call number, as the database gives them
+    let books = [(5, "823.8 DIC"), (4, "823.7 AUS"), (3, "823.1 CHA"), (7, "823.912 TOL"), (6, "823.912 TOL"), (8, "823.92 ROW"), (9, "824 LAM")]
+        .iter()
+        .filter_map(|(id, call_number)| ShelfBook::from_document(&shelved(*id, Some(call_number))).unwrap());
+    let shelf = Shelf::around("823.912 TOL".to_string(), books, 2);
+    // the books at the call number are after it, not before, in the order of their id
+    assert_eq!(titles(&shelf.before), ["Book 4", "Book 5"]);
+    assert_eq!(titles(&shelf.after), ["Book 6", "Book 7"]);
+
+    let books = [(1, "823.912 TOL"), (2, "823.913")].iter()
+        .filter_map(|(id, call_number)| ShelfBook::from_document(&shelved(*id, Some(call_number))).unwrap());
+    let shelf = Shelf::around("823.913".to_string(), books, 5);
+    assert_eq!((titles(&shelf.before), titles(&shelf.after)), (vec!["Book 1"], vec!["Book 2"]));
+}
+
+#[test]
+fn shelf_limit_is_clamped() {
+    assert_eq!(shelf_limit(None), SHELF_DEFAULT);
+    assert_eq!(shelf_limit(Some(0)), 1);
+    assert_eq!(shelf_limit(Some(10)), 10);
+    assert_eq!(shelf_limit(Some(1000)), SHELF_MAX);
+}
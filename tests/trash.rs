@@ -0,0 +1,58 @@
+use bibliotheca::book::{Book, Loan, NewBook};
+use bibliotheca::Deletion;
+use bson::doc;
+use chrono::{TimeZone, Utc};
+use rocket::serde::json::serde_json;
+
+fn deleted_book() -> Book {
+    let book: NewBook = serde_json::from_str(r#"{"title": "Dune", "author": "Frank Herbert", "year": 1965, "resume": ""}"#).unwrap();
+    let mut book = Book::from(book);
+    let at = Utc.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap();
+    book.availability = false;
+    book.loan = Some(Loan { user_id: "65f0a0a0a0a0a0a0a0a0a0a1".to_string(), at, due: at + chrono::Duration::days(21) });
+    book.deleted = Some(Deletion { by: Some("librarian".to_string()), at });
+    book
+}
+
+#[test]
+fn trash_filters() {
+    assert_eq!(Deletion::in_trash(doc! {"_id": 1}), doc! {"_id": 1, "deleted": {"$ne": null}});
+    let before = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+    assert_eq!(Deletion::expired(before), doc! {"deleted.at": {"$lt": bson::DateTime::from_chrono(before)}});
+}
+
+#[test]
+fn restoring_brings_back_what_was_deleted_along() {
+    let at = Utc.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap();
+    let deletion = Deletion { by: None, at };
+    // the comments deleted with the book share the date of its deletion
+    assert_eq!(deletion.along(doc! {"book_id": "b"}), doc! {"book_id": "b", "deleted.at": bson::DateTime::from_chrono(at)});
+}
+
+#[test]
+fn restored_book_is_freed_unless_still_listed() {
+    let now = Utc.with_ymd_and_hms(2024, 3, 5, 10, 0, 0).unwrap();
+    let mut book = deleted_book();
+    let (deletion, update) = book.restore(false, now);
+    assert_eq!(deletion.unwrap().by.as_deref(), Some("librarian"));
+    assert!(book.deleted.is_none());
+    assert!(book.availability);
+    assert!(book.loan.is_none());
+    assert_eq!(book.version, 2);
+    assert_eq!(update, doc! {
+        "$unset": {"deleted": "", "loan": ""},
+        "$set": {"availability": true, "updated_at": bson::DateTime::from_chrono(now)},
+        "$inc": {"version": 1},
+    });
+}
+
+#[test]
+fn restored_book_stays_on_loan_when_still_listed() {
+    let now = Utc.with_ymd_and_hms(2024, 3, 5, 10, 0, 0).unwrap();
+    let mut book = deleted_book();
+    let (_, update) = book.restore(true, now);
+    assert!(!book.availability);
+    assert!(book.loan.is_some());
+    assert_eq!(update.get_document("$unset").unwrap(), &doc! {"deleted": ""});
+    assert!(!update.get_document("$set").unwrap().get_bool("availability").unwrap());
+}
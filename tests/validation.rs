@@ -0,0 +1,63 @@
+use bibliotheca::comment::NewComment;
+use bibliotheca::book::{NewBook, UpdateBook};
+use bibliotheca::user::{NewUser, UpdateUser};
+use bibliotheca::validation::{parse_id, validate, FieldErrors};
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+
+#[test]
+fn invalid_fields_are_reported_by_name() {
+    let book: NewBook = serde_json::from_str(r#"{"title": "", "author": "Hugo", "year": 99999, "resume": "", "genre_ids": ["nope"]}"#).unwrap();
+    let error = validate(&book).unwrap_err();
+    let fields = &error.downcast_ref::<FieldErrors>().unwrap().0;
+    assert_eq!(fields.keys().collect::<Vec<_>>(), ["genre_ids", "title", "year"]);
+    assert_eq!(fields["year"], ["must be between -3000 and 2100"]);
+}
+
+#[test]
+fn valid_body_passes() {
+    let user: NewUser = serde_json::from_str(r#"{"first_name": "Ada", "last_name": "Lovelace", "email": "ada@example.org", "birth_date": "1815-12-10"}"#).unwrap();
+    assert!(validate(&user).is_ok());
+    let user = NewUser { email: "ada".to_string(), ..user };
+    assert!(validate(&user).is_err());
+}
+
+#[test]
+fn replies_need_no_rating() {
+    let review: NewComment = serde_json::from_str(r#"{"user_id": "65f0a0a0a0a0a0a0a0a0a0a0", "book_id": "65f0a0a0a0a0a0a0a0a0a0a1", "comment": "Great"}"#).unwrap();
+    let error = validate(&review).unwrap_err();
+    assert_eq!(error.downcast_ref::<FieldErrors>().unwrap().0["rating"], ["required for a review"]);
+
+    let reply: NewComment = serde_json::from_str(r#"{"user_id": "65f0a0a0a0a0a0a0a0a0a0a0", "book_id": "65f0a0a0a0a0a0a0a0a0a0a1", "comment": "Agreed",
+        "parent_id": "65f0a0a0a0a0a0a0a0a0a0a2"}"#).unwrap();
+    assert!(validate(&reply).is_ok());
+}
+
+#[test]
+fn loan_fields_are_read_only() {
+    let book: UpdateBook = serde_json::from_str(r#"{"title": "Dune", "availability": false}"#).unwrap();
+    assert_eq!(book.into_changes().keys().collect::<Vec<_>>(), [&"title"]);
+    let user: UpdateUser = serde_json::from_str(r#"{"borrowed_books": ["65f0a0a0a0a0a0a0a0a0a0a0"]}"#).unwrap();
+    assert!(user.into_changes().is_empty());
+}
+
+#[test]
+fn birth_dates_cannot_be_in_the_future() {
+    let tomorrow = (chrono::Utc::now().date_naive() + chrono::Duration::days(1)).format("%Y-%m-%d").to_string();
+    let user: NewUser = serde_json::from_value(serde_json::json!({"first_name": "Ada", "last_name": "Lovelace", "email": "ada@example.org", "birth_date": tomorrow})).unwrap();
+    let error = validate(&user).unwrap_err();
+    assert_eq!(error.downcast_ref::<FieldErrors>().unwrap().0["birth_date"], ["cannot be in the future"]);
+
+    let update: UpdateUser = serde_json::from_value(serde_json::json!({"birth_date": tomorrow})).unwrap();
+    assert!(validate(&update).is_err());
+    let update: UpdateUser = serde_json::from_str(r#"{"birth_date": "1815-12-10"}"#).unwrap();
+    assert!(validate(&update).is_ok());
+}
+
+#[test]
+fn path_ids_that_are_not_object_ids_name_no_document() {
+    assert_eq!(parse_id("65f0a0a0a0a0a0a0a0a0a0a0", "Book").unwrap().to_hex(), "65f0a0a0a0a0a0a0a0a0a0a0");
+    let error = parse_id("not-an-id", "Book").unwrap_err();
+    assert_eq!(error.status, Status::NotFound);
+    assert_eq!(error.message, "Book not found");
+}
@@ -0,0 +1,56 @@
+use bibliotheca::book::{Book, NewBook};
+use bibliotheca::validation::{validate, FieldErrors};
+use bibliotheca::work::{average_rating, Edition, NewWork, Work, WorkHit};
+use rocket::serde::json::serde_json;
+
+#[test]
+fn works_are_validated() {
+    let work: NewWork = serde_json::from_str(r#"{"title": "Les Misérables", "author": "Victor Hugo", "year": 1862, "original_language": "FR"}"#).unwrap();
+    assert!(validate(&work).is_ok());
+    assert_eq!(Work::from(work).original_language.as_deref(), Some("fr"));
+
+    let work: NewWork = serde_json::from_str(r#"{"title": "", "author": "Victor Hugo", "original_language": "french"}"#).unwrap();
+    let error = validate(&work).unwrap_err();
+    let fields = &error.downcast_ref::<FieldErrors>().unwrap().0;
+    assert_eq!(fields.keys().collect::<Vec<_>>(), ["original_language", "title"]);
+}
+
+#[test]
+fn books_are_editions_of_a_work() {
+    let book: NewBook = serde_json::from_str(r#"{"title": "Les Misérables", "author": "Victor Hugo", "year": 1887, "resume": "",
+        "language": "en", "edition": "translated by Isabel F. Hapgood", "work_id": "65f0a0a0a0a0a0a0a0a0a0a0"}"#).unwrap();
+    assert!(validate(&book).is_ok());
+    let edition = Edition::new("65f0a0a0a0a0a0a0a0a0a0a1".to_string(), &Book::from(book));
+    assert_eq!((edition.year, edition.language.as_deref()), (1887, Some("en")));
+
+    let book: NewBook = serde_json::from_str(r#"{"title": "Les Misérables", "author": "Victor Hugo", "year": 1862, "resume": "", "work_id": "hugo"}"#).unwrap();
+    assert!(validate(&book).is_err());
+}
+
+fn book(title: &str, year: i32, work_id: Option<&str>) -> (String, Book) {
+    let book: NewBook = serde_json::from_value(serde_json::json!({"title": title, "author": "Victor Hugo", "year": year, "resume": "", "work_id": work_id})).unwrap();
+    (format!("{title} {year}"), Book::from(book))
+}
+
+#[test]
+fn search_hits_are_collapsed_by_work() {
+    let hits = WorkHit::collapse([
+        book("Les Misérables", 1862, Some("miserables")),
+        book("Notre-Dame de Paris", 1831, None),
+        book("Les Misérables", 1887, Some("miserables")),
+        book("Notre-Dame de Paris", 1833, None),
+        book("Les Travailleurs de la mer", 1866, Some("travailleurs")),
+    ]);
+    let works: Vec<_> = hits.iter().map(|hit| (hit.work_id.as_deref(), hit.editions.len())).collect();
+    assert_eq!(works, [(Some("miserables"), 2), (None, 1), (None, 1), (Some("travailleurs"), 1)]);
+    let editions: Vec<_> = hits[0].editions.iter().map(|edition| edition.year).collect();
+    assert_eq!(editions, [1862, 1887]);
+    assert_eq!(hits[0].editions[1].book_id, "Les Misérables 1887");
+}
+
+#[test]
+fn work_rating_averages_the_reviews_of_its_editions() {
+    assert_eq!(average_rating([(9.0, 2), (2.0, 1)]), (Some(11.0 / 3.0), 3));
+    assert_eq!(average_rating([(4.0, 1)]), (Some(4.0), 1));
+    assert_eq!(average_rating([]), (None, 0));
+}